@@ -1,19 +1,98 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{ArgValueCandidates, CompleteEnv, CompletionCandidate, Shell};
+use regex::Regex;
 use reqwest::header::HeaderValue;
-use semver::{Version, VersionReq};
+use semver::{BuildMetadata, Prerelease, Version, VersionReq};
 use serde::{Deserialize, Serialize};
-use serde_yaml;
+use serde_json::json;
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use zip::ZipArchive;
 
+/// Prints a human-readable progress message, unless `--quiet` was passed.
+/// With `--json` it goes to stderr so that stdout only carries the JSON document.
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Normal {
+            summary!($($arg)*);
+        }
+    };
+}
+
+/// Prints a message that is shown even with `--quiet`, such as the final summary.
+macro_rules! summary {
+    ($($arg:tt)*) => {
+        print_line(json_output(), &redact(&format!($($arg)*)))
+    };
+}
+
+/// Prints an error on stderr, even with `--quiet`.
+macro_rules! error {
+    ($($arg:tt)*) => {
+        print_line(true, &redact(&format!($($arg)*)))
+    };
+}
+
+/// Prints a warning on stderr, unless `--quiet` was passed.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Normal {
+            print_line(true, &redact(&format!($($arg)*)));
+        }
+    };
+}
+
+/// Prints debugging details on stderr when `--verbose` was passed.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if verbosity() >= Verbosity::Verbose {
+            print_line(true, &redact(&format!($($arg)*)));
+        }
+    };
+}
+
 /// A CLI tool to manage WordPress dependencies.
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Print a JSON document on stdout instead of human-readable output
+    #[arg(long, global = true)]
+    json: bool,
+    /// Only print errors and the final summary
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also print the URLs fetched, the versions considered and the files extracted
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Use this wdm.yml instead of searching the current directory and its parents
+    #[arg(long, global = true, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+    /// Never run the hook commands in wdm.yml, for manifests you don't trust
+    #[arg(long, global = true)]
+    no_hooks: bool,
+    /// Fail right away if another wdm is changing this project, instead of waiting for it
+    #[arg(long, global = true)]
+    no_wait: bool,
+    /// How long to wait for another wdm changing this project to finish
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        default_value_t = 300,
+        conflicts_with = "no_wait"
+    )]
+    lock_timeout: u64,
+    /// Look up every dependency's tags again instead of reusing the ones cached recently
+    #[arg(long, global = true)]
+    refresh: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -21,647 +100,12122 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize wdm in the current directory
-    Init,
-    /// Add a dependency to wdm.yml and install it
+    Init {
+        /// Where WordPress lives, relative to wdm.yml (asked for when run in a terminal)
+        #[arg(long)]
+        wordpress_path: Option<String>,
+        /// Overwrite an existing wdm.yml
+        #[arg(long)]
+        force: bool,
+    },
+    /// Add dependencies to wdm.yml and install them
     Add {
-        name: String,
+        /// The dependencies' names, or owner/repo[@version] or repository URLs to take the names from
+        #[arg(required = true, value_name = "NAME")]
+        specs: Vec<String>,
+        /// The version requirement (defaults to the one after @, or one picked from the tags, or latest)
+        #[arg(long)]
+        version: Option<String>,
+        /// Write the caret range of the picked version (^x.y.z) instead of the exact version
+        #[arg(long, conflicts_with = "version")]
+        range: bool,
+        /// The repository as owner/repo or its URL (required for GitHub, GitLab and Bitbucket dependencies)
         #[arg(short, long)]
-        version: String,
+        repo: Option<String>,
+        /// Where the dependency is fetched from (defaults to github, or url when --url is given)
+        #[arg(short, long, value_enum)]
+        source: Option<Source>,
+        /// The zip archive to download for `url` dependencies
         #[arg(short, long)]
-        repo: String,
+        url: Option<String>,
+        /// The GitLab instance to use instead of gitlab.com, or the GitHub Enterprise Server instead of github.com
+        #[arg(long)]
+        host: Option<String>,
+        /// Install the GitHub release asset matching this glob instead of the source archive
+        #[arg(long)]
+        asset: Option<String>,
         #[arg(short = 'e', long)]
         token_env: Option<String>,
+        /// Give up on HTTP requests that take longer than this many seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// What kind of package this is (defaults to plugin)
+        #[arg(long = "type", value_enum)]
+        kind: Option<DependencyType>,
+        /// Add it to dev_dependencies, which production installs skip
+        #[arg(long)]
+        dev: bool,
+        /// Install into this directory, relative to wordpress_path, instead of wp-content/<type>/<name>
+        #[arg(long)]
+        install_path: Option<String>,
+        /// Only install this subdirectory of the repository, e.g. plugins/my-plugin in a monorepo
+        #[arg(long)]
+        path: Option<String>,
+        /// Let `latest` and version ranges resolve to prereleases (sets allow_prerelease)
+        #[arg(long)]
+        pre: bool,
+        /// Only write the dependency to wdm.yml, without resolving, downloading, or locking it
+        #[arg(long)]
+        no_install: bool,
+        /// Fail instead of warning when it requires a newer WordPress or PHP than the site has
+        #[arg(long, conflicts_with = "no_install")]
+        strict_compat: bool,
+        /// Fail instead of warning when its plugin header doesn't match its name or version
+        #[arg(long, conflicts_with = "no_install")]
+        strict: bool,
+        /// Also add the plugins it lists in `Requires Plugins` from WordPress.org, if they are missing
+        #[arg(long, conflicts_with = "no_install")]
+        with_requirements: bool,
+        /// Pin the archive's hash in wdm.yml (--integrity=sha256-<hex>); without a value, take it from the download
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        integrity: Option<String>,
+    },
+    /// Remove dependencies from wdm.yml and uninstall them
+    Remove {
+        /// The dependencies to remove (matched case-insensitively)
+        #[arg(
+            add = ArgValueCandidates::new(dependency_candidates),
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        names: Vec<String>,
+        /// Remove every dependency, after asking for confirmation
+        #[arg(long)]
+        all: bool,
+        /// Don't ask for confirmation before removing everything with --all
+        #[arg(short, long, requires = "all")]
+        yes: bool,
+        /// Also delete the cached archive from .wdm-cache
+        #[arg(long)]
+        purge: bool,
+        /// Delete the plugin directory even if wdm did not install it
+        #[arg(long)]
+        force: bool,
     },
-    /// Remove a dependency from wdm.yml
-    Remove { name: String },
     /// Install all dependencies from wdm.yml
-    Install,
+    Install {
+        /// Only install the dependencies with these names, leaving the rest of wdm.lock alone
+        #[arg(add = ArgValueCandidates::new(dependency_candidates))]
+        names: Vec<String>,
+        /// Install exactly what wdm.lock records and never write it
+        #[arg(long, alias = "locked")]
+        frozen: bool,
+        /// How many dependencies to resolve and download at once (defaults to the CPU count)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Give up on HTTP requests that take longer than this many seconds
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Install the versions in wdm.lock from .wdm-cache without using the network
+        #[arg(long)]
+        offline: bool,
+        /// Download every archive again even if a matching one is cached
+        #[arg(long, conflicts_with = "offline")]
+        no_cache: bool,
+        /// Install archives even if they don't match the hash in wdm.lock
+        #[arg(long)]
+        insecure_skip_verify: bool,
+        /// Skip dev_dependencies, for production deployments
+        #[arg(long, alias = "production")]
+        no_dev: bool,
+        /// Install into this WordPress directory instead of the one in wdm.yml
+        #[arg(long)]
+        wordpress_path: Option<PathBuf>,
+        /// Resolve versions and print what would change, without downloading or writing anything
+        #[arg(long, conflicts_with = "offline")]
+        dry_run: bool,
+        /// Replace dependencies that are already installed with a pristine copy
+        #[arg(long)]
+        force: bool,
+        /// Fail instead of warning when a dependency requires a newer WordPress or PHP than the site has
+        #[arg(long)]
+        strict_compat: bool,
+        /// Fail instead of warning when a plugin's header doesn't match its directory or version
+        #[arg(long)]
+        strict: bool,
+        /// Also uninstall dependencies that wdm.lock records but wdm.yml no longer lists
+        #[arg(long, conflicts_with = "frozen")]
+        prune: bool,
+        /// Rewrite the repo of GitHub dependencies that were renamed or transferred in wdm.yml
+        #[arg(long, conflicts_with_all = ["frozen", "dry_run"])]
+        update_manifest: bool,
+        /// Add missing plugins that dependencies list in `Requires Plugins` from WordPress.org
+        #[arg(long, conflicts_with_all = ["frozen", "dry_run", "offline", "wordpress_path"])]
+        with_requirements: bool,
+        /// Only use the dependencies for this environment (defaults to WDM_ENV)
+        #[arg(long, value_name = "NAME")]
+        env: Option<String>,
+        /// Run in every member of the wdm-workspace.yml in this directory or a parent
+        #[arg(long, conflicts_with = "wordpress_path")]
+        workspace: bool,
+        /// With --workspace, stop after the first member that fails
+        #[arg(long, requires = "workspace")]
+        fail_fast: bool,
+    },
+    /// Re-resolve version requirements and update changed dependencies
+    Update {
+        /// Only update the dependency with this name
+        #[arg(add = ArgValueCandidates::new(dependency_candidates))]
+        name: Option<String>,
+        /// Consider prereleases for this update, as if allow_prerelease were set
+        #[arg(long)]
+        pre: bool,
+        /// Fail instead of warning when a dependency requires a newer WordPress or PHP than the site has
+        #[arg(long)]
+        strict_compat: bool,
+        /// Fail instead of warning when a plugin's header doesn't match its directory or version
+        #[arg(long)]
+        strict: bool,
+        /// Rewrite the repo of GitHub dependencies that were renamed or transferred in wdm.yml
+        #[arg(long)]
+        update_manifest: bool,
+        /// Also update dependencies with an integrity in wdm.yml, recording the new hash there
+        #[arg(long)]
+        update_integrity: bool,
+        /// Run in every member of the wdm-workspace.yml in this directory or a parent
+        #[arg(long)]
+        workspace: bool,
+        /// With --workspace, stop after the first member that fails
+        #[arg(long, requires = "workspace")]
+        fail_fast: bool,
+    },
+    /// List dependencies with newer versions available, without installing
+    Outdated {
+        /// Run in every member of the wdm-workspace.yml in this directory or a parent
+        #[arg(long)]
+        workspace: bool,
+        /// With --workspace, stop after the first member that fails
+        #[arg(long, requires = "workspace")]
+        fail_fast: bool,
+    },
+    /// Resolve every dependency and write wdm.lock without installing anything
+    Lock {
+        /// Only check that wdm.lock is up to date with wdm.yml, and exit non-zero if it isn't
+        #[arg(long)]
+        check: bool,
+        /// How many dependencies to resolve and download at once
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Refuse dependencies that require a newer WordPress or PHP than the site has
+        #[arg(long)]
+        strict_compat: bool,
+        /// Refuse plugins whose headers don't match their name or tag
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Show how resolving wdm.yml would change wdm.lock, without writing or installing anything
+    Diff,
+    /// Check that wdm.yml, wdm.lock, and wp-content agree, without using the network
+    #[command(visible_alias = "status")]
+    Check {
+        /// Also check cached archives against the hashes in wdm.lock
+        #[arg(long)]
+        hashes: bool,
+        /// Treat dependencies in wdm.yml that would install over each other as problems
+        #[arg(long)]
+        strict: bool,
+        /// Only use the dependencies for this environment (defaults to WDM_ENV)
+        #[arg(long, value_name = "NAME")]
+        env: Option<String>,
+        /// Run in every member of the wdm-workspace.yml in this directory or a parent
+        #[arg(long)]
+        workspace: bool,
+        /// With --workspace, stop after the first member that fails
+        #[arg(long, requires = "workspace")]
+        fail_fast: bool,
+    },
+    /// Diagnose common setup problems, such as a missing git or an unset token
+    Doctor,
+    /// Parse and validate wdm.yml without touching anything else, e.g. in a pre-commit hook
+    Validate,
+    /// Show each dependency's requirement, locked version, and whether it is installed
+    List {
+        /// Only show dependencies whose directory is missing, and exit non-zero if there are any
+        #[arg(long)]
+        missing: bool,
+        /// Only show the dependencies for this environment (defaults to WDM_ENV)
+        #[arg(long, value_name = "NAME")]
+        env: Option<String>,
+    },
+    /// Copy the archive of every version in wdm.lock into a directory, for installs without the network
+    Vendor {
+        /// Where to put the archives, relative to wdm.yml (defaults to vendor_dir from wdm.yml, or wdm-vendor)
+        dir: Option<PathBuf>,
+        /// Also delete archives of versions that wdm.lock no longer records
+        #[arg(long)]
+        prune: bool,
+        /// How many archives to download at once (defaults to the CPU count)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Restore the version of a dependency that the last install replaced
+    Rollback {
+        /// The dependency to roll back
+        #[arg(add = ArgValueCandidates::new(dependency_candidates))]
+        name: String,
+    },
+    /// Bundle the installed dependencies with wdm.yml and wdm.lock into one .tar.gz
+    Pack {
+        /// The archive to write, e.g. site-plugins.tar.gz
+        output: PathBuf,
+        /// Replace the archive if it already exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Restore the dependencies, wdm.yml, and wdm.lock bundled by `wdm pack`
+    Unpack {
+        /// The archive `wdm pack` wrote
+        archive: PathBuf,
+        /// Restore into this WordPress directory instead of the one in the bundled wdm.yml
+        #[arg(long)]
+        wordpress_path: Option<PathBuf>,
+        /// Replace directories and files that wdm did not install
+        #[arg(long)]
+        force: bool,
+    },
+    /// Inspect and clean up cached archives
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Read and change the config section of wdm.yml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Print a shell completion script to stdout
+    Completions { shell: Shell },
+    /// Add the wpackagist plugins and themes required by a composer.json to wdm.yml
+    Import {
+        /// The composer.json to read
+        #[arg(default_value = "composer.json")]
+        path: PathBuf,
+        /// Print the dependencies that would be added without writing wdm.yml
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Add the plugins already installed under wp-content/plugins to wdm.yml, pinned to their versions
+    Adopt {
+        /// A YAML file mapping plugin directories to GitHub repositories (owner/repo), tried before WordPress.org
+        #[arg(long = "repo", value_name = "FILE")]
+        repos: Option<PathBuf>,
+        /// Add every plugin that was matched without asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Pin each dependency in wdm.yml to the exact version locked in wdm.lock
+    Freeze {
+        /// Only pin this dependency (may be repeated)
+        #[arg(long, value_name = "NAME")]
+        only: Vec<String>,
+        /// Print the changes to wdm.yml without writing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Convert wdm.yml into a composer.json, or dump it with wdm.lock as JSON
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Composer)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Require the versions recorded in wdm.lock instead of the ranges in wdm.yml
+        #[arg(long)]
+        locked: bool,
+    },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Config {
-    config: ConfigData,
-    dependencies: Vec<Dependency>,
+impl Commands {
+    /// Whether `--workspace` was passed, and if so whether `--fail-fast` was too.
+    fn workspace(&self) -> Option<bool> {
+        match self {
+            Commands::Install {
+                workspace: true,
+                fail_fast,
+                ..
+            }
+            | Commands::Update {
+                workspace: true,
+                fail_fast,
+                ..
+            }
+            | Commands::Outdated {
+                workspace: true,
+                fail_fast,
+            }
+            | Commands::Check {
+                workspace: true,
+                fail_fast,
+                ..
+            } => Some(*fail_fast),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct ConfigData {
-    wordpress_path: Option<String>,
+/// What `wdm export` writes.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    /// A composer.json using wpackagist and `package` repositories
+    Composer,
+    /// wdm.yml and wdm.lock as one JSON document
+    Json,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Dependency {
-    name: String,
-    version: String,
-    repo: String,
-    token_env: Option<String>,
-    #[serde(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    source: Option<String>,
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Show each cached archive, its size, and whether wdm.lock references it
+    List,
+    /// Delete cached archives that wdm.lock does not reference, and the cached tags
+    Clean {
+        /// Delete every cached archive
+        #[arg(long)]
+        all: bool,
+        /// Also delete the previous versions kept for `wdm rollback`
+        #[arg(long)]
+        backups: bool,
+    },
+    /// Check cached archives against the hashes in wdm.lock
+    Verify,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Lockfile {
-    dependencies: Vec<LockedDependency>,
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the value of a config key
+    Get { key: String },
+    /// Change a config key; `null` unsets it
+    Set { key: String, value: String },
+    /// Print every config key that is set
+    List,
 }
 
+// Unknown keys are rejected so a typo like `verion:` fails loudly instead of being ignored
 #[derive(Serialize, Deserialize, Debug)]
-struct LockedDependency {
-    name: String,
-    version: String,
-    repo: String,
-    hash: String,
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// A manifest, by path or URL, whose config and dependencies this one builds on
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extends: Option<String>,
+    config: ConfigData,
+    /// Commands run for every dependency, after its own hooks
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Hooks::is_empty")]
+    hooks: Hooks,
+    dependencies: Vec<Dependency>,
+    /// Dependencies only needed during development, skipped by `install --no-dev`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dev_dependencies: Vec<Dependency>,
+    /// What came from `extends`, which is left out when wdm.yml is written
+    #[serde(skip)]
+    inherited: Option<Inherited>,
+    /// The values `${VAR}` interpolation changed, which are written back as they were
+    #[serde(skip)]
+    interpolated: Vec<Interpolated>,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Check if Git is installed
-    if let Err(e) = check_git_installed() {
-        println!("{}", e);
-        return Ok(());
+impl Config {
+    /// An empty manifest for a new project.
+    fn new(wordpress_path: String) -> Self {
+        Config {
+            config: ConfigData {
+                wordpress_path: Some(wordpress_path),
+                http_attempts: None,
+                http_retry_delay_ms: None,
+                http_timeout_secs: None,
+                http_connect_timeout_secs: None,
+                cache_dir: None,
+                use_global_cache: None,
+                wordpress_version: None,
+                php_version: None,
+                github_host: None,
+                default_token_env: None,
+                resolver: None,
+                tag_cache_ttl_secs: None,
+                proxy: None,
+                no_proxy: None,
+                ca_cert_path: None,
+                danger_accept_invalid_certs: None,
+                vendor_dir: None,
+                backup_retention: None,
+            },
+            hooks: Hooks::default(),
+            dependencies: Vec::new(),
+            dev_dependencies: Vec::new(),
+            extends: None,
+            inherited: None,
+            interpolated: Vec::new(),
+        }
     }
 
-    let cli = Cli::parse();
+    /// Returns the dependencies followed by the dev dependencies, if they are included.
+    fn selected_dependencies(&self, include_dev: bool) -> Vec<&Dependency> {
+        let dev: &[Dependency] = if include_dev {
+            &self.dev_dependencies
+        } else {
+            &[]
+        };
+        self.dependencies.iter().chain(dev).collect()
+    }
 
-    match &cli.command {
-        Commands::Init => {
-            if Path::new("wdm.yml").exists() {
-                println!("wdm.yml already exists");
-            } else {
-                let config = Config {
-                    config: ConfigData {
-                        wordpress_path: Some(".".to_string()), // Set default to "."
-                    },
-                    dependencies: Vec::new(),
-                };
-                fs::write("wdm.yml", serde_yaml::to_string(&config)?)?;
-                println!("Initialized wdm.yml");
+    /// Whether the named dependency is listed under dev_dependencies.
+    fn is_dev(&self, name: &str) -> bool {
+        self.dev_dependencies
+            .iter()
+            .any(|d| same_name(&d.name, name))
+    }
+
+    /// Expands `${VAR}` in the values that differ between environments: the
+    /// WordPress path and GitHub host in the config section, and the `repo`,
+    /// `host`, `install_path`, and `url` of each dependency.
+    fn interpolate(&mut self) -> Result<(), WdmError> {
+        let mut interpolated = Vec::new();
+        let mut expand = |dependency: Option<&str>, field: &'static str, value: &mut String| {
+            let name = match dependency {
+                Some(dep) => format!("the {} of {}", field, dep),
+                None => format!("config.{}", field),
+            };
+            let resolved = interpolate(value, &name)?;
+            if resolved != *value {
+                interpolated.push(Interpolated {
+                    dependency: dependency.map(str::to_string),
+                    field,
+                    raw: std::mem::replace(value, resolved.clone()),
+                    resolved,
+                });
+            }
+            Ok::<_, WdmError>(())
+        };
+        for (field, value) in [
+            ("wordpress_path", &mut self.config.wordpress_path),
+            ("github_host", &mut self.config.github_host),
+        ] {
+            if let Some(value) = value {
+                expand(None, field, value)?;
             }
-            Ok(())
         }
-        Commands::Add {
-            name,
-            version,
-            repo,
-            token_env,
-        } => {
-            let mut config: Config = if Path::new("wdm.yml").exists() {
-                serde_yaml::from_str(&fs::read_to_string("wdm.yml")?)?
-            } else {
-                Config {
-                    config: ConfigData {
-                        wordpress_path: Some(".".to_string()),
-                    },
-                    dependencies: Vec::new(),
+        for dep in self
+            .dependencies
+            .iter_mut()
+            .chain(self.dev_dependencies.iter_mut())
+        {
+            expand(Some(&dep.name), "repo", &mut dep.repo)?;
+            for (field, value) in [
+                ("host", &mut dep.host),
+                ("install_path", &mut dep.install_path),
+                ("url", &mut dep.url),
+            ] {
+                if let Some(value) = value {
+                    expand(Some(&dep.name), field, value)?;
                 }
-            };
-
-            // Normalize the name for consistent comparison
-            let normalized_name = name.trim().to_lowercase();
-
-            // Remove any existing dependency with the same normalized name to prevent duplicates
-            let initial_len = config.dependencies.len();
-            config
-                .dependencies
-                .retain(|d| d.name.trim().to_lowercase() != normalized_name);
-
-            let mut dependency_existed = false;
-            if config.dependencies.len() < initial_len {
-                println!(
-                    "Dependency '{}' already exists. Updating its information.",
-                    name
-                );
-                dependency_existed = true;
             }
+        }
+        self.interpolated = interpolated;
+        Ok(())
+    }
 
-            // Add the new or updated dependency
-            config.dependencies.push(Dependency {
-                name: name.trim().to_string(),
-                version: version.trim().to_string(),
-                repo: repo.trim().to_string(),
-                token_env: token_env.clone(),
-                source: None,
-            });
+    /// The value in wdm.yml of a config key that interpolation changed.
+    fn raw_config_value(&self, key: &str) -> Option<&str> {
+        self.interpolated
+            .iter()
+            .find(|i| i.dependency.is_none() && i.field == key)
+            .map(|i| i.raw.as_str())
+    }
 
-            if dependency_existed {
-                println!("Updated {} in wdm.yml", name);
-            } else {
-                println!("Added {} to wdm.yml", name);
-            }
+    /// The manifest a dependency was taken from, unless wdm.yml lists it itself.
+    fn inherited_from(&self, name: &str) -> Option<&str> {
+        let inherited = self.inherited.as_ref()?;
+        if inherited.local_names.iter().any(|n| same_name(n, name)) {
+            return None;
+        }
+        self.base_origin(name)
+    }
 
-            fs::write("wdm.yml", serde_yaml::to_string(&config)?)?;
+    /// The manifest among the ones wdm.yml extends that lists the named
+    /// dependency, even when wdm.yml overrides it.
+    fn base_origin(&self, name: &str) -> Option<&str> {
+        self.inherited
+            .as_ref()?
+            .origins
+            .iter()
+            .find(|(n, _)| same_name(n, name))
+            .map(|(_, origin)| origin.as_str())
+    }
+}
 
-            // Proceed to install the newly added dependency
-            install_dependency(&config.dependencies.last().unwrap())?;
+/// A value in wdm.yml that had environment variables expanded.
+#[derive(Debug)]
+struct Interpolated {
+    /// The dependency the value belongs to, or `None` for the config section
+    dependency: Option<String>,
+    field: &'static str,
+    raw: String,
+    resolved: String,
+}
 
-            Ok(())
-        }
-        Commands::Remove { name } => {
-            if !Path::new("wdm.yml").exists() {
-                println!("wdm.yml does not exist. Run 'wdm init' first.");
-                return Ok(());
+impl Interpolated {
+    /// Puts the value back the way wdm.yml had it, unless a command changed it.
+    fn restore(&self, manifest: &mut serde_yaml::Mapping) {
+        let raw = serde_yaml::Value::String(self.raw.clone());
+        let resolved = serde_yaml::Value::String(self.resolved.clone());
+        let mut values: Vec<&mut serde_yaml::Value> = Vec::new();
+        for (key, section) in manifest.iter_mut() {
+            match (&self.dependency, key.as_str()) {
+                (None, Some("config")) => values.push(section),
+                (Some(name), Some("dependencies" | "dev_dependencies")) => {
+                    let deps = section.as_sequence_mut().into_iter().flatten();
+                    values.extend(deps.filter(|dep| same_name(dependency_name(dep), name)));
+                }
+                _ => {}
             }
-
-            let mut config: Config = serde_yaml::from_str(&fs::read_to_string("wdm.yml")?)?;
-            let initial_len = config.dependencies.len();
-            config.dependencies.retain(|d| d.name != *name);
-
-            if config.dependencies.len() < initial_len {
-                fs::write("wdm.yml", serde_yaml::to_string(&config)?)?;
-                println!("Removed {} from wdm.yml", name);
-            } else {
-                println!("Dependency '{}' not found in wdm.yml", name);
+        }
+        for values in values {
+            if let Some(value) = values.get_mut(self.field).filter(|v| **v == resolved) {
+                *value = raw.clone();
             }
+        }
+    }
+}
 
-            // Optionally, add code to remove the plugin from the wordpress_path
-            Ok(())
+/// Expands `${VAR}` and `${VAR:-default}` in `value`, taken from `field` of
+/// wdm.yml. The default is used when `VAR` is unset or empty, and `$$` stands
+/// for a literal `$`.
+fn interpolate(value: &str, field: &str) -> Result<String, WdmError> {
+    let invalid = || WdmError::InvalidInterpolation {
+        field: field.to_string(),
+        value: value.to_string(),
+    };
+    let mut expanded = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = after;
+            continue;
         }
-        Commands::Install => {
-            if !Path::new("wdm.yml").exists() {
-                println!("wdm.yml does not exist. Run 'wdm init' first.");
-                return Ok(());
+        let Some(after) = rest.strip_prefix("${") else {
+            expanded.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = after.find('}').ok_or_else(invalid)?;
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(invalid());
+        }
+        match (env::var(name).ok(), default) {
+            (Some(set), Some(default)) if set.is_empty() => expanded.push_str(default),
+            (Some(set), _) => expanded.push_str(&set),
+            (None, Some(default)) => expanded.push_str(default),
+            (None, None) => {
+                return Err(WdmError::UnsetVariable {
+                    variable: name.to_string(),
+                    field: field.to_string(),
+                })
             }
+        }
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
 
-            let config: Config = serde_yaml::from_str(&fs::read_to_string("wdm.yml")?)?;
-            let mut lockfile = if Path::new("wdm.lock").exists() {
-                serde_yaml::from_str(&fs::read_to_string("wdm.lock")?)?
-            } else {
-                Lockfile {
-                    dependencies: Vec::new(),
-                }
+/// The config, hooks, and dependencies a manifest took from the manifests it extends.
+#[derive(Debug)]
+struct Inherited {
+    /// The bases merged into one manifest, as wdm would write it
+    base: serde_yaml::Mapping,
+    /// The manifest each inherited dependency is listed in, by name
+    origins: Vec<(String, String)>,
+    /// The config keys and hooks the manifest sets itself
+    local_keys: Vec<(String, serde_yaml::Value)>,
+    /// The dependencies the manifest lists itself, in either section
+    local_names: Vec<String>,
+}
+
+impl Inherited {
+    /// Removes from `manifest` the config keys, hooks, and dependencies that are
+    /// still exactly as a base has them, so only the project's own entries are written.
+    fn strip(&self, manifest: &mut serde_yaml::Mapping) {
+        for section in ["config", "hooks"] {
+            let Some(serde_yaml::Value::Mapping(values)) = manifest.get_mut(section) else {
+                continue;
+            };
+            let base = self.base.get(section);
+            values.retain(|key, value| {
+                self.local_keys
+                    .iter()
+                    .any(|(s, k)| s == section && k == key)
+                    || base.and_then(|base| base.get(key)) != Some(value)
+            });
+        }
+        if manifest
+            .get("hooks")
+            .and_then(|hooks| hooks.as_mapping())
+            .is_some_and(|hooks| hooks.is_empty())
+        {
+            manifest.remove("hooks");
+        }
+        for section in ["dependencies", "dev_dependencies"] {
+            let Some(serde_yaml::Value::Sequence(deps)) = manifest.get_mut(section) else {
+                continue;
             };
+            let base = self.base.get(section).and_then(|deps| deps.as_sequence());
+            deps.retain(|dep| {
+                let name = dependency_name(dep);
+                self.local_names.iter().any(|n| same_name(n, name))
+                    || !base.is_some_and(|base| base.contains(dep))
+            });
+        }
+        if manifest
+            .get("dev_dependencies")
+            .and_then(|deps| deps.as_sequence())
+            .is_some_and(|deps| deps.is_empty())
+        {
+            manifest.remove("dev_dependencies");
+        }
+    }
+}
 
-            // Determine the root directory (where wdm.yml is located)
-            let root_dir = resolve_root_dir()?;
+/// The `name` of a dependency in a parsed manifest.
+fn dependency_name(dep: &serde_yaml::Value) -> &str {
+    dep.get("name")
+        .and_then(|name| name.as_str())
+        .unwrap_or_default()
+}
 
-            // Ensure that lockfile is at root_dir
-            let lockfile_path = root_dir.join("wdm.lock");
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfigData {
+    wordpress_path: Option<String>,
+    /// How many times an HTTP request is attempted before giving up
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http_attempts: Option<u32>,
+    /// The delay before the first retry, doubled after each further attempt
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http_retry_delay_ms: Option<u64>,
+    /// How long a single request, including its body, may take
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http_timeout_secs: Option<u64>,
+    /// How long connecting to a server may take
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    http_connect_timeout_secs: Option<u64>,
+    /// Where downloaded archives are kept instead of the project's `.wdm-cache`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_dir: Option<String>,
+    /// Share downloaded archives with other projects through the user cache directory
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    use_global_cache: Option<bool>,
+    /// The WordPress version plugins are checked against, instead of reading wp-includes/version.php
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wordpress_version: Option<String>,
+    /// The PHP version the site runs, which plugins' `Requires PHP` is checked against
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    php_version: Option<String>,
+    /// The GitHub Enterprise Server GitHub dependencies without a `host` come from
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    github_host: Option<String>,
+    /// The variable holding the token for GitHub, GitLab, and Bitbucket dependencies without a `token_env`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_token_env: Option<String>,
+    /// How tags of GitHub dependencies without a token are listed; picked automatically when unset
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolver: Option<Resolver>,
+    /// How long the tags listed for a repository are reused before asking it again (0 disables)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag_cache_ttl_secs: Option<u64>,
+    /// The proxy every request goes through, instead of the one in `HTTPS_PROXY` or `HTTP_PROXY`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<String>,
+    /// Comma-separated hosts that are reached directly rather than through `proxy`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    no_proxy: Option<String>,
+    /// A PEM file with the certificates of private CAs, trusted on top of the usual ones
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ca_cert_path: Option<String>,
+    /// Skip TLS certificate verification entirely; a last resort, warned about on every run
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    danger_accept_invalid_certs: Option<bool>,
+    /// Where `wdm vendor` keeps archives, which installs use before the cache or the network
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendor_dir: Option<String>,
+    /// How many replaced versions of each dependency install keeps for `wdm rollback`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backup_retention: Option<usize>,
+}
 
-            // Ensure that .wdm-cache directory is at root_dir
-            let cache_dir = root_dir.join(".wdm-cache");
-            if !cache_dir.exists() {
-                fs::create_dir_all(&cache_dir)?;
-            }
+/// How the tags and branches of a GitHub repository are looked up.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Resolver {
+    /// The GitHub REST API, which works without git but is rate limited without a token
+    Api,
+    /// `git ls-remote`, which needs git on PATH
+    Git,
+}
 
-            // Set wordpress_path to default to '.' if not specified
-            let wordpress_path = if let Some(path) = &config.config.wordpress_path {
-                Path::new(&path).to_path_buf()
-            } else {
-                Path::new(".").to_path_buf()
-            };
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct Dependency {
+    name: String,
+    version: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    repo: String,
+    token_env: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<Source>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset: Option<String>,
+    #[serde(rename = "type", default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<DependencyType>,
+    /// Where to install instead of `wp-content/<type>/<name>`, relative to wordpress_path
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    install_path: Option<String>,
+    /// The folder inside the archive to extract, instead of detecting its single top-level folder
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strip_prefix: Option<String>,
+    /// The archive's format, for the rare download whose contents can't be told apart
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archive_format: Option<ArchiveFormat>,
+    /// The subdirectory of the repository holding the plugin, for monorepos
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Hooks::is_empty")]
+    hooks: Hooks,
+    /// Let `latest` and version ranges pick prereleases such as `3.0.0-rc.1`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    allow_prerelease: bool,
+    /// A regex whose first capture group extracts the version from a tag name
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag_pattern: Option<String>,
+    /// A `sha256-<hex>` hash the archive must match, which --insecure-skip-verify can't override
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    integrity: Option<String>,
+    /// The environments the dependency is installed in, such as `staging`; all of them when empty
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    envs: Vec<String>,
+}
 
-            for dep in &config.dependencies {
-                println!("Installing {}...", dep.name);
+/// Shell commands run around installing a dependency.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+struct Hooks {
+    /// Run in the WordPress directory before the previous install is replaced
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pre_install: Vec<String>,
+    /// Run in the freshly extracted directory
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    post_install: Vec<String>,
+}
 
-                let token = if let Some(token_env) = &dep.token_env {
-                    env::var(token_env).ok()
-                } else {
-                    None
-                };
+impl Hooks {
+    fn is_empty(&self) -> bool {
+        self.pre_install.is_empty() && self.post_install.is_empty()
+    }
+}
 
-                let version =
-                    match resolve_github_version(&dep.repo, &dep.version, token.as_deref()) {
-                        Ok(ver) => ver,
-                        Err(e) => {
-                            println!("Error resolving version for {}: {}", dep.name, e);
-                            continue;
-                        }
-                    };
+/// Where a dependency is fetched from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum Source {
+    /// Tagged releases of a GitHub repository
+    #[default]
+    #[serde(rename = "github")]
+    #[value(name = "github")]
+    Github,
+    /// The official WordPress.org plugin directory
+    #[serde(rename = "wordpress.org")]
+    #[value(name = "wordpress.org")]
+    WordpressOrg,
+    /// A zip or tar.gz archive downloaded from an arbitrary URL
+    #[serde(rename = "url")]
+    #[value(name = "url")]
+    Url,
+    /// Tagged releases of a project on gitlab.com or a self-hosted GitLab
+    #[serde(rename = "gitlab")]
+    #[value(name = "gitlab")]
+    Gitlab,
+    /// Tagged releases of a Bitbucket Cloud repository
+    #[serde(rename = "bitbucket")]
+    #[value(name = "bitbucket")]
+    Bitbucket,
+}
+
+/// The kind of package a dependency is, which decides where it is installed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+enum DependencyType {
+    #[default]
+    Plugin,
+    Theme,
+    MuPlugin,
+}
+
+impl DependencyType {
+    /// Returns the directory under the WordPress root that holds this kind of package.
+    fn content_dir(self) -> &'static str {
+        match self {
+            DependencyType::Plugin => "wp-content/plugins",
+            DependencyType::Theme => "wp-content/themes",
+            DependencyType::MuPlugin => "wp-content/mu-plugins",
+        }
+    }
+}
+
+impl Dependency {
+    /// Whether the dependency is installed in `env`, the active environment.
+    /// Without one, only dependencies that list no `envs` are.
+    fn applies_to(&self, env: Option<&str>) -> bool {
+        self.envs.is_empty()
+            || env.is_some_and(|env| self.envs.iter().any(|e| e.eq_ignore_ascii_case(env)))
+    }
+
+    /// Compiles `tag_pattern`, which must have a capture group for the version.
+    fn tag_pattern(&self) -> Result<Option<Regex>, ResolveError> {
+        let Some(pattern) = &self.tag_pattern else {
+            return Ok(None);
+        };
+        let regex = Regex::new(pattern).map_err(|source| ResolveError::InvalidTagPattern {
+            pattern: pattern.clone(),
+            source,
+        })?;
+        if regex.captures_len() < 2 {
+            return Err(ResolveError::TagPatternWithoutGroup(pattern.clone()));
+        }
+        Ok(Some(regex))
+    }
+
+    /// Returns the directory this dependency is installed into.
+    fn install_dir(&self, wordpress_path: &Path) -> PathBuf {
+        match &self.install_path {
+            Some(install_path) => wordpress_path.join(install_path),
+            None => wordpress_path
+                .join(self.kind.unwrap_or_default().content_dir())
+                .join(normalize_name(&self.name)),
+        }
+    }
+}
+
+/// What the positional argument of `wdm add` names.
+struct AddSpec {
+    name: String,
+    repo: Option<String>,
+    /// The host of a repository URL that isn't github.com, gitlab.com, or bitbucket.org
+    host: Option<String>,
+    /// The source a repository URL implies
+    source: Option<Source>,
+    /// The requirement after `@`
+    version: Option<String>,
+}
+
+/// Parses the positional argument of `wdm add`: a plain name, `owner/repo`,
+/// or a repository URL, optionally followed by `@<version requirement>`. For
+/// repositories, the name is the last segment of its path.
+fn parse_add_spec(spec: &str, source: Option<Source>) -> Result<AddSpec, WdmError> {
+    let spec = spec.trim();
+    // An `@` before the last slash belongs to the credentials of a URL
+    let (target, version) = match spec.rsplit_once('@') {
+        Some((target, version)) if !target.is_empty() && !version.contains('/') => {
+            (target, Some(version.trim().to_string()))
+        }
+        _ => (spec, None),
+    };
+    if version.as_deref() == Some("") {
+        return Err(WdmError::InvalidArgument(format!(
+            "'{}' has nothing after '@': give a version, or leave out the '@'",
+            spec
+        )));
+    }
+    if !target.contains('/') {
+        return Ok(AddSpec {
+            name: target.to_string(),
+            repo: None,
+            host: None,
+            source: None,
+            version,
+        });
+    }
+
+    let source = source.or_else(|| repo_url_source(target));
+    let (repo, host) = normalize_repo(target, source)?;
+    Ok(AddSpec {
+        name: repo.rsplit('/').next().unwrap_or(&repo).to_string(),
+        repo: Some(repo),
+        host,
+        source,
+        version,
+    })
+}
+
+/// The source a repository URL on one of the public hosts belongs to.
+fn repo_url_source(repo: &str) -> Option<Source> {
+    let url = reqwest::Url::parse(repo).ok()?;
+    match url.host_str()?.trim_start_matches("www.") {
+        "github.com" => Some(Source::Github),
+        "gitlab.com" => Some(Source::Gitlab),
+        "bitbucket.org" => Some(Source::Bitbucket),
+        _ => None,
+    }
+}
+
+/// Turns `repo`, either `owner/repo` or the URL of its page, into the
+/// `owner/repo` written to wdm.yml, checking that it names a repository
+/// before anything tries to list its tags. Also returns the host of a URL
+/// for a self-hosted instance. GitLab projects may be nested in subgroups.
+fn normalize_repo(
+    repo: &str,
+    source: Option<Source>,
+) -> Result<(String, Option<String>), WdmError> {
+    let nested = source == Some(Source::Gitlab);
+    let (path, host) = if repo.contains("://") {
+        let url = reqwest::Url::parse(repo).map_err(|e| {
+            WdmError::InvalidArgument(format!("'{}' is not a valid repository URL: {}", repo, e))
+        })?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(WdmError::InvalidArgument(format!(
+                "'{}' is not a web URL: use https://<host>/owner/repo or owner/repo",
+                repo
+            )));
+        }
+        let host = url.host_str().unwrap_or_default();
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let segments: Vec<&str> = url
+            .path_segments()
+            .map(|s| s.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        // GitLab puts the pages of a project after a `-` segment
+        let segments: Vec<&str> = if nested {
+            segments.into_iter().take_while(|s| *s != "-").collect()
+        } else {
+            segments
+        };
+        let public = repo_url_source(repo).is_some();
+        (segments.join("/"), (!public).then_some(host))
+    } else {
+        (repo.trim_matches('/').to_string(), None)
+    };
+
+    let segments: Vec<&str> = path.split('/').collect();
+    if segments.len() < 2 {
+        return Err(WdmError::InvalidArgument(format!(
+            "'{}' is not a repository: use the form owner/repo",
+            repo
+        )));
+    }
+    if let Some(stripped) = path.strip_suffix(".git") {
+        return Err(WdmError::InvalidArgument(format!(
+            "'{}' ends in .git: use '{}' instead",
+            repo, stripped
+        )));
+    }
+    if segments.len() > 2 && !nested {
+        return Err(WdmError::InvalidArgument(format!(
+            "'{}' has extra path segments: use '{}' instead",
+            repo,
+            segments[..2].join("/")
+        )));
+    }
+    let valid = |segment: &&str| {
+        !segment.is_empty()
+            && *segment != "."
+            && *segment != ".."
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    if !segments.iter().all(valid) {
+        return Err(WdmError::InvalidArgument(format!(
+            "'{}' is not a valid repository: owners and names may only contain letters, digits, '-', '_', and '.'",
+            repo
+        )));
+    }
+    Ok((path, host))
+}
+
+/// The form dependency names are compared in. wdm.yml and wdm.lock keep the
+/// casing they were written with, but `Foo` and `foo` are the same dependency,
+/// installed into `wp-content/plugins/foo`.
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Orders dependency names the way they compare, falling back to their casing
+/// so that the order never depends on how a name was typed.
+fn compare_names(a: &str, b: &str) -> std::cmp::Ordering {
+    normalize_name(a)
+        .cmp(&normalize_name(b))
+        .then_with(|| a.cmp(b))
+}
+
+/// Whether two dependency names refer to the same dependency.
+fn same_name(a: &str, b: &str) -> bool {
+    normalize_name(a) == normalize_name(b)
+}
+
+/// Rejects an `install_path` that would put a dependency outside the WordPress root,
+/// or on top of it, since installing replaces the whole directory.
+fn check_install_path(dep: &Dependency, wordpress_path: &Path) -> Result<(), WdmError> {
+    let Some(install_path) = &dep.install_path else {
+        return Ok(());
+    };
+    let invalid = |reason| WdmError::InvalidInstallPath {
+        dep: dep.name.clone(),
+        path: install_path.clone(),
+        reason,
+    };
+
+    let path = Path::new(install_path);
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(invalid("must not contain '..'"));
+    }
+    let relative = if path.is_absolute() {
+        let root =
+            fs::canonicalize(wordpress_path).or_else(|_| std::path::absolute(wordpress_path))?;
+        path.strip_prefix(&root)
+            .map_err(|_| invalid("is outside the WordPress root"))?
+    } else {
+        path
+    };
+    if relative.components().all(|c| c == Component::CurDir) {
+        return Err(invalid("must be a directory inside the WordPress root"));
+    }
+    Ok(())
+}
+
+/// The newest wdm.lock format this build reads and writes.
+const LOCKFILE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Lockfile {
+    /// The format of the file. Lockfiles written before it was recorded are version 1.
+    #[serde(default = "lockfile_v1")]
+    version: u32,
+    dependencies: Vec<LockedDependency>,
+}
+
+fn lockfile_v1() -> u32 {
+    1
+}
+
+impl Lockfile {
+    fn new() -> Self {
+        Lockfile {
+            version: LOCKFILE_VERSION,
+            dependencies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LockedDependency {
+    name: String,
+    version: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    repo: String,
+    hash: String,
+    #[serde(rename = "type", default)]
+    kind: DependencyType,
+    #[serde(default)]
+    source: Source,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asset: Option<String>,
+    /// The subdirectory of the repository that was installed
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    /// The `install_path` override the dependency was installed with, if any
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    install_path: Option<String>,
+    /// Set for dev dependencies so production installs know to leave them out
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dev: bool,
+    /// The URL the archive was downloaded from, never including credentials
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resolved_url: Option<String>,
+    /// When this version was first locked, as an RFC 3339 UTC timestamp
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locked_at: Option<String>,
+    /// The `Version` header, which can differ from the tag when a release wasn't bumped
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    header_version: Option<String>,
+    /// The `Requires at least` header: the oldest WordPress it supports
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requires_wp: Option<String>,
+    /// The `Requires PHP` header: the oldest PHP it supports
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requires_php: Option<String>,
+    /// The `Requires Plugins` header: the slugs of plugins it needs to run
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    requires_plugins: Vec<String>,
+    /// Files extracted from the archive, relative to the install directory and
+    /// sorted, so `remove` can leave files added by hand alone
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+}
+
+/// Everything that can make a wdm command fail.
+#[derive(Debug, thiserror::Error)]
+enum WdmError {
+    #[error(
+        "wdm.yml does not exist in this directory or any parent directory. Run 'wdm init' first."
+    )]
+    ManifestNotFound,
+    #[error("Manifest path {} {reason}", .path.display())]
+    InvalidManifestPath { path: PathBuf, reason: &'static str },
+    #[error("Failed to parse {}: {source}{}", .path.display(), suggest_field(&source.to_string()))]
+    ManifestParse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+    #[error("Failed to parse {}: {source}", .path.display())]
+    ComposerParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("wdm.lock does not exist. Run 'wdm install' without --frozen to create it.")]
+    LockfileNotFound,
+    #[error(
+        "{} uses lockfile format version {version}, but this wdm only understands up to version {}. Upgrade wdm to use it.",
+        .path.display(),
+        LOCKFILE_VERSION
+    )]
+    UnsupportedLockfile { path: PathBuf, version: u32 },
+    #[error(
+        "{}\nRe-lock these dependencies by running 'wdm install' without --frozen.",
+        .0.join("\n")
+    )]
+    LockOutdated(Vec<String>),
+    #[error("Dependency '{0}' not found in wdm.yml")]
+    DependencyNotFound(String),
+    #[error(
+        "No dependency named {} in wdm.yml. Available dependencies: {}",
+        .names.join(", "),
+        if .available.is_empty() { "none".to_string() } else { .available.join(", ") }
+    )]
+    UnknownDependencies {
+        names: Vec<String>,
+        available: Vec<String>,
+    },
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("Unknown config key '{0}'. Supported keys: {}", config_keys().join(", "))]
+    UnknownConfigKey(String),
+    #[error("Config key '{0}' is not set")]
+    ConfigKeyNotSet(String),
+    #[error(
+        "Refusing to delete {0:?}: it was not installed by wdm. Use --force to delete it anyway."
+    )]
+    UnmanagedDirectory(PathBuf),
+    #[error(
+        "Refusing to overwrite what wdm did not create:{}\n  hint: pass --force to replace it anyway",
+        .0.iter().map(|p| format!("\n  {}", p.display())).collect::<String>()
+    )]
+    WouldOverwrite(Vec<PathBuf>),
+    #[error("install_path {path:?} of {dep} {reason}")]
+    InvalidInstallPath {
+        dep: String,
+        path: String,
+        reason: &'static str,
+    },
+    #[error(
+        "Invalid version requirement '{version}' for {dep}{}: {source}",
+        .path.as_ref().map(|p| format!(" in {}", p.display())).unwrap_or_default()
+    )]
+    InvalidVersion {
+        dep: String,
+        version: String,
+        /// The manifest it was read from, when it wasn't given on the command line
+        path: Option<PathBuf>,
+        source: RequirementError,
+    },
+    #[error(
+        "wordpress_path {0:?} does not exist. Set config.wordpress_path in wdm.yml to your WordPress directory."
+    )]
+    WordPressPathNotFound(PathBuf),
+    #[error("Error resolving version for {dep}: {source}{}", resolution_hint(*.from, .source))]
+    VersionResolution {
+        dep: String,
+        /// Where the dependency comes from, which decides the hint
+        from: Source,
+        source: ResolveError,
+    },
+    #[error("{}{}", describe_download(.dep, .source), hint(*.from, .source))]
+    Download {
+        dep: String,
+        from: Source,
+        source: SourceError,
+    },
+    #[error(
+        "Error downloading {dep}: {url} did not return a zip or tar.gz archive{}{}\n  hint: the tag may not exist, or a proxy or login page answered instead; nothing was cached",
+        .content_type.as_ref().map(|t| format!(" (Content-Type: {})", t)).unwrap_or_default(),
+        .first_line.as_ref().map(|l| format!(", it starts with {:?}", l)).unwrap_or_default()
+    )]
+    NotAnArchive {
+        dep: String,
+        url: String,
+        content_type: Option<String>,
+        /// The beginning of the body, when it looks like text
+        first_line: Option<String>,
+    },
+    #[error("Error extracting {dep}: {source}")]
+    Extraction {
+        dep: String,
+        source: ExtractionError,
+    },
+    #[error(
+        "Error generating loader for {dep}: No PHP file with a 'Plugin Name:' header in {dir:?}"
+    )]
+    NoPluginHeader { dep: String, dir: PathBuf },
+    #[error("Error generating loader for {dep}: {source}")]
+    MuPluginLoader { dep: String, source: std::io::Error },
+    #[error(
+        "{dep} is not compatible with this site: {}. Install it without --strict-compat to install it anyway.",
+        .problems.join("; ")
+    )]
+    Incompatible { dep: String, problems: Vec<String> },
+    #[error(
+        "{dep} does not match its headers: {}. Install it without --strict to install it anyway.",
+        .problems.join("; ")
+    )]
+    HeaderMismatch { dep: String, problems: Vec<String> },
+    #[error("The {phase} hook `{command}` for {dep} failed: {reason}")]
+    Hook {
+        dep: String,
+        phase: HookPhase,
+        command: String,
+        reason: String,
+    },
+    #[error(
+        "WARNING: Hash mismatch for {dep} {}: expected {expected}, got {actual}\n{}",
+        display_version(.version),
+        describe_hash_mismatch(.dep, .version, .url.as_deref())
+    )]
+    HashMismatch {
+        dep: String,
+        version: String,
+        url: Option<String>,
+        expected: String,
+        actual: String,
+    },
+    #[error(
+        "Integrity check failed for {dep} {}: wdm.yml requires {expected}, got {actual}\nLeaving the installed {dep} untouched. --insecure-skip-verify does not apply to the integrity in wdm.yml; change it there once the new archive has been reviewed.",
+        display_version(.version)
+    )]
+    IntegrityMismatch {
+        dep: String,
+        version: String,
+        expected: String,
+        actual: String,
+    },
+    #[error(
+        "{dep}: wdm.yml pins the integrity of {}, so it was not updated to {}. Run 'wdm update {dep} --update-integrity' to install it and record its hash in wdm.yml.",
+        display_version(.locked),
+        display_version(.version)
+    )]
+    IntegrityPinned {
+        dep: String,
+        locked: String,
+        version: String,
+    },
+    #[error("{dep}: integrity '{integrity}' in wdm.yml is not of the form sha256-<64 hex digits>")]
+    InvalidIntegrity { dep: String, integrity: String },
+    #[error("There is no earlier version of {0} to roll back to; wdm keeps one when an install replaces it")]
+    NoBackup(String),
+    #[error("{dep}: no entry in wdm.lock satisfies '{version_req}'")]
+    NotLocked { dep: String, version_req: String },
+    #[error("{dep}: {path:?} is not cached")]
+    NotCached { dep: String, path: PathBuf },
+    #[error("{dep}: {path:?} does not match the hash in wdm.lock")]
+    CacheMismatch { dep: String, path: PathBuf },
+    #[error("Cannot install offline:{}", .0.iter().map(|e| format!("\n  {}", e)).collect::<String>())]
+    OfflineUnavailable(Vec<WdmError>),
+    #[error(
+        "Another wdm ({}) is changing this project. {}",
+        describe_lock_holder(*.pid),
+        match .waited {
+            Some(waited) => format!("Gave up after waiting {}s; pass --lock-timeout to wait longer.", waited.as_secs()),
+            None => "Run again without --no-wait to wait for it to finish.".to_string(),
+        }
+    )]
+    ProjectLocked {
+        pid: Option<u32>,
+        waited: Option<Duration>,
+    },
+    #[error(
+        "wdm-workspace.yml does not exist in this directory or any parent directory. List the members there to use --workspace."
+    )]
+    WorkspaceNotFound,
+    #[error(
+        "{field} in wdm.yml uses ${{{variable}}}, but {variable} is not set. Set it, or give a default with ${{{variable}:-default}}."
+    )]
+    UnsetVariable { variable: String, field: String },
+    #[error(
+        "{field} in wdm.yml has an invalid ${{...}} in '{value}'. Write ${{VAR}} or ${{VAR:-default}}, and $$ for a literal $."
+    )]
+    InvalidInterpolation { field: String, value: String },
+    #[error("Failed to read {location}, which {from} extends: {reason}")]
+    ExtendsFailed {
+        location: String,
+        from: String,
+        reason: String,
+    },
+    #[error("Manifests extend each other in a cycle: {}", .0.join(" -> "))]
+    ExtendsCycle(Vec<String>),
+    #[error(
+        "'{dep}' comes from {origin}, which wdm.yml extends. wdm only changes wdm.yml itself; remove it from {origin} instead."
+    )]
+    InheritedDependency { dep: String, origin: String },
+    /// The command already printed what went wrong, such as every failed
+    /// dependency and a summary, so `main` only sets the exit status
+    #[error("wdm failed; see the errors above")]
+    AlreadyReported,
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to serialize YAML: {0}")]
+    Serialize(#[from] serde_yaml::Error),
+}
+
+impl WdmError {
+    /// A stable name for the kind of error, for `--json` output.
+    fn kind(&self) -> &'static str {
+        match self {
+            WdmError::ManifestNotFound => "manifest-not-found",
+            WdmError::InvalidManifestPath { .. } => "invalid-manifest-path",
+            WdmError::ManifestParse { .. } => "manifest-parse",
+            WdmError::LockfileNotFound => "lockfile-not-found",
+            WdmError::UnsupportedLockfile { .. } => "unsupported-lockfile",
+            WdmError::LockOutdated(_) => "lock-outdated",
+            WdmError::DependencyNotFound(_) => "dependency-not-found",
+            WdmError::UnknownDependencies { .. } => "unknown-dependencies",
+            WdmError::InvalidArgument(_) => "invalid-argument",
+            WdmError::ComposerParse { .. } => "composer-parse",
+            WdmError::UnknownConfigKey(_) => "unknown-config-key",
+            WdmError::ConfigKeyNotSet(_) => "config-key-not-set",
+            WdmError::UnmanagedDirectory(_) => "unmanaged-directory",
+            WdmError::WouldOverwrite(_) => "would-overwrite",
+            WdmError::InvalidInstallPath { .. } => "invalid-install-path",
+            WdmError::InvalidVersion { .. } => "invalid-version",
+            WdmError::WordPressPathNotFound(_) => "wordpress-path-not-found",
+            WdmError::VersionResolution { .. } => "version-resolution",
+            WdmError::Download { .. } => "download",
+            WdmError::NotAnArchive { .. } => "not-an-archive",
+            WdmError::Extraction { .. } => "extraction",
+            WdmError::Hook { .. } => "hook",
+            WdmError::NoPluginHeader { .. } | WdmError::MuPluginLoader { .. } => "mu-plugin-loader",
+            WdmError::Incompatible { .. } => "incompatible",
+            WdmError::HeaderMismatch { .. } => "header-mismatch",
+            WdmError::HashMismatch { .. } => "hash-mismatch",
+            WdmError::IntegrityMismatch { .. } => "integrity-mismatch",
+            WdmError::IntegrityPinned { .. } => "integrity-pinned",
+            WdmError::InvalidIntegrity { .. } => "invalid-integrity",
+            WdmError::NoBackup(_) => "no-backup",
+            WdmError::NotLocked { .. } => "not-locked",
+            WdmError::NotCached { .. } => "not-cached",
+            WdmError::CacheMismatch { .. } => "cache-mismatch",
+            WdmError::OfflineUnavailable(_) => "offline-unavailable",
+            WdmError::ProjectLocked { .. } => "project-locked",
+            WdmError::WorkspaceNotFound => "workspace-not-found",
+            WdmError::UnsetVariable { .. } => "unset-variable",
+            WdmError::InvalidInterpolation { .. } => "invalid-interpolation",
+            WdmError::ExtendsFailed { .. } => "extends-failed",
+            WdmError::ExtendsCycle(_) => "extends-cycle",
+            WdmError::InheritedDependency { .. } => "inherited-dependency",
+            WdmError::AlreadyReported => "already-reported",
+            WdmError::Io(_) => "io",
+            WdmError::Serialize(_) => "serialize",
+        }
+    }
+
+    /// The dependency the error is about, if any.
+    fn dependency(&self) -> Option<&str> {
+        match self {
+            WdmError::DependencyNotFound(dep)
+            | WdmError::NoBackup(dep)
+            | WdmError::InvalidInstallPath { dep, .. }
+            | WdmError::InvalidVersion { dep, .. }
+            | WdmError::VersionResolution { dep, .. }
+            | WdmError::Download { dep, .. }
+            | WdmError::NotAnArchive { dep, .. }
+            | WdmError::Extraction { dep, .. }
+            | WdmError::Hook { dep, .. }
+            | WdmError::NoPluginHeader { dep, .. }
+            | WdmError::MuPluginLoader { dep, .. }
+            | WdmError::Incompatible { dep, .. }
+            | WdmError::HeaderMismatch { dep, .. }
+            | WdmError::HashMismatch { dep, .. }
+            | WdmError::IntegrityMismatch { dep, .. }
+            | WdmError::IntegrityPinned { dep, .. }
+            | WdmError::InvalidIntegrity { dep, .. }
+            | WdmError::NotLocked { dep, .. }
+            | WdmError::NotCached { dep, .. }
+            | WdmError::CacheMismatch { dep, .. } => Some(dep),
+            _ => None,
+        }
+    }
+}
+
+/// Why asking a dependency's source for its tags, releases or archive failed.
+#[derive(Debug, thiserror::Error)]
+enum SourceError {
+    /// The server answered with an error status
+    #[error("{}", describe_status(*.0))]
+    Status(reqwest::StatusCode),
+    /// No response arrived
+    #[error("{}", describe_request_error(.0))]
+    Request(reqwest::Error),
+    /// The response stopped arriving for longer than the configured timeout
+    #[error("timed out after {}s", .0.as_secs())]
+    TimedOut(Duration),
+    #[error("Failed to read response body: {0}")]
+    Body(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Failed to parse {api} response: {source}")]
+    InvalidJson {
+        api: &'static str,
+        source: reqwest::Error,
+    },
+    #[error("Unexpected response from the {0}")]
+    UnexpectedResponse(&'static str),
+    /// The error WordPress.org reported for a lookup, such as an unknown slug
+    #[error("WordPress.org: {0}")]
+    WordpressOrg(String),
+    #[error("Failed to execute git: {0}")]
+    GitUnavailable(std::io::Error),
+    #[error("Git is not installed or not accessible in PATH.")]
+    GitMissing,
+    /// `git ls-remote` failed, with what it printed to stderr
+    #[error("Git command failed: {0}")]
+    Git(String),
+    #[error("Branch '{0}' not found in repository")]
+    BranchNotFound(String),
+    #[error("No GitHub release found for tag {0}")]
+    NoRelease(String),
+    #[error("No asset matching '{pattern}' in the {tag} release of {repo}")]
+    NoAsset {
+        pattern: String,
+        tag: String,
+        repo: String,
+    },
+    #[error("Release asset has no download URL")]
+    AssetWithoutUrl,
+    #[error("No url set for url dependency")]
+    MissingUrl,
+    #[error("Invalid token: {0}")]
+    InvalidToken(reqwest::header::InvalidHeaderValue),
+    /// Reading or writing the downloaded archive in the cache failed
+    #[error("Failed to {action} {}: {source}", .path.display())]
+    Cache {
+        action: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Why no version of a dependency could be picked from what its source lists.
+#[derive(Debug, thiserror::Error)]
+enum ResolveError {
+    #[error(transparent)]
+    Source(#[from] SourceError),
+    #[error("Invalid tag_pattern '{pattern}': {source}")]
+    InvalidTagPattern {
+        pattern: String,
+        source: regex::Error,
+    },
+    #[error("tag_pattern '{0}' needs a capture group around the version")]
+    TagPatternWithoutGroup(String),
+    /// Nothing listed parses as a version; says what was listed
+    #[error("No valid versions found in {0}.")]
+    NoVersions(&'static str),
+    #[error("Only prereleases found in repository tags; set allow_prerelease: true (or pass --pre) to use them")]
+    OnlyPrereleases,
+    #[error("Version {0} not found in repository tags")]
+    VersionNotFound(String),
+    #[error("Invalid version requirement '{requirement}': {source}")]
+    InvalidRequirement {
+        requirement: String,
+        source: semver::Error,
+    },
+    #[error("No matching version found for requirement {0}")]
+    NoMatch(String),
+}
+
+/// Why a `version` in wdm.yml or on the command line isn't one wdm can resolve.
+#[derive(Debug, thiserror::Error)]
+enum RequirementError {
+    #[error("branch= needs the name of a branch")]
+    EmptyBranch,
+    #[error("commit= needs a SHA of at least 7 hexadecimal digits")]
+    InvalidCommit,
+    #[error(transparent)]
+    Semver(#[from] semver::Error),
+}
+
+/// Why a downloaded archive could not be extracted.
+#[derive(Debug, thiserror::Error)]
+enum ExtractionError {
+    #[error("the archive is neither a zip nor a tar.gz file")]
+    UnknownFormat,
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error("could not read entry {index} of the archive: {source}")]
+    Entry {
+        index: usize,
+        source: zip::result::ZipError,
+    },
+    #[error("could not read the archive: {0}")]
+    Read(std::io::Error),
+    #[error("could not create directory {path:?}: {source}")]
+    CreateDir {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("could not create file {path:?}: {source}")]
+    CreateFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("could not write {name}: {source}")]
+    Write {
+        name: String,
+        source: std::io::Error,
+    },
+    /// The dependency's `path` leaves the archive
+    #[error("path '{0}' is not inside the repository")]
+    PathOutsideArchive(String),
+    #[error("the archive contains entries outside the install directory: {}", .0.join(", "))]
+    UnsafeEntries(Vec<String>),
+    /// Nothing was extracted from under the dependency's `path`
+    #[error("the archive has no files under {0}/")]
+    NothingUnder(String),
+    #[error("could not move the extracted files into {path:?}: {source}")]
+    Move {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Why a Composer requirement or an installed plugin can't be written to
+/// wdm.yml by `wdm import` or `wdm adopt`.
+#[derive(Debug, thiserror::Error)]
+enum ImportError {
+    #[error("it is not a wpackagist package")]
+    NotWpackagist,
+    #[error("alternative constraints (`||`) are not supported")]
+    AlternativeConstraints,
+    #[error("development branches are not supported")]
+    DevelopmentBranch,
+    #[error("`{0}` is not a constraint wdm understands")]
+    UnknownConstraint(String),
+    #[error("'{0}' is not on WordPress.org")]
+    NotOnWordpressOrg(String),
+    #[error("WordPress.org has no release of version {0}")]
+    NotReleased(String),
+    #[error(transparent)]
+    Source(#[from] SourceError),
+}
+
+/// Directories next to wdm.yml that WordPress commonly lives in.
+const WORDPRESS_DIR_CANDIDATES: [&str; 4] = [".", "public", "web", "wordpress"];
+
+/// Looks for a WordPress install (`wp-config.php` or `wp-content/`) in
+/// `project_dir` or one of the usual subdirectories, returning its path
+/// relative to `project_dir`.
+fn detect_wordpress_path(project_dir: &Path) -> Option<String> {
+    WORDPRESS_DIR_CANDIDATES
+        .iter()
+        .find(|candidate| {
+            let dir = project_dir.join(candidate);
+            dir.join("wp-config.php").is_file() || dir.join("wp-content").is_dir()
+        })
+        .map(|candidate| candidate.to_string())
+}
+
+/// Prints `question` to stderr and returns the trimmed line typed in reply.
+fn prompt(question: &str) -> std::io::Result<String> {
+    eprint!("{}", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Asks a yes/no `question`, defaulting to no.
+fn confirm(question: &str) -> std::io::Result<bool> {
+    let answer = prompt(&format!("{} [y/N] ", question))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Completes dependency names from the wdm.yml of the current project. Without
+/// a readable manifest there is nothing to suggest, and the shell falls back to
+/// its own completion.
+fn dependency_candidates() -> Vec<CompletionCandidate> {
+    let Ok(path) = find_manifest() else {
+        return Vec::new();
+    };
+    let Ok(config) = read_manifest(&path) else {
+        return Vec::new();
+    };
+    config
+        .selected_dependencies(true)
+        .into_iter()
+        .map(|dep| CompletionCandidate::new(&dep.name))
+        .collect()
+}
+
+/// The environment `--env` names, or else `WDM_ENV`.
+fn active_env(flag: Option<&str>) -> Option<String> {
+    match flag {
+        Some(env) => Some(env.to_string()),
+        None => env::var("WDM_ENV").ok().filter(|env| !env.is_empty()),
+    }
+}
+
+/// Narrows `dependencies` down to the ones named on the command line, comparing
+/// names the way `wdm add` normalizes them. No names selects everything.
+fn select_by_name<'a>(
+    dependencies: Vec<&'a Dependency>,
+    names: &[String],
+) -> Result<Vec<&'a Dependency>, WdmError> {
+    if names.is_empty() {
+        return Ok(dependencies);
+    }
+
+    let wanted: Vec<String> = names.iter().map(|name| normalize_name(name)).collect();
+    let unknown: Vec<String> = names
+        .iter()
+        .filter(|name| !dependencies.iter().any(|d| same_name(&d.name, name)))
+        .cloned()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(WdmError::UnknownDependencies {
+            names: unknown,
+            available: dependencies.iter().map(|d| d.name.clone()).collect(),
+        });
+    }
+    Ok(dependencies
+        .into_iter()
+        .filter(|d| wanted.contains(&normalize_name(&d.name)))
+        .collect())
+}
+
+/// Suggests the field a misspelled key in wdm.yml was probably meant to be,
+/// based on serde's "unknown field `x`, expected one of ..." message.
+fn suggest_field(message: &str) -> String {
+    let Some((unknown, expected)) = message
+        .split_once("unknown field `")
+        .and_then(|(_, rest)| rest.split_once('`'))
+    else {
+        return String::new();
+    };
+    let closest = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(|field| (edit_distance(unknown, field), field))
+        .min();
+    match closest {
+        Some((distance, field)) if distance <= 2.max(unknown.len() / 3) => {
+            format!("\n  hint: did you mean `{}`?", field)
+        }
+        _ => String::new(),
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Suggests a fix for the most common reasons a lookup or download of a
+/// dependency from `from` fails: a status that says its token or its name
+/// is wrong.
+fn hint(from: Source, error: &SourceError) -> &'static str {
+    let SourceError::Status(status) = error else {
+        return "";
+    };
+    match (status.as_u16(), from) {
+        (401 | 403, Source::Github | Source::Gitlab | Source::Bitbucket) => {
+            "\n  hint: check that the token named by token_env is set and can read the repository"
+        }
+        (401 | 403, Source::Url) => {
+            "\n  hint: check that the url in wdm.yml is right, and that the token named by token_env is set if the server needs one"
+        }
+        (404, Source::Github | Source::Gitlab | Source::Bitbucket) => {
+            "\n  hint: check the repository name, and set token_env if the repository is private"
+        }
+        (404, Source::WordpressOrg) => {
+            "\n  hint: check that the name is the slug the plugin or theme has on WordPress.org"
+        }
+        (404, Source::Url) => {
+            "\n  hint: check the url in wdm.yml; the archive may have moved or been taken down"
+        }
+        _ => "",
+    }
+}
+
+/// Suggests a fix for a failed resolution of a dependency from `from`: the
+/// hints of `hint`, and checking the version when no release matches it.
+fn resolution_hint(from: Source, error: &ResolveError) -> &'static str {
+    match (error, from) {
+        (ResolveError::Source(e), _) => hint(from, e),
+        (ResolveError::VersionNotFound(_) | ResolveError::NoMatch(_), Source::WordpressOrg) => {
+            "\n  hint: check the version in wdm.yml against the versions released on WordPress.org"
+        }
+        (ResolveError::VersionNotFound(_) | ResolveError::NoMatch(_), _) => {
+            "\n  hint: the tag may be missing; check the version in wdm.yml against the published tags"
+        }
+        _ => "",
+    }
+}
+
+/// Explains a hash mismatch and how to get past it when it is expected.
+fn describe_hash_mismatch(dep: &str, version: &str, url: Option<&str>) -> String {
+    // Vendor URLs are often overwritten in place, and tags can be force-moved
+    let cause = match url {
+        Some(url) => format!(
+            "WARNING: the archive at {} has changed since it was locked.",
+            url
+        ),
+        None => format!(
+            "WARNING: {} may have been re-tagged since it was locked.",
+            display_version(version)
+        ),
+    };
+    format!(
+        "{}\nLeaving the installed {} untouched. If the change is expected, run 'wdm install --insecure-skip-verify'.",
+        cause, dep
+    )
+}
+
+/// The manifest given with `--manifest-path`, if any.
+static MANIFEST_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Whether `manifest_conflicts` were already warned about (or are reported
+/// as problems by `wdm check --strict`), so reloading wdm.yml stays quiet.
+static MANIFEST_CONFLICTS_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Finds dependencies in wdm.yml that would install over each other: names
+/// that only differ in case, within or across the two sections, entries
+/// fetching the same repository (or URL), and entries whose install
+/// directories coincide. Each conflict names both entries.
+fn manifest_conflicts(config: &Config, wordpress_path: &Path) -> Vec<CheckProblem> {
+    let entries: Vec<(&Dependency, &str)> = config
+        .dependencies
+        .iter()
+        .map(|d| (d, "dependencies"))
+        .chain(
+            config
+                .dev_dependencies
+                .iter()
+                .map(|d| (d, "dev_dependencies")),
+        )
+        .collect();
+    let mut conflicts = Vec::new();
+    for (i, (first, first_section)) in entries.iter().enumerate() {
+        for (second, second_section) in &entries[i + 1..] {
+            let both = if first_section == second_section {
+                format!(
+                    "'{}' and '{}' in {}",
+                    first.name, second.name, first_section
+                )
+            } else {
+                format!(
+                    "'{}' in {} and '{}' in {}",
+                    first.name, first_section, second.name, second_section
+                )
+            };
+            let message = if same_name(&first.name, &second.name) {
+                format!("{} are the same dependency; remove one of them", both)
+            } else if first.source.unwrap_or_default() != Source::WordpressOrg
+                && first.source == second.source
+                && first.path == second.path
+                && first.origin().eq_ignore_ascii_case(&second.origin())
+            {
+                let target = match &first.url {
+                    Some(url) if first.repo.is_empty() => strip_credentials(url),
+                    _ => first.repo.clone(),
+                };
+                format!("{} both install {}", both, target)
+            } else if first.install_dir(wordpress_path) == second.install_dir(wordpress_path) {
+                format!(
+                    "{} are both installed into {:?}",
+                    both,
+                    first.install_dir(wordpress_path)
+                )
+            } else {
+                continue;
+            };
+            conflicts.push(CheckProblem::new(
+                ProblemKind::Conflict,
+                &second.name,
+                message,
+            ));
+        }
+    }
+    conflicts
+}
+
+/// Finds wdm.yml in the current directory or the closest parent that has one,
+/// unless `--manifest-path` names it explicitly.
+///
+/// The path is kept relative to the current directory so that paths derived
+/// from it read the same as before when wdm runs next to the manifest.
+fn find_manifest() -> Result<PathBuf, WdmError> {
+    if let Some(path) = MANIFEST_PATH.get() {
+        let reason = if !path.exists() {
+            "does not exist"
+        } else if !path.is_file() {
+            "is not a file"
+        } else {
+            return Ok(path.clone());
+        };
+        return Err(WdmError::InvalidManifestPath {
+            path: path.clone(),
+            reason,
+        });
+    }
+
+    let mut relative = PathBuf::new();
+    for dir in env::current_dir()?.ancestors() {
+        if dir.join("wdm.yml").is_file() {
+            return Ok(relative.join("wdm.yml"));
+        }
+        relative.push("..");
+    }
+    Err(WdmError::ManifestNotFound)
+}
+
+/// Parses the manifest at `path`, merged with the manifests its `extends` names,
+/// and expands the environment variables in its values.
+fn read_manifest(path: &Path) -> Result<Config, WdmError> {
+    let mut config = parse_manifest(path)?;
+    config.interpolate()?;
+    Ok(config)
+}
+
+/// Parses the manifest at `path`, merged with the manifests its `extends` names.
+fn parse_manifest(path: &Path) -> Result<Config, WdmError> {
+    let parse_error = |source| WdmError::ManifestParse {
+        path: path.to_path_buf(),
+        source,
+    };
+    let contents = fs::read_to_string(path)?;
+    let local = serde_yaml::from_str::<serde_yaml::Mapping>(&contents)
+        .ok()
+        .filter(|local| local.contains_key("extends"));
+    let Some(mut local) = local else {
+        return serde_yaml::from_str(&contents).map_err(parse_error);
+    };
+    let config = parse_partial_manifest(&mut local).map_err(parse_error)?;
+    let Some(extends) = &config.extends else {
+        return Ok(config);
+    };
+
+    let location = path.display().to_string();
+    let mut chain = vec![(
+        fs::canonicalize(path)?.display().to_string(),
+        location.clone(),
+    )];
+    let (base, origins) = read_base(extends, &location, &mut chain)?;
+    let mut config: Config =
+        serde_yaml::from_value(serde_yaml::Value::Mapping(merge_manifests(&base, &local)))
+            .map_err(parse_error)?;
+    let local_keys = ["config", "hooks"]
+        .into_iter()
+        .flat_map(|section| {
+            let keys = local.get(section).and_then(|values| values.as_mapping());
+            keys.into_iter()
+                .flat_map(|keys| keys.keys())
+                .map(move |key| (section.to_string(), key.clone()))
+        })
+        .collect();
+    config.inherited = Some(Inherited {
+        base,
+        origins,
+        local_keys,
+        local_names: listed_names(&local),
+    });
+    Ok(config)
+}
+
+/// Reads the manifest that `extends` names, relative to the manifest at `from`,
+/// merged with the manifests it extends in turn. `chain` holds the manifests
+/// being read, as `(identity, location)`, so a cycle is caught instead of
+/// recursing forever.
+///
+/// Returns the merged base, normalized the way wdm writes manifests, and the
+/// manifest each of its dependencies came from.
+fn read_base(
+    extends: &str,
+    from: &str,
+    chain: &mut Vec<(String, String)>,
+) -> Result<(serde_yaml::Mapping, Vec<(String, String)>), WdmError> {
+    let location = extends_location(extends, from);
+    let failed = |reason: String| WdmError::ExtendsFailed {
+        location: location.clone(),
+        from: from.to_string(),
+        reason,
+    };
+    let (identity, contents) = if is_url(&location) {
+        (
+            location.clone(),
+            download_manifest(&location).map_err(|e| failed(e.to_string()))?,
+        )
+    } else {
+        let identity = fs::canonicalize(&location).map_err(|e| failed(e.to_string()))?;
+        let contents = fs::read_to_string(&identity).map_err(|e| failed(e.to_string()))?;
+        (identity.display().to_string(), contents)
+    };
+    if let Some(start) = chain.iter().position(|(seen, _)| *seen == identity) {
+        let mut cycle: Vec<String> = chain[start..].iter().map(|(_, l)| l.clone()).collect();
+        cycle.push(location);
+        return Err(WdmError::ExtendsCycle(cycle));
+    }
+
+    let parse_error = |source| WdmError::ManifestParse {
+        path: PathBuf::from(&location),
+        source,
+    };
+    let mut value: serde_yaml::Mapping = serde_yaml::from_str(&contents).map_err(parse_error)?;
+    let config = parse_partial_manifest(&mut value).map_err(parse_error)?;
+    let serde_yaml::Value::Mapping(mut own) = serde_yaml::to_value(&config)? else {
+        unreachable!("a manifest serializes to a mapping");
+    };
+    // Unset keys would otherwise hide the values of the manifests further down
+    if let Some(serde_yaml::Value::Mapping(values)) = own.get_mut("config") {
+        values.retain(|_, value| !value.is_null());
+    }
+    own.remove("extends");
+    let own_origins = listed_names(&own)
+        .into_iter()
+        .map(|name| (name, location.clone()));
+
+    let Some(extends) = &config.extends else {
+        return Ok((own, own_origins.collect()));
+    };
+    chain.push((identity, location.clone()));
+    let (base, origins) = read_base(extends, &location, chain)?;
+    chain.pop();
+    let mut merged = merge_manifests(&base, &own);
+    merged.remove("extends");
+    let names = listed_names(&own);
+    let origins = origins
+        .into_iter()
+        .filter(|(name, _)| !names.iter().any(|n| same_name(n, name)))
+        .chain(own_origins)
+        .collect();
+    Ok((merged, origins))
+}
+
+/// Parses a manifest that extends another one, or is extended, and so needs
+/// neither a config section nor dependencies of its own. The missing sections
+/// are added to `manifest` as empty ones.
+fn parse_partial_manifest(manifest: &mut serde_yaml::Mapping) -> Result<Config, serde_yaml::Error> {
+    for (key, empty) in [
+        ("config", serde_yaml::Value::Mapping(Default::default())),
+        ("dependencies", serde_yaml::Value::Sequence(Vec::new())),
+    ] {
+        if !manifest.contains_key(key) {
+            manifest.insert(key.into(), empty);
+        }
+    }
+    serde_yaml::from_value(serde_yaml::Value::Mapping(manifest.clone()))
+}
+
+/// Where the manifest named by `extends` is, resolving a relative path against
+/// the manifest at `from`.
+fn extends_location(extends: &str, from: &str) -> String {
+    if is_url(extends) {
+        return extends.to_string();
+    }
+    if is_url(from) {
+        return reqwest::Url::parse(from)
+            .and_then(|url| url.join(extends))
+            .map_or_else(|_| extends.to_string(), |url| url.to_string());
+    }
+    Path::new(from)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(extends)
+        .display()
+        .to_string()
+}
+
+/// Whether `location` is an http(s) URL rather than a path.
+fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// Downloads the manifest at `url`.
+///
+/// This happens before wdm.yml's HTTP settings are known, so it uses a client
+/// of its own rather than building the shared one too early.
+fn download_manifest(url: &str) -> Result<String, SourceError> {
+    let client = HttpContext::new(http_settings(), proxy_settings(), tls_settings()).client;
+    let response = send_with_retries(url, || client.get(url))?;
+    if !response.status().is_success() {
+        return Err(SourceError::Status(response.status()));
+    }
+    response.text().map_err(|e| SourceError::Body(e.into()))
+}
+
+/// Merges a manifest over the base it extends: its config keys and hooks take
+/// precedence, and its dependencies replace the base's ones with the same name.
+fn merge_manifests(base: &serde_yaml::Mapping, local: &serde_yaml::Mapping) -> serde_yaml::Mapping {
+    let mut merged = local.clone();
+    for section in ["config", "hooks"] {
+        let mut values = base
+            .get(section)
+            .and_then(|values| values.as_mapping())
+            .cloned()
+            .unwrap_or_default();
+        if let Some(own) = local.get(section).and_then(|values| values.as_mapping()) {
+            for (key, value) in own {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+        merged.insert(section.into(), serde_yaml::Value::Mapping(values));
+    }
+    let names = listed_names(local);
+    for section in ["dependencies", "dev_dependencies"] {
+        let inherited = base
+            .get(section)
+            .and_then(|deps| deps.as_sequence())
+            .into_iter()
+            .flatten()
+            .filter(|dep| !names.iter().any(|n| same_name(n, dependency_name(dep))));
+        let own = local
+            .get(section)
+            .and_then(|deps| deps.as_sequence())
+            .into_iter()
+            .flatten();
+        let deps: Vec<serde_yaml::Value> = inherited.chain(own).cloned().collect();
+        merged.insert(section.into(), serde_yaml::Value::Sequence(deps));
+    }
+    merged
+}
+
+/// The names of the dependencies a parsed manifest lists, in either section.
+fn listed_names(manifest: &serde_yaml::Mapping) -> Vec<String> {
+    ["dependencies", "dev_dependencies"]
+        .into_iter()
+        .filter_map(|section| manifest.get(section)?.as_sequence())
+        .flatten()
+        .map(|dep| dependency_name(dep).to_string())
+        .collect()
+}
+
+/// The projects listed in wdm-workspace.yml.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Workspace {
+    /// Directories relative to wdm-workspace.yml, each holding a wdm.yml
+    members: Vec<String>,
+}
+
+/// How the command went in one member of a workspace.
+#[derive(Serialize, Debug)]
+struct WorkspaceMember {
+    member: String,
+    status: MemberStatus,
+    /// The exit status of wdm in the member, unless it didn't run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    /// What wdm printed with `--json`, or why it didn't run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum MemberStatus {
+    Ok,
+    Failed,
+    /// Not run, because an earlier member failed and `--fail-fast` was passed
+    Skipped,
+}
+
+impl std::fmt::Display for MemberStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MemberStatus::Ok => "ok",
+            MemberStatus::Failed => "failed",
+            MemberStatus::Skipped => "skipped",
+        })
+    }
+}
+
+/// Finds wdm-workspace.yml in the current directory or the closest parent that has one.
+fn find_workspace() -> Result<PathBuf, WdmError> {
+    let mut relative = PathBuf::new();
+    for dir in env::current_dir()?.ancestors() {
+        if dir.join("wdm-workspace.yml").is_file() {
+            return Ok(relative.join("wdm-workspace.yml"));
+        }
+        relative.push("..");
+    }
+    Err(WdmError::WorkspaceNotFound)
+}
+
+/// Runs the same command in every member of the workspace, one after another.
+///
+/// Each member gets a wdm of its own, started in its directory, so it uses its
+/// own wdm.yml, wdm.lock, cache and project lock exactly as if the command had
+/// been run there. A member that fails doesn't stop the rest unless `fail_fast`.
+fn run_workspace(fail_fast: bool) -> Result<(), WdmError> {
+    if MANIFEST_PATH.get().is_some() {
+        return Err(WdmError::InvalidArgument(
+            "--workspace runs in each member's own directory, so it can't be combined with --manifest-path".to_string(),
+        ));
+    }
+    let path = find_workspace()?;
+    let workspace: Workspace =
+        serde_yaml::from_str(&fs::read_to_string(&path)?).map_err(|source| {
+            WdmError::ManifestParse {
+                path: path.clone(),
+                source,
+            }
+        })?;
+    let root_dir = path.parent().unwrap_or(Path::new("."));
+    let exe = env::current_exe()?;
+    // Everything else on the command line is passed on to each member
+    let args: Vec<std::ffi::OsString> = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--workspace" && arg != "--fail-fast")
+        .collect();
+
+    let mut members = Vec::new();
+    for member in &workspace.members {
+        let dir = root_dir.join(member);
+        let failed = members
+            .iter()
+            .any(|m: &WorkspaceMember| m.status == MemberStatus::Failed);
+        if fail_fast && failed {
+            members.push(WorkspaceMember {
+                member: member.clone(),
+                status: MemberStatus::Skipped,
+                exit_code: None,
+                output: None,
+            });
+            continue;
+        }
+        if !dir.join("wdm.yml").is_file() {
+            let message = format!("{} has no wdm.yml", dir.display());
+            warning!("{}", message);
+            members.push(WorkspaceMember {
+                member: member.clone(),
+                status: MemberStatus::Failed,
+                exit_code: None,
+                output: Some(json!(message)),
+            });
+            continue;
+        }
+
+        progress!("==> {}", member);
+        let mut command = Command::new(&exe);
+        command.args(&args).current_dir(&dir);
+        let (code, output) = if json_output() {
+            let output = command.stderr(Stdio::inherit()).output()?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let printed = serde_json::from_str(&stdout).unwrap_or_else(|_| json!(stdout.trim()));
+            (output.status.code(), Some(printed))
+        } else {
+            (command.status()?.code(), None)
+        };
+        members.push(WorkspaceMember {
+            member: member.clone(),
+            status: if code == Some(0) {
+                MemberStatus::Ok
+            } else {
+                MemberStatus::Failed
+            },
+            exit_code: code,
+            output,
+        });
+    }
+
+    let count = |status| members.iter().filter(|m| m.status == status).count();
+    let failed = count(MemberStatus::Failed);
+    if json_output() {
+        print_json(&members);
+    } else {
+        let rows: Vec<Vec<String>> = members
+            .iter()
+            .map(|m| {
+                let status = match m.exit_code {
+                    Some(code) if m.status == MemberStatus::Failed => {
+                        format!("failed (exit {})", code)
+                    }
+                    _ => m.status.to_string(),
+                };
+                vec![m.member.clone(), status]
+            })
+            .collect();
+        print_table(&["Member", "Status"], &rows);
+    }
+    let skipped = count(MemberStatus::Skipped);
+    summary!(
+        "{} succeeded, {} failed{}",
+        count(MemberStatus::Ok),
+        failed,
+        if skipped > 0 {
+            format!(", {} skipped", skipped)
+        } else {
+            String::new()
+        }
+    );
+    if failed > 0 {
+        return Err(WdmError::AlreadyReported);
+    }
+    Ok(())
+}
+
+/// Reads and parses the wdm.yml found by `find_manifest`.
+fn load_config() -> Result<Config, WdmError> {
+    let path = find_manifest()?;
+    let config = read_manifest(&path)?;
+
+    let (wordpress_path, _) = resolve_wordpress_path(&config.config, &manifest_root()?, None);
+    if !MANIFEST_CONFLICTS_REPORTED.swap(true, Ordering::Relaxed) {
+        for conflict in manifest_conflicts(&config, &wordpress_path) {
+            warning!("{}", conflict.message);
+        }
+    }
+    for dep in config.selected_dependencies(true) {
+        check_install_path(dep, &wordpress_path)?;
+        check_version_requirement(&dep.version, dep.source.unwrap_or_default()).map_err(
+            |source| WdmError::InvalidVersion {
+                dep: dep.name.clone(),
+                version: dep.version.clone(),
+                path: Some(path.clone()),
+                source,
+            },
+        )?;
+        if let Some(integrity) = &dep.integrity {
+            if integrity_digest(integrity).is_none() {
+                return Err(WdmError::InvalidIntegrity {
+                    dep: dep.name.clone(),
+                    integrity: integrity.clone(),
+                });
+            }
+        }
+    }
+    let _ = DEPENDENCY_DEFAULTS.set(DependencyDefaults {
+        github_host: config.config.github_host.clone(),
+        token_env: config.config.default_token_env.clone(),
+        resolver: config.config.resolver,
+    });
+    Ok(config)
+}
+
+/// Builds the lock entry for `dep` installed at `version`.
+///
+/// An entry that already locked the same version and archive keeps its
+/// `locked_at` (and `resolved_url` when the archive came from the cache), so
+/// reinstalling leaves wdm.lock untouched.
+fn lock_entry(
+    ctx: &InstallContext,
+    dep: &Dependency,
+    version: &str,
+    hash: String,
+    resolved_url: Option<String>,
+    files: Vec<String>,
+    headers: PackageHeaders,
+) -> LockedDependency {
+    let previous = ctx
+        .lockfile
+        .dependencies
+        .iter()
+        .find(|d| same_name(&d.name, &dep.name) && d.version == version && d.hash == hash);
+    LockedDependency {
+        name: dep.name.clone(),
+        version: version.to_string(),
+        repo: dep.repo.clone(),
+        hash,
+        kind: dep.kind.unwrap_or_default(),
+        source: dep.source.unwrap_or_default(),
+        url: dep.url.clone(),
+        host: dep.host.clone(),
+        asset: dep.asset.clone(),
+        path: dep.path.clone(),
+        install_path: dep.install_path.clone(),
+        dev: ctx.config.is_dev(&dep.name),
+        resolved_url: resolved_url.or_else(|| previous.and_then(|d| d.resolved_url.clone())),
+        locked_at: match previous {
+            Some(previous) => previous.locked_at.clone(),
+            None => Some(format_timestamp(SystemTime::now())),
+        },
+        header_version: headers.version,
+        requires_wp: headers.requires_wp,
+        requires_php: headers.requires_php,
+        requires_plugins: headers.requires_plugins,
+        files,
+    }
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp such as `2024-05-01T12:30:00Z`.
+fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Convert days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Writes wdm.yml with its dependencies sorted by name, so the same edits made on
+/// different machines produce the same file.
+///
+/// Whatever the manifest inherited through `extends` and didn't change is left
+/// out, so only the project's own file is ever written, and values that had
+/// environment variables expanded are written as they were.
+fn write_manifest(path: &Path, config: &mut Config) -> Result<(), WdmError> {
+    config
+        .dependencies
+        .sort_by(|a, b| compare_names(&a.name, &b.name));
+    config
+        .dev_dependencies
+        .sort_by(|a, b| compare_names(&a.name, &b.name));
+    let contents = if config.inherited.is_none() && config.interpolated.is_empty() {
+        serde_yaml::to_string(config)?
+    } else {
+        let serde_yaml::Value::Mapping(mut manifest) = serde_yaml::to_value(&*config)? else {
+            unreachable!("a manifest serializes to a mapping");
+        };
+        for interpolated in &config.interpolated {
+            interpolated.restore(&mut manifest);
+        }
+        if let Some(inherited) = &config.inherited {
+            inherited.strip(&mut manifest);
+        }
+        serde_yaml::to_string(&manifest)?
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Writes wdm.lock with its entries sorted by name, leaving the file alone when
+/// its contents would not change. Returns whether it was written.
+fn write_lockfile(path: &Path, lockfile: &mut Lockfile) -> Result<bool, WdmError> {
+    lockfile
+        .dependencies
+        .sort_by(|a, b| compare_names(&a.name, &b.name));
+    let contents = serde_yaml::to_string(lockfile)?;
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// Reports the outcome of `write_lockfile`.
+fn report_lockfile_write(path: &Path, changed: bool) {
+    if changed {
+        progress!("Updated lockfile at {:?}", path);
+    } else {
+        progress!("Lockfile at {:?} is unchanged", path);
+    }
+}
+
+/// Reads and parses wdm.lock, or returns an empty lockfile if there is none yet.
+fn load_lockfile() -> Result<Lockfile, WdmError> {
+    let path = manifest_root()?.join("wdm.lock");
+    if !path.exists() {
+        return Ok(Lockfile::new());
+    }
+    parse_lockfile(&path)
+}
+
+/// Parses the lockfile at `path`, upgrading older formats to `LOCKFILE_VERSION`
+/// in memory. Lockfiles from a newer wdm are refused rather than being
+/// rewritten without the fields this build doesn't know about.
+fn parse_lockfile(path: &Path) -> Result<Lockfile, WdmError> {
+    #[derive(Deserialize)]
+    struct Header {
+        #[serde(default = "lockfile_v1")]
+        version: u32,
+    }
+
+    let parse_error = |source| WdmError::ManifestParse {
+        path: path.to_path_buf(),
+        source,
+    };
+    let contents = fs::read_to_string(path)?;
+    let header: Header = serde_yaml::from_str(&contents).map_err(parse_error)?;
+    if header.version > LOCKFILE_VERSION {
+        return Err(WdmError::UnsupportedLockfile {
+            path: path.to_path_buf(),
+            version: header.version,
+        });
+    }
+
+    let mut lockfile: Lockfile = serde_yaml::from_str(&contents).map_err(parse_error)?;
+    if lockfile.version < LOCKFILE_VERSION {
+        // Version 2 only added optional fields, so version 1 entries carry over as they are
+        verbose!(
+            "Upgrading {} from format version {} to {}",
+            path.display(),
+            lockfile.version,
+            LOCKFILE_VERSION
+        );
+        lockfile.version = LOCKFILE_VERSION;
+    }
+    Ok(lockfile)
+}
+
+fn main() {
+    // Answers completion requests from the shell (`COMPLETE=bash wdm`) and exits
+    CompleteEnv::with_factory(Cli::command).complete();
+
+    let cli = Cli::parse();
+    JSON_OUTPUT.store(cli.json, Ordering::Relaxed);
+    let verbosity = if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    if let Some(path) = &cli.manifest_path {
+        let _ = MANIFEST_PATH.set(path.clone());
+    }
+    NO_HOOKS.store(cli.no_hooks, Ordering::Relaxed);
+    REFRESH_TAGS.store(cli.refresh, Ordering::Relaxed);
+    let _ = LOCK_WAIT.set(if cli.no_wait {
+        None
+    } else {
+        Some(Duration::from_secs(cli.lock_timeout))
+    });
+    let result = run(&cli);
+    report_tag_cache();
+    if let Err(WdmError::AlreadyReported) = result {
+        std::process::exit(1);
+    }
+    if let Err(e) = result {
+        if json_output() {
+            error!(
+                "{}",
+                json!({
+                    "error": {
+                        "kind": e.kind(),
+                        "dependency": e.dependency(),
+                        "message": redact(&e.to_string()),
+                    }
+                })
+            );
+        } else {
+            error!("{}", e);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// How long to wait for another wdm's project lock, or `None` for `--no-wait`.
+static LOCK_WAIT: OnceLock<Option<Duration>> = OnceLock::new();
+
+/// An advisory lock on `.wdm-cache/.lock`, held until it is dropped by
+/// commands that change wdm.yml, wdm.lock, or wp-content.
+struct ProjectLock {
+    _file: fs::File,
+    /// Whether another wdm held the lock first, and may have changed wdm.yml
+    waited: bool,
+}
+
+/// Takes the project lock of the project in `root_dir`, waiting for another
+/// wdm to release it unless `--no-wait` was passed.
+///
+/// The holder writes its PID into the lock file so whoever is waiting can say
+/// which process it is waiting for. The operating system releases the lock
+/// when the process exits, however it exits.
+fn lock_project(root_dir: &Path) -> Result<ProjectLock, WdmError> {
+    let dir = root_dir.join(".wdm-cache");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(".lock");
+    // Not truncated on open: until the lock is ours, the PID in it is the holder's
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+
+    let wait = LOCK_WAIT.get().copied().unwrap_or(None);
+    let started = Instant::now();
+    let mut announced = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => break,
+            Err(fs::TryLockError::WouldBlock) => {}
+            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+        let pid = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| contents.trim().parse().ok());
+        match wait {
+            None => return Err(WdmError::ProjectLocked { pid, waited: None }),
+            Some(wait) if started.elapsed() >= wait => {
+                return Err(WdmError::ProjectLocked {
+                    pid,
+                    waited: Some(wait),
+                })
+            }
+            Some(_) => {}
+        }
+        if !announced {
+            progress!(
+                "Waiting for another wdm ({}) to finish...",
+                describe_lock_holder(pid)
+            );
+            announced = true;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    Ok(ProjectLock {
+        _file: file,
+        waited: announced,
+    })
+}
+
+/// Loads wdm.yml and takes the project lock.
+///
+/// The manifest is parsed first so a broken one fails without creating
+/// `.wdm-cache`, and read again if the wdm that held the lock may have changed it.
+fn load_config_locked() -> Result<(Config, ProjectLock), WdmError> {
+    let config = load_config()?;
+    let lock = lock_project(&manifest_root()?)?;
+    let config = if lock.waited { load_config()? } else { config };
+    Ok((config, lock))
+}
+
+/// Names the process holding the project lock, e.g. `PID 4242`.
+fn describe_lock_holder(pid: Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!("PID {}", pid),
+        None => "unknown PID".to_string(),
+    }
+}
+
+/// Whether `--json` was passed.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// How much wdm prints, from `--quiet` and `--verbose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Prints a line on stderr or stdout, moving any progress bars out of its way.
+fn print_line(to_stderr: bool, line: &str) {
+    let print = || {
+        if to_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+    match PROGRESS_BARS.get() {
+        Some(bars) => bars.suspend(print),
+        None => print(),
+    }
+}
+
+/// The progress bars of every download and extraction under way, drawn on
+/// stdout once the first one starts.
+static PROGRESS_BARS: OnceLock<indicatif::MultiProgress> = OnceLock::new();
+
+/// How often progress is logged when stdout isn't a terminal to draw bars on.
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a `Progress` counts.
+#[derive(Debug, Clone, Copy)]
+enum ProgressUnit {
+    Bytes,
+    Files,
+}
+
+/// Shows how far a download or extraction has got: a bar on a terminal, a
+/// line every few seconds otherwise (such as in CI), and nothing with
+/// `--quiet` or `--json`. The bar is cleared when it is dropped.
+struct Progress {
+    bar: Option<indicatif::ProgressBar>,
+    /// What is being done, e.g. `Downloading akismet`
+    label: String,
+    unit: ProgressUnit,
+    total: Option<u64>,
+    done: u64,
+    last_logged: Instant,
+    enabled: bool,
+}
+
+impl Progress {
+    fn start(label: String, total: Option<u64>, unit: ProgressUnit) -> Progress {
+        let enabled = verbosity() >= Verbosity::Normal && !json_output();
+        let bar = (enabled && std::io::stdout().is_terminal()).then(|| {
+            let bars = PROGRESS_BARS.get_or_init(|| {
+                indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::stdout())
+            });
+            let template = match (unit, total) {
+                (ProgressUnit::Bytes, Some(_)) => {
+                    "{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} {eta}"
+                }
+                (ProgressUnit::Bytes, None) => "{msg} {spinner} {bytes} {bytes_per_sec}",
+                (ProgressUnit::Files, _) => "{msg} [{bar:30}] {pos}/{len} files",
+            };
+            let bar = match total {
+                Some(total) => indicatif::ProgressBar::new(total),
+                None => indicatif::ProgressBar::new_spinner(),
+            };
+            bar.set_style(
+                indicatif::ProgressStyle::with_template(template)
+                    .expect("progress templates are valid")
+                    .progress_chars("=> "),
+            );
+            bar.set_message(label.clone());
+            bars.add(bar)
+        });
+        Progress {
+            bar,
+            label,
+            unit,
+            total,
+            done: 0,
+            last_logged: Instant::now(),
+            enabled,
+        }
+    }
+
+    fn advance(&mut self, amount: u64) {
+        self.done += amount;
+        if let Some(bar) = &self.bar {
+            bar.inc(amount);
+        } else if self.enabled && self.last_logged.elapsed() >= PROGRESS_LOG_INTERVAL {
+            self.last_logged = Instant::now();
+            let count = |n| match self.unit {
+                ProgressUnit::Bytes => format_size(n),
+                ProgressUnit::Files => format!("{} files", n),
+            };
+            match self.total {
+                Some(total) => {
+                    progress!("{}: {} of {}", self.label, count(self.done), count(total))
+                }
+                None => progress!("{}: {}", self.label, count(self.done)),
+            }
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            if let Some(bars) = PROGRESS_BARS.get() {
+                bars.remove(bar);
+            }
+        }
+    }
+}
+
+/// Describes how much was installed and how long it took, e.g. `1.5 MB in 2.3s`.
+fn describe_transfer(size: u64, elapsed: Duration) -> String {
+    format!("{} in {:.1}s", format_size(size), elapsed.as_secs_f64())
+}
+
+/// Prints the `--json` result of a command on stdout.
+fn print_json(value: &impl Serialize) {
+    println!(
+        "{}",
+        redact(&serde_json::to_string_pretty(value).expect("JSON output is always serializable"))
+    );
+}
+
+/// What happened to one dependency during `install`, `update` or `add`.
+#[derive(Serialize, Debug)]
+struct InstallReport {
+    name: String,
+    version: Option<String>,
+    status: InstallStatus,
+    path: PathBuf,
+    hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum InstallStatus {
+    Installed,
+    Skipped,
+    UpToDate,
+    Failed,
+    /// Uninstalled by `install --prune`
+    Pruned,
+}
+
+impl InstallReport {
+    fn new(dep: &Dependency, wordpress_path: &Path, status: InstallStatus) -> Self {
+        InstallReport {
+            name: dep.name.clone(),
+            version: None,
+            status,
+            path: dep.install_dir(wordpress_path),
+            hash: None,
+            error: None,
+        }
+    }
+
+    /// A dependency that was left as it is, described by its lock entry.
+    fn unchanged(
+        dep: &Dependency,
+        wordpress_path: &Path,
+        lockfile: &Lockfile,
+        status: InstallStatus,
+    ) -> Self {
+        let locked = lockfile
+            .dependencies
+            .iter()
+            .find(|d| same_name(&d.name, &dep.name));
+        InstallReport {
+            version: locked.map(|d| d.version.clone()),
+            hash: locked.map(|d| d.hash.clone()),
+            ..InstallReport::new(dep, wordpress_path, status)
+        }
+    }
+
+    /// A dependency `install --prune` uninstalled.
+    fn pruned(pruned: &Prunable) -> Self {
+        InstallReport {
+            name: pruned.name.clone(),
+            version: Some(pruned.version.clone()),
+            status: InstallStatus::Pruned,
+            path: pruned.path.clone(),
+            hash: Some(pruned.hash.clone()),
+            error: None,
+        }
+    }
+
+    fn installed(dep: &Dependency, wordpress_path: &Path, version: &str, hash: &str) -> Self {
+        InstallReport {
+            version: Some(version.to_string()),
+            hash: Some(hash.to_string()),
+            ..InstallReport::new(dep, wordpress_path, InstallStatus::Installed)
+        }
+    }
+
+    fn failed(dep: &Dependency, wordpress_path: &Path, error: &WdmError) -> Self {
+        InstallReport {
+            error: Some(error.to_string()),
+            ..InstallReport::new(dep, wordpress_path, InstallStatus::Failed)
+        }
+    }
+}
+
+/// One way wdm.yml, wdm.lock, and the install disagree, as reported by `wdm check`.
+#[derive(Serialize, Debug)]
+struct CheckProblem {
+    kind: ProblemKind,
+    name: String,
+    message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum ProblemKind {
+    /// In wdm.yml but not in wdm.lock
+    NotLocked,
+    /// The locked version no longer satisfies wdm.yml
+    LockOutdated,
+    /// Locked as a dev dependency but listed as a production one, or the other way around
+    WrongSection,
+    /// In wdm.lock but not in wdm.yml
+    Orphaned,
+    NotInstalled,
+    /// Installed without wdm's marker, or with one for another dependency
+    Unmanaged,
+    /// The marker records a different version or archive than wdm.lock
+    MarkerMismatch,
+    NotCached,
+    CacheMismatch,
+    /// Two entries in wdm.yml that would install over each other
+    Conflict,
+    /// wdm.yml pins an integrity that wdm.lock's hash doesn't match
+    IntegrityMismatch,
+    /// A plugin in its `Requires Plugins` header is not installed
+    MissingRequirement,
+}
+
+impl CheckProblem {
+    fn new(kind: ProblemKind, name: &str, message: String) -> Self {
+        CheckProblem {
+            kind,
+            name: name.to_string(),
+            message,
+        }
+    }
+}
+
+/// Finds the `dependencies` whose lock entry is missing or no longer matches
+/// wdm.yml. Shared by `install --frozen` and `wdm check`.
+fn lock_problems(
+    config: &Config,
+    dependencies: &[&Dependency],
+    lockfile: &Lockfile,
+) -> Vec<CheckProblem> {
+    let mut problems = Vec::new();
+    for dep in dependencies {
+        let problem = match lockfile
+            .dependencies
+            .iter()
+            .find(|d| same_name(&d.name, &dep.name))
+        {
+            None => CheckProblem::new(
+                ProblemKind::NotLocked,
+                &dep.name,
+                format!("Dependency '{}' has no entry in wdm.lock", dep.name),
+            ),
+            Some(locked) if find_locked(lockfile, dep).is_none() => CheckProblem::new(
+                ProblemKind::LockOutdated,
+                &dep.name,
+                format!(
+                    "Locked version {} of '{}' does not satisfy '{}' from {}",
+                    locked.version, dep.name, dep.version, dep.repo
+                ),
+            ),
+            Some(locked) if locked.dev != config.is_dev(&dep.name) => CheckProblem::new(
+                ProblemKind::WrongSection,
+                &dep.name,
+                format!(
+                    "Dependency '{}' is locked as a {} dependency",
+                    dep.name,
+                    if locked.dev { "dev" } else { "production" }
+                ),
+            ),
+            Some(_) => continue,
+        };
+        problems.push(problem);
+    }
+    problems
+}
+
+/// Reports lock entries for dependencies that are no longer in wdm.yml.
+fn orphaned_lock_entries(dependencies: &[&Dependency], lockfile: &Lockfile) -> Vec<CheckProblem> {
+    lockfile
+        .dependencies
+        .iter()
+        .filter(|locked| {
+            !dependencies
+                .iter()
+                .any(|d| same_name(&d.name, &locked.name))
+        })
+        .map(|locked| {
+            CheckProblem::new(
+                ProblemKind::Orphaned,
+                &locked.name,
+                format!(
+                    "wdm.lock has an entry for '{}', which is not in wdm.yml",
+                    locked.name
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Prints the problems found by `wdm check` or `wdm lock --check`, exiting
+/// non-zero if there are any, or `ok` if there are none.
+fn report_problems(problems: &[CheckProblem], ok: &str) -> Result<(), WdmError> {
+    if json_output() {
+        print_json(&problems);
+    } else {
+        for problem in problems {
+            error!("{}", problem.message);
+        }
+    }
+    if !problems.is_empty() {
+        let noun = if problems.len() == 1 {
+            "problem"
+        } else {
+            "problems"
+        };
+        summary!("Found {} {}", problems.len(), noun);
+        return Err(WdmError::AlreadyReported);
+    }
+    summary!("{}", ok);
+    Ok(())
+}
+
+/// Deletes what wdm installed for `name` in `plugin_install_dir`: the
+/// `installed_files` recorded in wdm.lock, or the whole directory for entries
+/// from before files were recorded, and a mu-plugin's loader. Returns the
+/// paths that are gone.
+fn uninstall(
+    name: &str,
+    kind: Option<DependencyType>,
+    plugin_install_dir: &Path,
+    installed_files: &[String],
+) -> Result<Vec<PathBuf>, WdmError> {
+    let mut removed = Vec::new();
+    if plugin_install_dir.exists() && !installed_files.is_empty() {
+        match fs::remove_file(plugin_install_dir.join(INSTALL_MARKER)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        // Keep whatever was added next to the plugin's own files
+        let leftovers = remove_installed_files(plugin_install_dir, installed_files)?;
+        if leftovers.is_empty() {
+            progress!("Removed {:?}", plugin_install_dir);
+            removed.push(plugin_install_dir.to_path_buf());
+        } else {
+            progress!(
+                "Removed the files wdm installed in {:?}",
+                plugin_install_dir
+            );
+            warning!(
+                "Kept {:?} because wdm did not install these files: {}",
+                plugin_install_dir,
+                leftovers.join(", ")
+            );
+        }
+    } else if plugin_install_dir.exists() {
+        fs::remove_dir_all(plugin_install_dir)?;
+        progress!("Removed {:?}", plugin_install_dir);
+        removed.push(plugin_install_dir.to_path_buf());
+    }
+
+    let loader_path = mu_plugin_loader_path(name, plugin_install_dir);
+    if kind == Some(DependencyType::MuPlugin) && loader_path.exists() {
+        fs::remove_file(&loader_path)?;
+        progress!("Removed {:?}", loader_path);
+        removed.push(loader_path);
+    }
+    Ok(removed)
+}
+
+/// A dependency wdm.yml no longer lists, which `install --prune` uninstalls.
+struct Prunable {
+    name: String,
+    version: String,
+    kind: DependencyType,
+    path: PathBuf,
+    /// The files extracted, from wdm.lock
+    files: Vec<String>,
+    hash: String,
+    /// The environments it is installed in, when wdm.yml still lists it for others
+    only_in: Vec<String>,
+}
+
+/// Finds what `install --prune` uninstalls: lock entries of dependencies no
+/// longer listed anywhere in wdm.yml, and directories whose `INSTALL_MARKER`
+/// names one, even when wdm.lock has forgotten them. Dev dependencies count
+/// as listed even with `--no-dev`.
+///
+/// Installed dependencies that are only listed for environments other than
+/// `env` are found too; their lock entries are kept for those environments.
+fn prunable(
+    config: &Config,
+    lockfile: &Lockfile,
+    wordpress_path: &Path,
+    env: Option<&str>,
+) -> Vec<Prunable> {
+    let listed = config.selected_dependencies(true);
+    let find = |name: &str| listed.iter().find(|d| same_name(&d.name, name));
+    let only_in = |name: &str| match find(name) {
+        Some(dep) if !dep.applies_to(env) => Some(dep.envs.clone()),
+        _ => None,
+    };
+    let mut found: Vec<Prunable> = lockfile
+        .dependencies
+        .iter()
+        .filter(|locked| match only_in(&locked.name) {
+            Some(_) => locked.install_dir(wordpress_path).exists(),
+            None => find(&locked.name).is_none(),
+        })
+        .map(|locked| Prunable {
+            name: locked.name.clone(),
+            version: locked.version.clone(),
+            kind: locked.kind,
+            path: locked.install_dir(wordpress_path),
+            files: locked.files.clone(),
+            hash: locked.hash.clone(),
+            only_in: only_in(&locked.name).unwrap_or_default(),
+        })
+        .collect();
+
+    for kind in [
+        DependencyType::Plugin,
+        DependencyType::Theme,
+        DependencyType::MuPlugin,
+    ] {
+        let Ok(entries) = fs::read_dir(wordpress_path.join(kind.content_dir())) else {
+            continue;
+        };
+        let mut dirs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        dirs.sort();
+        for path in dirs {
+            let Some(marker) = InstallMarker::read(&path) else {
+                continue;
+            };
+            let known = found
+                .iter()
+                .any(|p| p.path == path || same_name(&p.name, &marker.name));
+            let elsewhere = only_in(&marker.name);
+            if (find(&marker.name).is_none() || elsewhere.is_some()) && !known {
+                found.push(Prunable {
+                    name: marker.name,
+                    version: marker.version,
+                    kind,
+                    path,
+                    files: Vec::new(),
+                    hash: marker.hash,
+                    only_in: elsewhere.unwrap_or_default(),
+                });
+            }
+        }
+    }
+    found
+}
+
+/// One row of `wdm list`.
+#[derive(Serialize, Debug)]
+struct ListedDependency {
+    name: String,
+    /// The requirement from wdm.yml, unless the dependency is orphaned
+    required: Option<String>,
+    locked: Option<String>,
+    /// The oldest WordPress the locked version supports, from its headers
+    requires_wp: Option<String>,
+    /// The oldest PHP the locked version supports, from its headers
+    requires_php: Option<String>,
+    status: ListedStatus,
+    path: PathBuf,
+    dev: bool,
+    /// The manifest wdm.yml extends that the dependency comes from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+}
+
+impl ListedDependency {
+    /// The requirements for the "Requires" column, such as `WP 6.0, PHP 7.4`.
+    fn requires(&self) -> String {
+        let requires: Vec<String> = [("WP", &self.requires_wp), ("PHP", &self.requires_php)]
+            .into_iter()
+            .filter_map(|(product, version)| Some(format!("{} {}", product, version.as_ref()?)))
+            .collect();
+        if requires.is_empty() {
+            "-".to_string()
+        } else {
+            requires.join(", ")
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum ListedStatus {
+    Installed,
+    Missing,
+    /// Locked, but no longer in wdm.yml
+    Orphaned,
+}
+
+impl std::fmt::Display for ListedStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ListedStatus::Installed => "installed",
+            ListedStatus::Missing => "missing",
+            ListedStatus::Orphaned => "orphaned",
+        })
+    }
+}
+
+/// What `install --dry-run` expects to do with one dependency.
+#[derive(Serialize, Debug)]
+struct PlannedInstall {
+    name: String,
+    action: PlannedAction,
+    /// The version currently installed, when it would be replaced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    /// The version that would be installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum PlannedAction {
+    Install,
+    Upgrade,
+    Skip,
+    Fail,
+    Prune,
+}
+
+/// How wdm.lock would change for one dependency, according to `wdm diff`.
+#[derive(Serialize, Debug)]
+struct LockChange {
+    name: String,
+    change: ChangeKind,
+    /// The version wdm.lock records
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    /// The version wdm.yml resolves to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum ChangeKind {
+    Added,
+    Upgraded,
+    Downgraded,
+    /// Taken from somewhere else, or moved between versions that can't be
+    /// ordered, such as two commits
+    Changed,
+    Removed,
+    Unchanged,
+    Failed,
+}
+
+impl ChangeKind {
+    fn label(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Upgraded => "upgraded",
+            ChangeKind::Downgraded => "downgraded",
+            ChangeKind::Changed => "changed",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Unchanged => "unchanged",
+            ChangeKind::Failed => "failed",
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), WdmError> {
+    if let Some(fail_fast) = cli.command.workspace() {
+        return run_workspace(fail_fast);
+    }
+    match &cli.command {
+        Commands::Init {
+            wordpress_path,
+            force,
+        } => {
+            let manifest_path = MANIFEST_PATH
+                .get()
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from("wdm.yml"));
+            if manifest_path.is_dir() {
+                return Err(WdmError::InvalidManifestPath {
+                    path: manifest_path,
+                    reason: "is not a file",
+                });
+            }
+            let interactive = std::io::stdin().is_terminal();
+            let mut initialized = !manifest_path.exists();
+            if !initialized && *force {
+                // The flag is the confirmation when nobody is there to answer
+                initialized =
+                    !interactive || confirm(&format!("Overwrite {}?", manifest_path.display()))?;
+            }
+            if !initialized {
+                progress!("{} already exists", manifest_path.display());
+            } else {
+                let project_dir = manifest_dir(&manifest_path);
+                let wordpress_path = match wordpress_path {
+                    Some(path) => path.clone(),
+                    None => {
+                        let detected = detect_wordpress_path(project_dir);
+                        if let Some(path) = detected.as_deref().filter(|p| *p != ".") {
+                            progress!("Found WordPress in {}/", path);
+                        }
+                        let default = detected.unwrap_or_else(|| ".".to_string());
+                        if interactive {
+                            let answer = prompt(&format!("WordPress path [{}]: ", default))?;
+                            if answer.is_empty() {
+                                default
+                            } else {
+                                answer
+                            }
+                        } else {
+                            default
+                        }
+                    }
+                };
+                let _lock = lock_project(project_dir)?;
+                let mut config = Config::new(wordpress_path);
+                write_manifest(&manifest_path, &mut config)?;
+                progress!("Initialized {}", manifest_path.display());
+            }
+            if json_output() {
+                print_json(&json!({
+                    "action": if initialized { "initialized" } else { "none" },
+                    "path": manifest_path,
+                }));
+            }
+            Ok(())
+        }
+        Commands::Add {
+            specs,
+            version,
+            range,
+            repo,
+            source,
+            url,
+            host,
+            asset,
+            token_env,
+            timeout,
+            kind,
+            dev,
+            install_path,
+            path,
+            pre,
+            no_install,
+            strict_compat,
+            strict,
+            with_requirements,
+            integrity,
+        } => {
+            // A bare --integrity is filled in from the download once it is installed
+            let compute_integrity = integrity.as_deref().is_some_and(|i| i.trim().is_empty());
+            let integrity = integrity
+                .as_deref()
+                .map(str::trim)
+                .filter(|i| !i.is_empty());
+            if compute_integrity && *no_install {
+                return Err(WdmError::InvalidArgument(
+                    "--integrity needs the download to take the hash from; give it as --integrity=sha256-<hex> with --no-install".to_string(),
+                ));
+            }
+            if let Some(integrity) = integrity {
+                if integrity_digest(integrity).is_none() {
+                    return Err(WdmError::InvalidArgument(format!(
+                        "--integrity must be sha256- followed by 64 hex digits, not '{}'",
+                        integrity
+                    )));
+                }
+            }
+            if specs.len() > 1 {
+                let single: Vec<&str> = [
+                    ("--version", version.is_some()),
+                    ("--repo", repo.is_some()),
+                    ("--url", url.is_some()),
+                    ("--install-path", install_path.is_some()),
+                    ("--path", path.is_some()),
+                    ("--integrity=<hash>", integrity.is_some()),
+                ]
+                .into_iter()
+                .filter_map(|(flag, given)| given.then_some(flag))
+                .collect();
+                if !single.is_empty() {
+                    return Err(WdmError::InvalidArgument(format!(
+                        "{} can only be used when adding one dependency; give each one as owner/repo@version instead",
+                        single.join(", ")
+                    )));
+                }
+            }
+            let url = url.as_deref().map(str::trim);
+            let path = path
+                .as_deref()
+                .map(|p| p.trim().trim_matches('/').to_string());
+            if let Some(path) = &path {
+                if path.is_empty() || !is_safe_relative_path(Path::new(path)) {
+                    return Err(WdmError::InvalidArgument(format!(
+                        "--path must be a directory inside the repository, not '{}'",
+                        path
+                    )));
+                }
+            }
+            // Also says whether the version is left for `pick_version` to choose
+            let dependency = |spec: &str| -> Result<(Dependency, bool), WdmError> {
+                let spec = parse_add_spec(spec, *source)?;
+                if *range && spec.version.is_some() {
+                    return Err(WdmError::InvalidArgument(format!(
+                        "--range applies to the version wdm picks, so {} can't also give one after @",
+                        spec.name.trim()
+                    )));
+                }
+                let unversioned = version.is_none() && spec.version.is_none();
+                // A URL on its own implies a url-sourced dependency
+                let source = source.or(spec.source).or(url.map(|_| Source::Url));
+                // Explicit flags win over what the shorthand says
+                let (repo, repo_host) = match repo.as_deref().map(str::trim) {
+                    Some(repo) => normalize_repo(repo, source)?,
+                    None => (spec.repo.unwrap_or_default(), spec.host),
+                };
+                let host = host
+                    .as_deref()
+                    .map(str::trim)
+                    .map(str::to_string)
+                    .or(repo_host);
+                let version = version
+                    .as_deref()
+                    .or(spec.version.as_deref())
+                    .unwrap_or("latest")
+                    .trim();
+                let name = spec.name.trim();
+                check_version_requirement(version, source.unwrap_or_default()).map_err(
+                    |source| WdmError::InvalidVersion {
+                        dep: name.to_string(),
+                        version: version.to_string(),
+                        path: None,
+                        source,
+                    },
+                )?;
+                let missing = match source.unwrap_or_default() {
+                    Source::Github if repo.is_empty() => Some("--repo is required for GitHub"),
+                    Source::Gitlab if repo.is_empty() => Some("--repo is required for GitLab"),
+                    Source::Bitbucket if repo.is_empty() => {
+                        Some("--repo is required for Bitbucket")
+                    }
+                    Source::Url if url.is_none() => Some("--url is required for url"),
+                    _ => None,
+                };
+                if let Some(missing) = missing {
+                    return Err(WdmError::InvalidArgument(format!(
+                        "{} dependencies",
+                        missing
+                    )));
+                }
+                let dependency = Dependency {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    repo,
+                    token_env: token_env.clone(),
+                    source,
+                    url: url.map(str::to_string),
+                    host,
+                    asset: asset.as_deref().map(str::trim).map(str::to_string),
+                    kind: *kind,
+                    install_path: install_path.as_deref().map(str::trim).map(str::to_string),
+                    strip_prefix: None,
+                    archive_format: None,
+                    path: path.clone(),
+                    hooks: Hooks::default(),
+                    allow_prerelease: *pre,
+                    tag_pattern: None,
+                    integrity: integrity.map(str::to_string),
+                    envs: Vec::new(),
+                };
+                Ok((dependency, unversioned))
+            };
+
+            // Every spec is checked before wdm.yml is touched
+            let mut dependencies: Vec<Dependency> = Vec::new();
+            let mut unversioned = Vec::new();
+            let mut invalid = Vec::new();
+            for spec in specs {
+                match dependency(spec) {
+                    Ok((dep, _)) if dependencies.iter().any(|d| same_name(&d.name, &dep.name)) => {
+                        invalid.push(WdmError::InvalidArgument(format!(
+                            "{} is given more than once",
+                            dep.name
+                        )));
+                    }
+                    Ok((dep, without_version)) => {
+                        unversioned.push(without_version);
+                        dependencies.push(dep);
+                    }
+                    Err(e) => invalid.push(e),
+                }
+            }
+            if invalid.len() == 1 {
+                return Err(invalid.remove(0));
+            } else if !invalid.is_empty() {
+                let messages: Vec<String> = invalid.iter().map(|e| e.to_string()).collect();
+                return Err(WdmError::InvalidArgument(messages.join("\n")));
+            }
+
+            // Without a version, one is picked from the tags when someone is there to
+            // choose; scripts get latest, as before
+            let interactive = std::io::stdin().is_terminal();
+            for (dep, _) in dependencies
+                .iter_mut()
+                .zip(&unversioned)
+                .filter(|(dep, unversioned)| **unversioned && dep.source != Some(Source::Url))
+            {
+                if !interactive && !*range {
+                    warning!(
+                        "No --version given for {}; adding it at latest (pass --version to pin one)",
+                        dep.name
+                    );
+                    continue;
+                }
+                let versions = available_versions(dep, dependency_token(dep).as_deref())?;
+                let picked = if interactive {
+                    pick_version(dep, &versions)?
+                } else {
+                    newest_version(dep, &versions)?
+                };
+                dep.version = if *range {
+                    caret_range(&picked)
+                } else {
+                    picked.to_string()
+                };
+                if !interactive {
+                    warning!(
+                        "No --version given for {}; adding it at {}, the range of the latest version",
+                        dep.name,
+                        dep.version
+                    );
+                }
+            }
+
+            // Without a manifest anywhere above, start one in the current directory
+            let manifest_path = match find_manifest() {
+                Err(WdmError::ManifestNotFound) => PathBuf::from("wdm.yml"),
+                found => found?,
+            };
+            let _lock = lock_project(manifest_dir(&manifest_path))?;
+            let mut config = if manifest_path.exists() {
+                load_config()?
+            } else {
+                Config::new(".".to_string())
+            };
+            let root_dir = manifest_path.parent().unwrap_or_else(|| Path::new("."));
+            let wordpress_path = resolve_wordpress_path(&config.config, root_dir, None).0;
+
+            let mut existed = Vec::new();
+            for dep in dependencies {
+                check_install_path(&dep, &wordpress_path)?;
+                // Remove any existing dependency with the same normalized name to prevent duplicates,
+                // in either section so a dependency can be moved between them
+                let initial_len = config.dependencies.len() + config.dev_dependencies.len();
+                for section in [&mut config.dependencies, &mut config.dev_dependencies] {
+                    section.retain(|d| !same_name(&d.name, &dep.name));
+                }
+
+                let dependency_existed =
+                    config.dependencies.len() + config.dev_dependencies.len() < initial_len;
+                if dependency_existed {
+                    progress!(
+                        "Dependency '{}' already exists. Updating its information.",
+                        dep.name
+                    );
+                    progress!("Updated {} in wdm.yml", dep.name);
+                } else if *dev {
+                    progress!("Added {} to wdm.yml as a dev dependency", dep.name);
+                } else {
+                    progress!("Added {} to wdm.yml", dep.name);
+                }
+                existed.push((dep.name.clone(), dependency_existed));
+
+                // Add the new or updated dependency
+                if *dev {
+                    config.dev_dependencies.push(dep);
+                } else {
+                    config.dependencies.push(dep);
+                }
+            }
+
+            write_manifest(&manifest_path, &mut config)?;
+
+            // Proceed to install the newly added dependencies, all in one pass
+            let mut added: Vec<Dependency> = existed
+                .iter()
+                .filter_map(|(name, _)| {
+                    config
+                        .selected_dependencies(true)
+                        .into_iter()
+                        .find(|d| same_name(&d.name, name))
+                        .cloned()
+                })
+                .collect();
+            let mut results: Vec<(Option<InstallReport>, Option<WdmError>)> = if *no_install {
+                let names: Vec<&str> = added.iter().map(|d| d.name.as_str()).collect();
+                progress!(
+                    "Run 'wdm install {}' to install {}",
+                    names.join(" "),
+                    if names.len() == 1 { "it" } else { "them" }
+                );
+                added.iter().map(|_| (None, None)).collect()
+            } else {
+                let deps: Vec<&Dependency> = added.iter().collect();
+                install_dependencies(&deps, *timeout, *strict_compat, *strict, *with_requirements)?
+                    .into_iter()
+                    .map(|(report, error)| (Some(report), error))
+                    .collect()
+            };
+            if compute_integrity {
+                let integrities: Vec<(String, String)> = added
+                    .iter()
+                    .zip(&results)
+                    .filter(|(_, (_, error))| error.is_none())
+                    .filter_map(|(dep, (report, _))| {
+                        let hash = report.as_ref()?.hash.as_ref()?;
+                        Some((dep.name.clone(), format!("sha256-{}", hash)))
+                    })
+                    .collect();
+                record_integrity(&integrities)?;
+                for dep in &mut added {
+                    if let Some((_, integrity)) = integrities.iter().find(|(n, _)| *n == dep.name) {
+                        dep.integrity = Some(integrity.clone());
+                    }
+                }
+            }
+            let required = if *with_requirements {
+                let names = added.iter().map(|d| d.name.clone()).collect();
+                install_requirements(names, *timeout, *strict_compat, *strict)?
+            } else {
+                Vec::new()
+            };
+            let required_failed = required.iter().filter(|(_, error)| error.is_some()).count();
+            for error in required.iter().filter_map(|(_, error)| error.as_ref()) {
+                error!("{}", error);
+            }
+
+            // A single dependency fails the command with its error, as before
+            if let [(_, error @ Some(_))] = results.as_mut_slice() {
+                return Err(error.take().unwrap());
+            }
+            let failed = results.iter().filter(|(_, error)| error.is_some()).count();
+            for error in results.iter().filter_map(|(_, error)| error.as_ref()) {
+                error!("{}", error);
+            }
+            if added.len() > 1 && !*no_install {
+                let count = |status| {
+                    results
+                        .iter()
+                        .filter(|(report, _)| report.as_ref().is_some_and(|r| r.status == status))
+                        .count()
+                };
+                summary!(
+                    "{} added, {} installed, {} skipped, {} failed",
+                    added.len(),
+                    count(InstallStatus::Installed),
+                    count(InstallStatus::Skipped),
+                    failed
+                );
+            }
+            if json_output() {
+                let changes: Vec<serde_json::Value> = added
+                    .iter()
+                    .zip(&existed)
+                    .zip(&results)
+                    .map(|((dependency, (_, dependency_existed)), (report, _))| {
+                        json!({
+                            "action": if *dependency_existed { "updated" } else { "added" },
+                            "dependency": dependency,
+                            "install": report,
+                        })
+                    })
+                    .collect();
+                match changes.as_slice() {
+                    [change] => print_json(change),
+                    _ => print_json(&changes),
+                }
+            }
+            if failed + required_failed > 0 {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+        Commands::Remove {
+            names,
+            all,
+            yes,
+            purge,
+            force,
+        } => {
+            let (mut config, _lock) = load_config_locked()?;
+            let mut lockfile = load_lockfile()?;
+
+            let names: Vec<String> = if *all {
+                // Dependencies from the manifests wdm.yml extends stay, since they aren't wdm.yml's to remove
+                let every: Vec<String> = config
+                    .selected_dependencies(true)
+                    .iter()
+                    .filter(|d| config.base_origin(&d.name).is_none())
+                    .map(|d| d.name.clone())
+                    .collect();
+                if every.is_empty() {
+                    summary!("wdm.yml has no dependencies to remove");
+                    return Ok(());
+                }
+                if !*yes {
+                    if !std::io::stdin().is_terminal() {
+                        return Err(WdmError::InvalidArgument(
+                            "--all removes every dependency; pass --yes to confirm".to_string(),
+                        ));
+                    }
+                    let question = format!(
+                        "Remove all {} dependencies ({}) and uninstall them?",
+                        every.len(),
+                        every.join(", ")
+                    );
+                    if !confirm(&question)? {
+                        summary!("Nothing was removed");
+                        return Ok(());
+                    }
+                }
+                every
+            } else {
+                names.clone()
+            };
+
+            let root_dir = resolve_root_dir()?;
+            let (wordpress_path, _) = resolve_wordpress_path(&config.config, &root_dir, None);
+            let cache = resolve_cache(&config.config, &root_dir)?;
+
+            // Check every name before changing anything
+            let mut failures = Vec::new();
+            let mut removals = Vec::new();
+            for name in &names {
+                let Some(dep) = config
+                    .selected_dependencies(true)
+                    .into_iter()
+                    .find(|d| same_name(&d.name, name))
+                    .cloned()
+                else {
+                    failures.push(WdmError::DependencyNotFound(name.clone()));
+                    continue;
+                };
+                if let Some(origin) = config.base_origin(&dep.name) {
+                    failures.push(WdmError::InheritedDependency {
+                        dep: dep.name.clone(),
+                        origin: origin.to_string(),
+                    });
+                    continue;
+                }
+                if removals
+                    .iter()
+                    .any(|(d, _, _): &(Dependency, _, _)| same_name(&d.name, &dep.name))
+                {
+                    continue;
+                }
+
+                // The lock records where the dependency was actually installed
+                let locked = lockfile
+                    .dependencies
+                    .iter()
+                    .find(|d| same_name(&d.name, &dep.name));
+                let plugin_install_dir = Dependency {
+                    kind: locked.map(|d| d.kind).or(dep.kind),
+                    install_path: locked
+                        .map(|d| d.install_path.clone())
+                        .unwrap_or(dep.install_path.clone()),
+                    ..dep.clone()
+                }
+                .install_dir(&wordpress_path);
+
+                // Only delete directories wdm can prove it installed
+                if plugin_install_dir.exists()
+                    && !is_managed_by_wdm(&plugin_install_dir, &dep.name)
+                    && !*force
+                {
+                    failures.push(WdmError::UnmanagedDirectory(plugin_install_dir));
+                    continue;
+                }
+                removals.push((dep, locked.cloned(), plugin_install_dir));
+            }
+
+            if !removals.is_empty() {
+                for section in [&mut config.dependencies, &mut config.dev_dependencies] {
+                    section.retain(|d| {
+                        !removals
+                            .iter()
+                            .any(|(dep, _, _)| same_name(&dep.name, &d.name))
+                    });
+                }
+                write_manifest(&find_manifest()?, &mut config)?;
+                for (dep, _, _) in &removals {
+                    progress!("Removed {} from wdm.yml", dep.name);
+                }
+            }
+
+            let mut changes = Vec::new();
+            for (dep, locked, plugin_install_dir) in &removals {
+                let name = &dep.name;
+                let mut removed = uninstall(
+                    name,
+                    locked.as_ref().map(|d| d.kind).or(dep.kind),
+                    plugin_install_dir,
+                    locked
+                        .as_ref()
+                        .map(|d| d.files.as_slice())
+                        .unwrap_or_default(),
+                )?;
+
+                if *purge {
+                    // Archives cached before versions were part of the file name are still ours too
+                    let mut cache_files =
+                        vec![root_dir.join(".wdm-cache").join(format!("{}.zip", name))];
+                    cache_files.extend(
+                        locked
+                            .as_ref()
+                            .map(|d| cache.archive_path(name, &d.origin(), &d.version)),
+                    );
+                    for cache_file in cache_files.iter().filter(|f| f.exists()) {
+                        fs::remove_file(cache_file)?;
+                        progress!("Removed {:?}", cache_file);
+                        removed.push(cache_file.clone());
+                    }
+                }
+                changes.push(json!({
+                    "action": "removed",
+                    "name": name,
+                    "unlocked": locked.is_some(),
+                    "removed": removed,
+                }));
+            }
+
+            let initial_len = lockfile.dependencies.len();
+            lockfile.dependencies.retain(|d| {
+                !removals
+                    .iter()
+                    .any(|(dep, _, _)| same_name(&dep.name, &d.name))
+            });
+            if lockfile.dependencies.len() < initial_len {
+                write_lockfile(&root_dir.join("wdm.lock"), &mut lockfile)?;
+                for (dep, _, _) in removals.iter().filter(|(_, locked, _)| locked.is_some()) {
+                    progress!("Removed {} from wdm.lock", dep.name);
+                }
+            }
+
+            // A single name fails the command with its error, as before
+            let single = names.len() == 1 && !*all;
+            if single {
+                if let Some(failure) = failures.pop() {
+                    return Err(failure);
+                }
+            }
+            for failure in &failures {
+                error!("{}", failure);
+            }
+            if !single {
+                summary!("{} removed, {} failed", removals.len(), failures.len());
+            }
+            if json_output() {
+                match changes.as_slice() {
+                    [change] if single => print_json(change),
+                    _ => print_json(&changes),
+                }
+            }
+            if !failures.is_empty() {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+        Commands::Install {
+            names,
+            frozen,
+            jobs,
+            timeout,
+            offline,
+            no_cache,
+            insecure_skip_verify,
+            no_dev,
+            wordpress_path,
+            dry_run,
+            force,
+            strict_compat,
+            strict,
+            prune,
+            update_manifest,
+            with_requirements,
+            env,
+            ..
+        } => {
+            // Planning only reads, so it doesn't wait for anyone
+            let (config, _lock) = if *dry_run {
+                (load_config()?, None)
+            } else {
+                let (config, lock) = load_config_locked()?;
+                (config, Some(lock))
+            };
+            configure_http(&config.config, *timeout);
+            let lockfile = load_lockfile()?;
+            let env = active_env(env.as_deref());
+            let (dependencies, elsewhere): (Vec<_>, Vec<_>) =
+                select_by_name(config.selected_dependencies(!*no_dev), names)?
+                    .into_iter()
+                    .partition(|dep| dep.applies_to(env.as_deref()));
+            // They stay in wdm.lock, so switching environments doesn't re-lock them
+            for dep in &elsewhere {
+                progress!(
+                    "Skipping {}, which is only installed in {}",
+                    dep.name,
+                    dep.envs.join(", ")
+                );
+            }
+
+            // A frozen install must be fully described by wdm.lock up front
+            if *frozen {
+                REFRESH_TAGS.store(true, Ordering::Relaxed);
+                if !manifest_root()?.join("wdm.lock").exists() {
+                    return Err(WdmError::LockfileNotFound);
+                }
+
+                let frozen_errors: Vec<String> = lock_problems(&config, &dependencies, &lockfile)
+                    .into_iter()
+                    .map(|problem| problem.message)
+                    .collect();
+                if !frozen_errors.is_empty() {
+                    return Err(WdmError::LockOutdated(frozen_errors));
+                }
+            }
+
+            // Planning must not even create the cache directory
+            if *dry_run {
+                let (wordpress_path, origin) = resolve_wordpress_path(
+                    &config.config,
+                    &manifest_root()?,
+                    wordpress_path.as_deref(),
+                );
+                check_wordpress_path(&wordpress_path, origin)?;
+                let mut plan: Vec<PlannedInstall> = dependencies
+                    .iter()
+                    .map(|dep| plan_dependency(dep, &lockfile, &wordpress_path, *force))
+                    .collect();
+                if *prune {
+                    plan.extend(
+                        prunable(&config, &lockfile, &wordpress_path, env.as_deref())
+                            .into_iter()
+                            .map(|pruned| PlannedInstall {
+                                name: pruned.name,
+                                action: PlannedAction::Prune,
+                                from: Some(pruned.version),
+                                to: None,
+                                error: None,
+                            }),
+                    );
+                }
+
+                let count = |action| plan.iter().filter(|p| p.action == action).count();
+                if json_output() {
+                    print_json(&plan);
+                } else {
+                    for planned in &plan {
+                        let to = planned.to.as_deref().map(display_version).unwrap_or("");
+                        match planned.action {
+                            PlannedAction::Install => {
+                                println!("Would install {} {}", planned.name, to)
+                            }
+                            PlannedAction::Upgrade => println!(
+                                "Would upgrade {} {} -> {}",
+                                planned.name,
+                                planned.from.as_deref().map(display_version).unwrap_or(""),
+                                to
+                            ),
+                            PlannedAction::Skip => {
+                                println!("Would skip {} {} (already installed)", planned.name, to)
+                            }
+                            PlannedAction::Fail => {
+                                println!("Would fail to resolve {}", planned.name)
+                            }
+                            PlannedAction::Prune => println!(
+                                "Would prune {} {}",
+                                planned.name,
+                                planned.from.as_deref().map(display_version).unwrap_or("")
+                            ),
+                        }
+                    }
+                }
+                let failed = count(PlannedAction::Fail);
+                let pruned = if *prune {
+                    format!(", {} to prune", count(PlannedAction::Prune))
+                } else {
+                    String::new()
+                };
+                summary!(
+                    "{} to install, {} to upgrade, {} skipped, {} failed{}",
+                    count(PlannedAction::Install),
+                    count(PlannedAction::Upgrade),
+                    count(PlannedAction::Skip),
+                    failed,
+                    pruned
+                );
+                if failed > 0 {
+                    return Err(WdmError::AlreadyReported);
+                }
+                return Ok(());
+            }
+
+            let mut ctx = InstallContext::new(&config, lockfile, wordpress_path.as_deref())?;
+            ctx.insecure_skip_verify = *insecure_skip_verify;
+            ctx.strict_compat = *strict_compat;
+            ctx.strict_headers = *strict;
+            ctx.with_requirements = *with_requirements;
+
+            // Resolve and download concurrently, then extract and lock one at a time in
+            // wdm.yml order so output and the lockfile don't depend on which finished first
+            let fetched = if *offline {
+                // Every dependency must be available before anything is touched
+                let (fetched, missing): (Vec<_>, Vec<_>) = dependencies
+                    .iter()
+                    .map(|dep| {
+                        fetch_cached_dependency(
+                            dep,
+                            &ctx.lockfile,
+                            &ctx.wordpress_path,
+                            &ctx.cache,
+                            *force,
+                        )
+                    })
+                    .partition(Result::is_ok);
+                if !missing.is_empty() {
+                    return Err(WdmError::OfflineUnavailable(
+                        missing.into_iter().filter_map(Result::err).collect(),
+                    ));
+                }
+                fetched.into_iter().filter_map(Result::ok).collect()
+            } else {
+                let jobs = jobs.unwrap_or_else(default_jobs);
+                let started = Instant::now();
+                let fetched = parallel_map(&dependencies, jobs, |dep| {
+                    fetch_dependency(
+                        dep,
+                        &ctx.lockfile,
+                        &ctx.wordpress_path,
+                        &ctx.cache,
+                        !*no_cache,
+                        *force,
+                    )
+                });
+                verbose!(
+                    "Fetched {} dependencies in {:.1}s with {} HTTP requests over one connection pool",
+                    dependencies.len(),
+                    started.elapsed().as_secs_f64(),
+                    HTTP_REQUESTS.load(Ordering::Relaxed)
+                );
+                fetched
+            };
+
+            let mut reports = Vec::new();
+            for (dep, fetched) in dependencies.iter().copied().zip(fetched) {
+                progress!("Installing {}...", dep.name);
+                let outcome = install_one(&mut ctx, dep, fetched);
+                match &outcome {
+                    Ok(InstallOutcome::Installed {
+                        version,
+                        size,
+                        elapsed,
+                        ..
+                    }) => progress!(
+                        "Installed {} {} ({})",
+                        dep.name,
+                        version,
+                        describe_transfer(*size, *elapsed)
+                    ),
+                    Ok(_) => {}
+                    Err(e) => error!("{}", e),
+                }
+                reports.push(ctx.report(dep, &outcome));
+            }
+
+            // Other environments' dependencies are locked without installing them, so
+            // every environment agrees on one wdm.lock
+            if !*frozen && !*offline {
+                let unlocked: Vec<&Dependency> = elsewhere
+                    .iter()
+                    .copied()
+                    .filter(|dep| !lock_problems(&config, &[*dep], &ctx.lockfile).is_empty())
+                    .collect();
+                let fetched = parallel_map(&unlocked, jobs.unwrap_or_else(default_jobs), |dep| {
+                    fetch_dependency(
+                        dep,
+                        &ctx.lockfile,
+                        &ctx.wordpress_path,
+                        &ctx.cache,
+                        !*no_cache,
+                        true,
+                    )
+                });
+                for (dep, fetched) in unlocked.iter().copied().zip(fetched) {
+                    match lock_one(&mut ctx, dep, fetched) {
+                        Ok((version, _)) => progress!(
+                            "Locked {} {} for {}",
+                            dep.name,
+                            display_version(&version),
+                            dep.envs.join(", ")
+                        ),
+                        Err(e) => warning!(
+                            "Could not lock {}, which is only installed in {}: {}",
+                            dep.name,
+                            dep.envs.join(", "),
+                            e
+                        ),
+                    }
+                }
+            }
+
+            // Teammates' removals from wdm.yml reach this site too
+            if *prune {
+                for pruned in prunable(&config, &ctx.lockfile, &ctx.wordpress_path, env.as_deref())
+                {
+                    // A directory someone replaced by hand isn't wdm's to delete anymore
+                    if pruned.path.exists() && !is_managed_by_wdm(&pruned.path, &pruned.name) {
+                        warning!(
+                            "Kept {:?} because it has no {} naming {}; delete it by hand if it is no longer needed",
+                            pruned.path,
+                            INSTALL_MARKER,
+                            pruned.name
+                        );
+                    } else {
+                        uninstall(&pruned.name, Some(pruned.kind), &pruned.path, &pruned.files)?;
+                    }
+                    if pruned.only_in.is_empty() {
+                        progress!(
+                            "Pruned {} {}, which is no longer in wdm.yml",
+                            pruned.name,
+                            display_version(&pruned.version)
+                        );
+                        ctx.lockfile
+                            .dependencies
+                            .retain(|d| !same_name(&d.name, &pruned.name));
+                    } else {
+                        progress!(
+                            "Pruned {} {}, which is only installed in {}",
+                            pruned.name,
+                            display_version(&pruned.version),
+                            pruned.only_in.join(", ")
+                        );
+                    }
+                    reports.push(InstallReport::pruned(&pruned));
+                }
+            }
+
+            if *update_manifest {
+                update_moved_repos(&mut ctx.lockfile)?;
+            }
+
+            // Write the updated lockfile at root_dir
+            if !*frozen {
+                let changed = ctx.save_lockfile()?;
+                report_lockfile_write(&ctx.lockfile_path(), changed);
+            }
+            if *with_requirements {
+                let names = dependencies.iter().map(|d| d.name.clone()).collect();
+                for (report, error) in
+                    install_requirements(names, *timeout, *strict_compat, *strict)?
+                {
+                    if let Some(error) = error {
+                        error!("{}", error);
+                    }
+                    reports.push(report);
+                }
+            }
+
+            let count = |status| reports.iter().filter(|r| r.status == status).count();
+            let failed = count(InstallStatus::Failed);
+            let pruned = if *prune {
+                format!(", {} pruned", count(InstallStatus::Pruned))
+            } else {
+                String::new()
+            };
+            summary!(
+                "{} installed, {} skipped, {} failed{}",
+                count(InstallStatus::Installed),
+                count(InstallStatus::Skipped),
+                failed,
+                pruned
+            );
+            if json_output() {
+                print_json(&reports);
+            }
+            if failed > 0 {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+        Commands::Update {
+            name,
+            pre,
+            strict_compat,
+            strict,
+            update_manifest,
+            update_integrity,
+            ..
+        } => {
+            let (config, _lock) = load_config_locked()?;
+            configure_http(&config.config, None);
+            // Updating is asking for the newest tags, so they are always looked up again
+            REFRESH_TAGS.store(true, Ordering::Relaxed);
+            let mut ctx = InstallContext::new(&config, load_lockfile()?, None)?;
+            ctx.strict_compat = *strict_compat;
+            ctx.strict_headers = *strict;
+
+            // Restrict the update to a single dependency when a name is given
+            let dependencies: Vec<&Dependency> = match name {
+                Some(name) => {
+                    let matches: Vec<&Dependency> = config
+                        .selected_dependencies(true)
+                        .into_iter()
+                        .filter(|d| same_name(&d.name, name))
+                        .collect();
+                    if matches.is_empty() {
+                        return Err(WdmError::DependencyNotFound(name.clone()));
+                    }
+                    matches
+                }
+                None => config.selected_dependencies(true),
+            };
+
+            let mut reports = Vec::new();
+            let mut integrities = Vec::new();
+            for dep in dependencies {
+                let dep = &Dependency {
+                    allow_prerelease: dep.allow_prerelease || *pre,
+                    ..dep.clone()
+                };
+                let locked_version = ctx
+                    .lockfile
+                    .dependencies
+                    .iter()
+                    .find(|d| same_name(&d.name, &dep.name))
+                    .map(|d| d.version.clone());
+
+                let fetched = fetch_update(
+                    dep,
+                    locked_version.as_deref(),
+                    &ctx.cache,
+                    *update_integrity,
+                );
+                // The new archive replaces the pinned hash instead of being checked against it
+                let pinned = dep.integrity.is_some() && *update_integrity;
+                let unpinned = Dependency {
+                    integrity: None,
+                    ..dep.clone()
+                };
+                let outcome = install_one(&mut ctx, if pinned { &unpinned } else { dep }, fetched);
+                if let (true, Ok(InstallOutcome::Installed { hash, .. })) = (pinned, &outcome) {
+                    integrities.push((dep.name.clone(), format!("sha256-{}", hash)));
+                }
+                match &outcome {
+                    Ok(InstallOutcome::Installed {
+                        version,
+                        size,
+                        elapsed,
+                        ..
+                    }) => progress!(
+                        "{} {} -> {} ({})",
+                        dep.name,
+                        locked_version
+                            .as_deref()
+                            .map(display_version)
+                            .unwrap_or("none"),
+                        display_version(version),
+                        describe_transfer(*size, *elapsed)
+                    ),
+                    Ok(_) => {}
+                    Err(e) => error!("{}", e),
+                }
+                reports.push(ctx.report(dep, &outcome));
+            }
+
+            if *update_manifest {
+                update_moved_repos(&mut ctx.lockfile)?;
+            }
+            let changed = ctx.save_lockfile()?;
+            report_lockfile_write(&ctx.lockfile_path(), changed);
+            record_integrity(&integrities)?;
+            let count = |status| reports.iter().filter(|r| r.status == status).count();
+            let failed = count(InstallStatus::Failed);
+            if failed > 0 {
+                summary!(
+                    "{} updated, {} up to date, {} failed",
+                    count(InstallStatus::Installed),
+                    count(InstallStatus::UpToDate),
+                    failed
+                );
+            }
+            if json_output() {
+                print_json(&reports);
+            }
+            if failed > 0 {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+        Commands::Vendor { dir, prune, jobs } => {
+            let config = load_config()?;
+            configure_http(&config.config, None);
+            let lockfile = load_lockfile()?;
+            let root_dir = resolve_root_dir()?;
+            let mut cache = resolve_cache(&config.config, &root_dir)?;
+            // Like vendor_dir, the directory is relative to wdm.yml
+            if let Some(dir) = dir {
+                let dir = root_dir.join(dir);
+                if dir != cache.vendor_dir {
+                    warning!(
+                        "wdm install only looks for vendored archives in {:?}; set vendor_dir in wdm.yml to use the ones in {:?}",
+                        cache.vendor_dir,
+                        dir
+                    );
+                }
+                cache.vendor_dir = dir;
+            }
+            fs::create_dir_all(&cache.vendor_dir)?;
+            let file_name = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+
+            // wdm.yml says where each locked version comes from
+            let mut present = Vec::new();
+            let mut missing = Vec::new();
+            for locked in &lockfile.dependencies {
+                let dep = config.selected_dependencies(true).into_iter().find(|dep| {
+                    find_locked(&lockfile, dep).is_some_and(|l| same_name(&l.name, &locked.name))
+                });
+                match dep {
+                    None => warning!(
+                        "Skipping {} {}: wdm.yml no longer asks for it; run 'wdm install' to update wdm.lock",
+                        locked.name,
+                        display_version(&locked.version)
+                    ),
+                    Some(_) if cache.find_vendored(locked).is_some() => {
+                        verbose!("{} {} is already vendored", locked.name, locked.version);
+                        present.push(file_name(&cache.vendored_path(&locked.name, &locked.version)));
+                    }
+                    Some(dep) => missing.push((dep, locked)),
+                }
+            }
+
+            let (wordpress_path, _) = resolve_wordpress_path(&config.config, &root_dir, None);
+            let fetched = parallel_map(&missing, jobs.unwrap_or_else(default_jobs), |(dep, _)| {
+                fetch_dependency(dep, &lockfile, &wordpress_path, &cache, true, true)
+            });
+            let mut added = Vec::new();
+            let mut failed = Vec::new();
+            for ((dep, locked), fetched) in missing.iter().zip(fetched) {
+                let target = cache.vendored_path(&locked.name, &locked.version);
+                let vendored = match fetched {
+                    FetchOutcome::Downloaded { archive, hash, .. } => {
+                        verify_hash(dep, &locked.version, &locked.hash, &hash)
+                            .inspect_err(|_| {
+                                let _ = fs::remove_file(&archive);
+                            })
+                            .and_then(|()| {
+                                let partial = target.with_extension("zip.part");
+                                fs::copy(&archive, &partial)?;
+                                fs::rename(&partial, &target)?;
+                                Ok(())
+                            })
+                    }
+                    FetchOutcome::Failed(e) => Err(e),
+                    // fetch_dependency is always forced, so it never skips
+                    FetchOutcome::Skipped(message) | FetchOutcome::UpToDate(message) => {
+                        unreachable!("{}", message)
+                    }
+                };
+                match vendored {
+                    Ok(()) => {
+                        progress!(
+                            "Vendored {} {} as {}",
+                            dep.name,
+                            display_version(&locked.version),
+                            file_name(&target)
+                        );
+                        added.push(file_name(&target));
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        failed.push(dep.name.clone());
+                    }
+                }
+            }
+
+            // Only archives named like a version of a known dependency are ever
+            // deleted, along with copies that were interrupted
+            let mut removed = Vec::new();
+            if *prune {
+                let wanted: Vec<PathBuf> = lockfile
+                    .dependencies
+                    .iter()
+                    .map(|locked| cache.vendored_path(&locked.name, &locked.version))
+                    .collect();
+                let prefixes: Vec<String> = lockfile
+                    .dependencies
+                    .iter()
+                    .map(|locked| locked.name.as_str())
+                    .chain(config.dependencies.iter().map(|dep| dep.name.as_str()))
+                    .chain(config.dev_dependencies.iter().map(|dep| dep.name.as_str()))
+                    .map(|name| format!("{}-", cache_file_component(&normalize_name(name))))
+                    .collect();
+                let is_vendored = |path: &Path| {
+                    let name = file_name(path);
+                    let version = prefixes
+                        .iter()
+                        .find_map(|prefix| name.strip_prefix(prefix.as_str()))
+                        .and_then(|rest| {
+                            rest.strip_suffix(".zip")
+                                .or_else(|| rest.strip_suffix(".zip.part"))
+                        });
+                    version.is_some_and(|version| !version.is_empty())
+                };
+                for entry in fs::read_dir(&cache.vendor_dir)? {
+                    let path = entry?.path();
+                    if path.is_file() && is_vendored(&path) && !wanted.contains(&path) {
+                        fs::remove_file(&path)?;
+                        progress!("Removed {}", file_name(&path));
+                        removed.push(file_name(&path));
+                    }
+                }
+                removed.sort();
+            }
+
+            let pruned = if *prune {
+                format!(", {} removed", removed.len())
+            } else {
+                String::new()
+            };
+            summary!(
+                "{} added, {} already vendored, {} failed{} in {:?}",
+                added.len(),
+                present.len(),
+                failed.len(),
+                pruned,
+                cache.vendor_dir
+            );
+            if json_output() {
+                print_json(&json!({
+                    "dir": cache.vendor_dir,
+                    "added": added,
+                    "present": present,
+                    "removed": removed,
+                    "failed": failed,
+                }));
+            }
+            if !failed.is_empty() {
+                return Err(WdmError::AlreadyReported);
+            }
+            Ok(())
+        }
+        Commands::Rollback { name } => rollback(name),
+        Commands::Pack { output, force } => pack(output, *force),
+        Commands::Unpack {
+            archive,
+            wordpress_path,
+            force,
+        } => unpack(archive, wordpress_path.as_deref(), *force),
+        Commands::Cache { command } => {
+            let config = load_config()?;
+            let lockfile = load_lockfile()?;
+            let root_dir = resolve_root_dir()?;
+            let cache = resolve_cache(&config.config, &root_dir)?;
+
+            // Pair every cached file with the lock entry it holds, if any
+            let mut entries: Vec<(PathBuf, u64, Option<&LockedDependency>)> = Vec::new();
+            for entry in fs::read_dir(&cache.dir)? {
+                let path = entry?.path();
+                if !path.is_file() || CacheValidators::is_sidecar(&path) {
+                    continue;
+                }
+                let locked = lockfile
+                    .dependencies
+                    .iter()
+                    .find(|d| cache.archive_path(&d.name, &d.origin(), &d.version) == path);
+                entries.push((path.clone(), fs::metadata(&path)?.len(), locked));
+            }
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            let file_name = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+            let total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+
+            match command {
+                CacheCommand::List if json_output() => {
+                    let archives: Vec<_> = entries
+                        .iter()
+                        .map(|(path, size, locked)| {
+                            json!({
+                                "archive": file_name(path),
+                                "path": path,
+                                "size": size,
+                                "locked": locked.map(|d| json!({
+                                    "name": d.name,
+                                    "version": d.version,
+                                })),
+                            })
+                        })
+                        .collect();
+                    print_json(&json!({
+                        "dir": cache.dir,
+                        "archives": archives,
+                        "total_size": total,
+                    }));
+                }
+                CacheCommand::List => {
+                    if entries.is_empty() {
+                        progress!("The cache at {:?} is empty", cache.dir);
+                        return Ok(());
+                    }
+                    let rows: Vec<Vec<String>> = entries
+                        .iter()
+                        .map(|(path, size, locked)| {
+                            vec![
+                                file_name(path),
+                                format_size(*size),
+                                match locked {
+                                    Some(d) => {
+                                        format!("{} {}", d.name, display_version(&d.version))
+                                    }
+                                    None => "-".to_string(),
+                                },
+                            ]
+                        })
+                        .collect();
+                    print_table(&["Archive", "Size", "Locked"], &rows);
+                    summary!("Total: {} in {:?}", format_size(total), cache.dir);
+                }
+                CacheCommand::Clean { all, backups } => {
+                    // Other projects' archives look unreferenced from here
+                    let clean_archives = !cache.shared || *all;
+                    if !clean_archives && !*backups {
+                        return Err(WdmError::InvalidArgument(format!(
+                            "The cache at {:?} is shared with other projects. Use --all to empty it.",
+                            cache.dir
+                        )));
+                    }
+                    let mut freed = 0;
+                    let mut removed = Vec::new();
+                    for (path, size, locked) in &entries {
+                        if clean_archives && (*all || locked.is_none()) {
+                            fs::remove_file(path)?;
+                            CacheValidators::forget(path);
+                            progress!("Removed {}", file_name(path));
+                            removed.push(file_name(path));
+                            freed += size;
+                        }
+                    }
+                    let tags_dir = cache.tags_dir();
+                    if clean_archives && tags_dir.is_dir() {
+                        fs::remove_dir_all(&tags_dir)?;
+                        progress!("Removed the cached tags");
+                    }
+                    if *backups && cache.backups_dir.is_dir() {
+                        fs::remove_dir_all(&cache.backups_dir)?;
+                        progress!("Removed the backups kept for 'wdm rollback'");
+                    }
+                    summary!("Freed {}", format_size(freed));
+                    if json_output() {
+                        print_json(&json!({
+                            "removed": removed,
+                            "freed": freed,
+                            "total_size": total - freed,
+                        }));
+                    }
+                }
+                CacheCommand::Verify => {
+                    let mut corrupt = Vec::new();
+                    for (path, _, locked) in &entries {
+                        let Some(locked) = locked else { continue };
+                        if hash_file(path)? != locked.hash {
+                            corrupt.push(file_name(path));
+                            error!(
+                                "{} is corrupt: it does not match the hash of {} {} in wdm.lock",
+                                file_name(path),
+                                locked.name,
+                                display_version(&locked.version)
+                            );
+                        }
+                    }
+                    let verified = entries
+                        .iter()
+                        .filter(|(_, _, locked)| locked.is_some())
+                        .count();
+                    summary!(
+                        "Verified {} cached archives ({} total), {} corrupt",
+                        verified,
+                        format_size(total),
+                        corrupt.len()
+                    );
+                    if json_output() {
+                        print_json(&json!({
+                            "verified": verified,
+                            "corrupt": corrupt,
+                            "total_size": total,
+                        }));
+                    }
+                    if !corrupt.is_empty() {
+                        return Err(WdmError::AlreadyReported);
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        Commands::Config { command } => {
+            // Only `config set` writes wdm.yml
+            let (mut config, _lock) = match command {
+                ConfigCommand::Set { .. } => {
+                    let (config, lock) = load_config_locked()?;
+                    (config, Some(lock))
+                }
+                _ => (load_config()?, None),
+            };
+            let mut values = config_values(&config.config)?;
+            match command {
+                ConfigCommand::Get { key } => {
+                    check_config_key(key)?;
+                    let value = values
+                        .get(key.as_str())
+                        .ok_or_else(|| WdmError::ConfigKeyNotSet(key.clone()))?;
+                    if json_output() {
+                        print_json(&json!({ key: value }));
+                    } else {
+                        println!("{}", display_config_value(value));
+                    }
+                }
+                ConfigCommand::Set { key, value } => {
+                    check_config_key(key)?;
+                    // Values are read as YAML so numbers and booleans keep their type,
+                    // falling back to a plain string for fields that expect one
+                    let typed = serde_yaml::from_str(value)
+                        .unwrap_or_else(|_| serde_yaml::Value::String(value.clone()));
+                    let mut updated = None;
+                    let mut error = None;
+                    for candidate in [typed, serde_yaml::Value::String(value.clone())] {
+                        values.insert(key.clone().into(), candidate);
+                        match serde_yaml::from_value(serde_yaml::Value::Mapping(values.clone())) {
+                            Ok(data) => {
+                                updated = Some(data);
+                                break;
+                            }
+                            Err(e) => error = error.or(Some(e)),
+                        }
+                    }
+                    let Some(updated) = updated else {
+                        return Err(WdmError::InvalidArgument(format!(
+                            "Invalid value '{}' for {}: {}",
+                            value,
+                            key,
+                            error.expect("a failed candidate records its error")
+                        )));
+                    };
+                    config.config = updated;
+                    write_manifest(&find_manifest()?, &mut config)?;
+                    let values = config_values(&config.config)?;
+                    match values.get(key.as_str()) {
+                        Some(value) => {
+                            progress!("Set {} to {}", key, display_config_value(value))
+                        }
+                        None => progress!("Unset {}", key),
+                    }
+                    if json_output() {
+                        print_json(&json!({ "key": key, "value": values.get(key.as_str()) }));
+                    }
+                }
+                ConfigCommand::List => {
+                    if json_output() {
+                        print_json(&values);
+                    } else {
+                        for (key, value) in &values {
+                            // Values with environment variables show what wdm.yml says, too
+                            let raw = key
+                                .as_str()
+                                .and_then(|key| config.raw_config_value(key))
+                                .map(|raw| format!(" (from {})", raw))
+                                .unwrap_or_default();
+                            println!(
+                                "{} = {}{}",
+                                display_config_value(key),
+                                display_config_value(value),
+                                raw
+                            );
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        }
+        Commands::Outdated { .. } => {
+            // This command is read-only: it never writes wdm.lock, the cache, or wp-content
+            let config = load_config()?;
+            configure_http(&config.config, None);
+            let lockfile = load_lockfile()?;
+
+            let mut rows = Vec::new();
+            let mut failed = false;
+
+            for dep in config.selected_dependencies(true) {
+                let token = dependency_token(dep);
+
+                let wanted = match resolve_dependency_version(dep, &dep.version, token.as_deref()) {
+                    Ok(ver) => ver,
+                    Err(e) => {
+                        error!("{}", e);
+                        failed = true;
+                        continue;
+                    }
+                };
+                // A branch or commit has no "latest" beyond what it currently points at
+                let latest = if parse_git_ref(&dep.version).is_some() {
+                    wanted.clone()
+                } else {
+                    match resolve_dependency_version(dep, "latest", token.as_deref()) {
+                        Ok(ver) => ver,
+                        Err(e) => {
+                            error!("{}", e);
+                            failed = true;
+                            continue;
+                        }
+                    }
+                };
+                let locked = lockfile
+                    .dependencies
+                    .iter()
+                    .find(|d| same_name(&d.name, &dep.name))
+                    .map(|d| d.version.clone());
+
+                if locked.as_deref() != Some(wanted.as_str()) || wanted != latest {
+                    rows.push(vec![
+                        dep.name.clone(),
+                        locked
+                            .as_deref()
+                            .map(display_version)
+                            .unwrap_or("-")
+                            .to_string(),
+                        display_version(&wanted).to_string(),
+                        display_version(&latest).to_string(),
+                    ]);
+                }
+            }
+
+            if json_output() {
+                let outdated: Vec<_> = rows
+                    .iter()
+                    .map(|row| {
+                        json!({
+                            "name": row[0],
+                            "locked": (row[1] != "-").then_some(&row[1]),
+                            "wanted": row[2],
+                            "latest": row[3],
+                        })
+                    })
+                    .collect();
+                print_json(&outdated);
+            } else if rows.is_empty() {
+                if !failed {
+                    summary!("All dependencies are up to date");
+                }
+            } else {
+                print_table(&["Name", "Locked", "Wanted", "Latest"], &rows);
+            }
+
+            if failed || !rows.is_empty() {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+        Commands::Diff => {
+            let config = load_config()?;
+            configure_http(&config.config, None);
+            let lockfile = load_lockfile()?;
+            let changes = lock_changes(&config, &lockfile);
+
+            let count = |kind| changes.iter().filter(|c| c.change == kind).count();
+            let differences: Vec<&LockChange> = changes
+                .iter()
+                .filter(|c| c.change != ChangeKind::Unchanged)
+                .collect();
+            if json_output() {
+                print_json(&changes);
+            } else if !differences.is_empty() {
+                let version = |v: &Option<String>| {
+                    v.as_deref().map(display_version).unwrap_or("-").to_string()
+                };
+                let rows: Vec<Vec<String>> = differences
+                    .iter()
+                    .map(|c| {
+                        vec![
+                            c.name.clone(),
+                            c.change.label().to_string(),
+                            version(&c.from),
+                            version(&c.to),
+                        ]
+                    })
+                    .collect();
+                print_table(&["Name", "Change", "Locked", "Resolved"], &rows);
+            }
+            let extra: String = [ChangeKind::Changed, ChangeKind::Failed]
+                .into_iter()
+                .filter(|kind| count(*kind) > 0)
+                .map(|kind| format!(", {} {}", count(kind), kind.label()))
+                .collect();
+            summary!(
+                "{} upgraded, {} downgraded, {} added, {} removed, {} unchanged{}",
+                count(ChangeKind::Upgraded),
+                count(ChangeKind::Downgraded),
+                count(ChangeKind::Added),
+                count(ChangeKind::Removed),
+                count(ChangeKind::Unchanged),
+                extra
+            );
+            if !differences.is_empty() {
+                return Err(WdmError::AlreadyReported);
+            }
+            Ok(())
+        }
+        Commands::Check {
+            hashes,
+            strict,
+            env,
+            ..
+        } => {
+            // Under --strict, conflicts are problems instead of warnings
+            MANIFEST_CONFLICTS_REPORTED.store(*strict, Ordering::Relaxed);
+            // A read-only subset of install: nothing is resolved, downloaded, or created
+            let config = load_config()?;
+            let lockfile = load_lockfile()?;
+            let root_dir = manifest_root()?;
+            let (wordpress_path, _) = resolve_wordpress_path(&config.config, &root_dir, None);
+            let dependencies = config.selected_dependencies(true);
+
+            let mut problems = lock_problems(&config, &dependencies, &lockfile);
+            problems.extend(orphaned_lock_entries(&dependencies, &lockfile));
+            if *strict {
+                problems.extend(manifest_conflicts(&config, &wordpress_path));
+            }
+
+            // wdm.lock covers every environment, but only this one's dependencies are installed
+            let env = active_env(env.as_deref());
+            let dependencies: Vec<&Dependency> = dependencies
+                .into_iter()
+                .filter(|dep| dep.applies_to(env.as_deref()))
+                .collect();
+            let cache = cache_location(&config.config, &root_dir)?;
+            for dep in &dependencies {
+                let Some(locked) = find_locked(&lockfile, dep) else {
+                    continue;
+                };
+                if let Some(integrity) = &dep.integrity {
+                    if integrity_digest(integrity).as_deref() != Some(locked.hash.as_str()) {
+                        problems.push(CheckProblem::new(
+                            ProblemKind::IntegrityMismatch,
+                            &dep.name,
+                            format!(
+                                "The integrity of {} in wdm.yml does not match the hash wdm.lock records for {}",
+                                dep.name,
+                                display_version(&locked.version)
+                            ),
+                        ));
+                    }
+                }
+                for slug in &locked.requires_plugins {
+                    let installed = wordpress_path
+                        .join(DependencyType::Plugin.content_dir())
+                        .join(slug)
+                        .is_dir()
+                        || dependencies.iter().any(|d| {
+                            same_name(&d.name, slug) && d.install_dir(&wordpress_path).is_dir()
+                        });
+                    if !installed {
+                        problems.push(CheckProblem::new(
+                            ProblemKind::MissingRequirement,
+                            &dep.name,
+                            format!(
+                                "'{}' requires the plugin '{}', which is not installed",
+                                dep.name, slug
+                            ),
+                        ));
+                    }
+                }
+                let plugin_install_dir = dep.install_dir(&wordpress_path);
+                if !plugin_install_dir.is_dir() {
+                    problems.push(CheckProblem::new(
+                        ProblemKind::NotInstalled,
+                        &dep.name,
+                        format!(
+                            "'{}' is not installed: {:?} does not exist",
+                            dep.name, plugin_install_dir
+                        ),
+                    ));
+                } else {
+                    match InstallMarker::read(&plugin_install_dir) {
+                        Some(marker) if same_name(&marker.name, &dep.name) => {
+                            if marker.version != locked.version || marker.hash != locked.hash {
+                                problems.push(CheckProblem::new(
+                                    ProblemKind::MarkerMismatch,
+                                    &dep.name,
+                                    format!(
+                                        "{:?} holds {} {}, but wdm.lock has {}",
+                                        plugin_install_dir,
+                                        dep.name,
+                                        display_version(&marker.version),
+                                        display_version(&locked.version)
+                                    ),
+                                ));
+                            }
+                        }
+                        _ => problems.push(CheckProblem::new(
+                            ProblemKind::Unmanaged,
+                            &dep.name,
+                            format!(
+                                "{:?} was not installed by wdm: it has no {} for '{}'. Run 'wdm install --force {}' to replace it.",
+                                plugin_install_dir, INSTALL_MARKER, dep.name, dep.name
+                            ),
+                        )),
+                    }
+                }
+
+                if *hashes {
+                    let archive = cache.archive_path(&dep.name, &dep.origin(), &locked.version);
+                    let version = display_version(&locked.version);
+                    if !archive.exists() {
+                        problems.push(CheckProblem::new(
+                            ProblemKind::NotCached,
+                            &dep.name,
+                            format!(
+                                "No cached archive of {} {} at {:?}",
+                                dep.name, version, archive
+                            ),
+                        ));
+                    } else if hash_file(&archive)? != locked.hash {
+                        problems.push(CheckProblem::new(
+                            ProblemKind::CacheMismatch,
+                            &dep.name,
+                            format!(
+                                "The cached archive of {} {} does not match the hash in wdm.lock",
+                                dep.name, version
+                            ),
+                        ));
+                    }
+                }
+            }
+
+            report_problems(&problems, "wdm.yml, wdm.lock, and wp-content agree")
+        }
+        Commands::Lock { check: true, .. } => {
+            // Like check, this never resolves, downloads, or creates anything
+            let config = load_config()?;
+            let lockfile = load_lockfile()?;
+            let dependencies = config.selected_dependencies(true);
+            let mut problems = lock_problems(&config, &dependencies, &lockfile);
+            problems.extend(orphaned_lock_entries(&dependencies, &lockfile));
+            report_problems(&problems, "wdm.lock is up to date with wdm.yml")
+        }
+        Commands::Lock {
+            check: false,
+            jobs,
+            strict_compat,
+            strict,
+        } => {
+            let (config, _lock) = load_config_locked()?;
+            configure_http(&config.config, None);
+            let mut ctx = InstallContext::for_lock(&config, load_lockfile()?)?;
+            ctx.strict_compat = *strict_compat;
+            ctx.strict_headers = *strict;
+            let dependencies = config.selected_dependencies(true);
+
+            // Locked versions that still satisfy wdm.yml are kept, like install does,
+            // and downloaded again only when they aren't cached
+            let fetched = parallel_map(&dependencies, jobs.unwrap_or_else(default_jobs), |dep| {
+                fetch_dependency(
+                    dep,
+                    &ctx.lockfile,
+                    &ctx.wordpress_path,
+                    &ctx.cache,
+                    true,
+                    true,
+                )
+            });
+            let mut reports = Vec::new();
+            let mut failed = 0;
+            for (dep, fetched) in dependencies.iter().copied().zip(fetched) {
+                match lock_one(&mut ctx, dep, fetched) {
+                    Ok((version, hash)) => {
+                        progress!("Locked {} {}", dep.name, display_version(&version));
+                        reports.push(json!({
+                            "name": dep.name,
+                            "version": version,
+                            "hash": hash,
+                        }));
+                    }
+                    Err(e) => {
+                        error!("{}", e);
+                        failed += 1;
+                        reports.push(json!({
+                            "name": dep.name,
+                            "error": e.to_string(),
+                        }));
+                    }
+                }
+            }
+            // A complete lock describes exactly what wdm.yml lists
+            ctx.lockfile.dependencies.retain(|locked| {
+                let listed = dependencies
+                    .iter()
+                    .any(|d| same_name(&d.name, &locked.name));
+                if !listed {
+                    progress!("Removed {} from wdm.lock", locked.name);
+                }
+                listed
+            });
+
+            let changed = ctx.save_lockfile()?;
+            report_lockfile_write(&ctx.lockfile_path(), changed);
+            summary!("{} locked, {} failed", dependencies.len() - failed, failed);
+            if json_output() {
+                print_json(&reports);
+            }
+            if failed > 0 {
+                return Err(WdmError::AlreadyReported);
+            }
+            Ok(())
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(*shell, &mut Cli::command(), "wdm", &mut std::io::stdout());
+            Ok(())
+        }
+        Commands::Import { path, dry_run } => {
+            let contents = fs::read_to_string(path).map_err(|e| {
+                WdmError::InvalidArgument(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+            let composer: serde_json::Value =
+                serde_json::from_str(&contents).map_err(|source| WdmError::ComposerParse {
+                    path: path.clone(),
+                    source,
+                })?;
+
+            // Without a manifest anywhere above, start one in the current directory
+            let manifest_path = match find_manifest() {
+                Err(WdmError::ManifestNotFound) => PathBuf::from("wdm.yml"),
+                found => found?,
+            };
+            let _lock = if *dry_run {
+                None
+            } else {
+                Some(lock_project(manifest_dir(&manifest_path))?)
+            };
+            let mut config = if manifest_path.exists() {
+                load_config()?
+            } else {
+                Config::new(".".to_string())
+            };
+
+            let mut names: Vec<String> = config
+                .selected_dependencies(true)
+                .iter()
+                .map(|d| normalize_name(&d.name))
+                .collect();
+            let mut imported = Vec::new();
+            let mut duplicates = 0;
+            let mut unmapped = Vec::new();
+            for (section, dev) in [("require", false), ("require-dev", true)] {
+                let Some(packages) = composer.get(section).and_then(|r| r.as_object()) else {
+                    continue;
+                };
+                for (package, constraint) in packages {
+                    let constraint = constraint.as_str().unwrap_or_default();
+                    if is_composer_platform_package(package) {
+                        verbose!("Skipping {}: it is not a WordPress package", package);
+                        continue;
+                    }
+                    let dependency = match composer_dependency(package, constraint) {
+                        Ok(dependency) => dependency,
+                        Err(reason) => {
+                            unmapped.push((package.clone(), constraint.to_string(), reason));
+                            continue;
+                        }
+                    };
+                    let normalized_name = normalize_name(&dependency.name);
+                    if names.contains(&normalized_name) {
+                        progress!("Skipping {}: it is already in wdm.yml", dependency.name);
+                        duplicates += 1;
+                        continue;
+                    }
+                    names.push(normalized_name);
+
+                    let kind = if dependency.kind == Some(DependencyType::Theme) {
+                        "theme"
+                    } else {
+                        "plugin"
+                    };
+                    progress!(
+                        "{} {} {} ({}{})",
+                        if *dry_run { "Would add" } else { "Added" },
+                        dependency.name,
+                        dependency.version,
+                        kind,
+                        if dev { ", dev" } else { "" }
+                    );
+                    imported.push((dependency, dev));
+                }
+            }
+
+            for (package, constraint, reason) in &unmapped {
+                let name = package.rsplit('/').next().unwrap_or(package);
+                let version = composer_constraint(constraint).unwrap_or_else(|_| "latest".into());
+                warning!(
+                    "Could not import {} {}: {}. Add it with: wdm add {} --version '{}' --repo {}",
+                    package,
+                    constraint,
+                    reason,
+                    name,
+                    version,
+                    package
+                );
+            }
+
+            if json_output() {
+                let dependencies: Vec<&Dependency> = imported.iter().map(|(d, _)| d).collect();
+                print_json(&dependencies);
+            }
+            if !*dry_run && !imported.is_empty() {
+                for (dependency, dev) in imported.iter().cloned() {
+                    if dev {
+                        config.dev_dependencies.push(dependency);
+                    } else {
+                        config.dependencies.push(dependency);
+                    }
+                }
+                write_manifest(&manifest_path, &mut config)?;
+            }
+            summary!(
+                "{} imported, {} already in wdm.yml, {} not imported",
+                imported.len(),
+                duplicates,
+                unmapped.len()
+            );
+            if !*dry_run && !imported.is_empty() {
+                progress!("Run 'wdm install' to install them");
+            }
+
+            Ok(())
+        }
+        Commands::Adopt { repos, yes } => {
+            let mapping: BTreeMap<String, String> = match repos {
+                Some(path) => {
+                    let contents = fs::read_to_string(path).map_err(|e| {
+                        WdmError::InvalidArgument(format!(
+                            "Failed to read {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                    serde_yaml::from_str(&contents).map_err(|e| {
+                        WdmError::InvalidArgument(format!(
+                            "{} must map plugin directories to owner/repo: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?
+                }
+                None => BTreeMap::new(),
+            };
+            let (mut config, _lock) = load_config_locked()?;
+            let (wordpress_path, _) =
+                resolve_wordpress_path(&config.config, &manifest_root()?, None);
+            let manifest_path = find_manifest()?;
+            let plugins_dir = wordpress_path.join(DependencyType::Plugin.content_dir());
+            let entries = fs::read_dir(&plugins_dir).map_err(|e| {
+                WdmError::InvalidArgument(format!("Failed to read {:?}: {}", plugins_dir, e))
+            })?;
+            let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+            paths.sort();
+
+            let listed = config.selected_dependencies(true);
+            let interactive = std::io::stdin().is_terminal();
+            let mut adopted = Vec::new();
+            let mut declined = 0;
+            let mut duplicates = 0;
+            let mut unmatched = Vec::new();
+            for path in paths {
+                let Some(slug) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                    continue;
+                };
+                // Skip wdm's own staging directories and files such as index.php
+                if slug.starts_with('.') {
+                    continue;
+                }
+                if path.is_file() {
+                    let header = read_header_block(&path)
+                        .filter(|h| file_header(h, "Plugin Name").is_some());
+                    if let (Some(header), Some(name)) = (header, slug.strip_suffix(".php")) {
+                        unmatched.push(AdoptCandidate::unmatched(
+                            name,
+                            file_header(&header, "Version"),
+                            "it is a single-file plugin, which wdm can only install from a directory",
+                        ));
+                    }
+                    continue;
+                }
+                if listed
+                    .iter()
+                    .any(|d| same_name(&d.name, &slug) || d.install_dir(&wordpress_path) == path)
+                {
+                    verbose!("Skipping {}: it is already in wdm.yml", slug);
+                    duplicates += 1;
+                    continue;
+                }
+
+                let headers = read_package_headers(&path, DependencyType::Plugin);
+                let (Some(_), Some(version)) = (&headers.name, &headers.version) else {
+                    let reason = if headers.name.is_none() {
+                        "it has no Plugin Name header"
+                    } else {
+                        "its header has no Version"
+                    };
+                    unmatched.push(AdoptCandidate::unmatched(&slug, headers.version, reason));
+                    continue;
+                };
+                let dependency = match adopted_dependency(&slug, version, &mapping) {
+                    Ok(dependency) => dependency,
+                    Err(reason) => {
+                        unmatched.push(AdoptCandidate::unmatched(
+                            &slug,
+                            Some(version.clone()),
+                            &reason.to_string(),
+                        ));
+                        continue;
+                    }
+                };
+                let origin = if dependency.repo.is_empty() {
+                    "WordPress.org".to_string()
+                } else {
+                    dependency.repo.clone()
+                };
+                if !*yes
+                    && interactive
+                    && !confirm(&format!(
+                        "Add {} {} from {} to wdm.yml?",
+                        slug, dependency.version, origin
+                    ))?
+                {
+                    declined += 1;
+                    continue;
+                }
+                progress!(
+                    "{} {} {} from {}",
+                    if *yes || interactive {
+                        "Adopted"
+                    } else {
+                        "Would adopt"
+                    },
+                    slug,
+                    dependency.version,
+                    origin
+                );
+                adopted.push(dependency);
+            }
+
+            for candidate in &unmatched {
+                warning!(
+                    "Could not match {}: {}. Add it with: wdm add {} --version '{}' --repo <owner/repo> --no-install",
+                    candidate.name,
+                    candidate.reason,
+                    candidate.name,
+                    candidate.version.as_deref().unwrap_or("latest")
+                );
+            }
+
+            let write = (*yes || interactive) && !adopted.is_empty();
+            if json_output() {
+                print_json(&json!({
+                    "adopted": adopted,
+                    "unmatched": unmatched,
+                    "written": write,
+                }));
+            }
+            if write {
+                config.dependencies.extend(adopted.iter().cloned());
+                write_manifest(&manifest_path, &mut config)?;
+            }
+            summary!(
+                "{} adopted, {} already in wdm.yml, {} not matched{}",
+                adopted.len(),
+                duplicates,
+                unmatched.len(),
+                if declined > 0 {
+                    format!(", {} declined", declined)
+                } else {
+                    String::new()
+                }
+            );
+            if !*yes && !interactive && !adopted.is_empty() {
+                progress!("Run 'wdm adopt --yes' to add them to wdm.yml");
+            } else if write {
+                progress!("Run 'wdm install --force' to let wdm manage their directories");
+            }
+
+            Ok(())
+        }
+        Commands::Export {
+            format,
+            output,
+            locked,
+        } => {
+            let config = load_config()?;
+            let lockfile = load_lockfile()?;
+            let document = match format {
+                ExportFormat::Composer => composer_manifest(&config, &lockfile, *locked)?,
+                ExportFormat::Json => json!({ "manifest": config, "lock": lockfile }),
+            };
+            let contents = serde_json::to_string_pretty(&document)
+                .expect("JSON output is always serializable");
+            match output {
+                Some(path) => {
+                    fs::write(path, contents + "\n")?;
+                    summary!("Wrote {}", path.display());
+                }
+                None => println!("{}", contents),
+            }
+            Ok(())
+        }
+        Commands::Freeze { only, dry_run } => {
+            let (mut config, _lock) = if *dry_run {
+                (load_config()?, None)
+            } else {
+                let (config, lock) = load_config_locked()?;
+                (config, Some(lock))
+            };
+            let lockfile = load_lockfile()?;
+            let names: Vec<String> = select_by_name(config.selected_dependencies(true), only)?
+                .into_iter()
+                .map(|d| d.name.clone())
+                .collect();
+
+            // Everything must be locked before wdm.yml is touched
+            let mut pins = Vec::new();
+            let mut unlocked = Vec::new();
+            for dep in config
+                .dependencies
+                .iter_mut()
+                .chain(config.dev_dependencies.iter_mut())
+                .filter(|d| names.contains(&d.name))
+            {
+                if is_frozen(dep) {
+                    continue;
+                }
+                let Some(locked) = find_locked(&lockfile, dep) else {
+                    unlocked.push(dep.name.clone());
+                    continue;
+                };
+                let pin = frozen_version(dep, &locked.version);
+                pins.push(json!({ "name": dep.name, "from": dep.version, "to": pin }));
+                dep.version = pin;
+            }
+            if !unlocked.is_empty() {
+                return Err(WdmError::InvalidArgument(format!(
+                    "{} {} no entry in wdm.lock to pin to. Run 'wdm install' or 'wdm lock' first.",
+                    unlocked.join(", "),
+                    if unlocked.len() == 1 { "has" } else { "have" }
+                )));
+            }
+
+            let manifest_path = find_manifest()?;
+            for pin in &pins {
+                if *dry_run && !json_output() {
+                    println!(" - name: {}", pin["name"].as_str().unwrap_or_default());
+                    println!("-  version: {}", pin["from"].as_str().unwrap_or_default());
+                    println!("+  version: {}", pin["to"].as_str().unwrap_or_default());
+                } else {
+                    progress!(
+                        "Pinned {}: {} -> {}",
+                        pin["name"].as_str().unwrap_or_default(),
+                        pin["from"].as_str().unwrap_or_default(),
+                        pin["to"].as_str().unwrap_or_default()
+                    );
+                }
+            }
+            if !pins.is_empty() && !*dry_run {
+                write_manifest(&manifest_path, &mut config)?;
+            }
+            match (pins.len(), *dry_run) {
+                (0, _) => summary!("Every dependency is already pinned"),
+                (count, true) => summary!(
+                    "Would pin {} {} in {}",
+                    count,
+                    if count == 1 {
+                        "dependency"
+                    } else {
+                        "dependencies"
+                    },
+                    manifest_path.display()
+                ),
+                (count, false) => summary!(
+                    "Pinned {} {} in {}",
+                    count,
+                    if count == 1 {
+                        "dependency"
+                    } else {
+                        "dependencies"
+                    },
+                    manifest_path.display()
+                ),
+            }
+            if json_output() {
+                print_json(&json!({
+                    "pinned": pins,
+                    "written": !pins.is_empty() && !*dry_run,
+                }));
+            }
+            Ok(())
+        }
+        Commands::Validate => {
+            load_config()?;
+            let path = find_manifest()?;
+            summary!("{} is valid", path.display());
+            if json_output() {
+                print_json(&json!({ "valid": true, "path": path }));
+            }
+            Ok(())
+        }
+        Commands::Doctor => {
+            // Every check runs even when an earlier one fails, so one run lists everything to fix
+            let mut checks = Vec::new();
+            let config = match find_manifest() {
+                Ok(path) => {
+                    let (check, config) = doctor_manifest(&path);
+                    checks.push(check);
+                    config
+                }
+                Err(e) => {
+                    checks.push(DoctorCheck::fail("wdm.yml", e.to_string(), None));
+                    None
+                }
+            };
+            if let Some(config) = &config {
+                let root_dir = manifest_root()?;
+                configure_http(&config.config, None);
+                checks.push(doctor_lockfile(&root_dir.join("wdm.lock")));
+                let (wordpress_path, _) = resolve_wordpress_path(&config.config, &root_dir, None);
+                checks.push(doctor_wordpress_path(&wordpress_path));
+                checks.push(doctor_content_dir(&wordpress_path));
+                checks.push(doctor_cache(
+                    &cache_location(&config.config, &root_dir)?.dir,
+                ));
+                checks.extend(doctor_tokens(config));
+            }
+            checks.insert(
+                0,
+                doctor_git(config.as_ref().and_then(|c| c.config.resolver)),
+            );
+            checks.push(doctor_network(&github_base_url()));
+
+            if json_output() {
+                print_json(&checks);
+            } else {
+                for check in &checks {
+                    println!("{:<4} {}: {}", check.status, check.name, check.message);
+                    if let Some(hint) = &check.hint {
+                        println!("     hint: {}", hint);
+                    }
+                }
+            }
+            let count = |status| checks.iter().filter(|c| c.status == status).count();
+            let failed = count(DoctorStatus::Fail);
+            summary!(
+                "{} passed, {} warnings, {} failed",
+                count(DoctorStatus::Ok),
+                count(DoctorStatus::Warn),
+                failed
+            );
+            if failed > 0 {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+        Commands::List { missing, env } => {
+            // Like outdated, this only reads: it never creates .wdm-cache
+            let config = load_config()?;
+            let lockfile = load_lockfile()?;
+            let (wordpress_path, _) =
+                resolve_wordpress_path(&config.config, &manifest_root()?, None);
+            // Without an environment every dependency is listed, whichever ones it is for
+            let env = active_env(env.as_deref());
+
+            let mut listed: Vec<ListedDependency> = config
+                .selected_dependencies(true)
+                .into_iter()
+                .filter(|dep| env.is_none() || dep.applies_to(env.as_deref()))
+                .map(|dep| {
+                    let path = dep.install_dir(&wordpress_path);
+                    let locked = lockfile
+                        .dependencies
+                        .iter()
+                        .find(|d| same_name(&d.name, &dep.name));
+                    ListedDependency {
+                        name: dep.name.clone(),
+                        required: Some(dep.version.clone()),
+                        locked: locked.map(|d| d.version.clone()),
+                        requires_wp: locked.and_then(|d| d.requires_wp.clone()),
+                        requires_php: locked.and_then(|d| d.requires_php.clone()),
+                        status: if path.is_dir() {
+                            ListedStatus::Installed
+                        } else {
+                            ListedStatus::Missing
+                        },
+                        path,
+                        dev: config.is_dev(&dep.name),
+                        from: config.inherited_from(&dep.name).map(str::to_string),
+                    }
+                })
+                .collect();
+            for locked in &lockfile.dependencies {
+                let in_manifest = config
+                    .selected_dependencies(true)
+                    .iter()
+                    .any(|d| same_name(&d.name, &locked.name));
+                if in_manifest {
+                    continue;
+                }
+                listed.push(ListedDependency {
+                    name: locked.name.clone(),
+                    required: None,
+                    locked: Some(locked.version.clone()),
+                    requires_wp: locked.requires_wp.clone(),
+                    requires_php: locked.requires_php.clone(),
+                    status: ListedStatus::Orphaned,
+                    path: locked.install_dir(&wordpress_path),
+                    dev: locked.dev,
+                    from: None,
+                });
+            }
+            if *missing {
+                listed.retain(|d| d.status == ListedStatus::Missing);
+            }
+
+            if json_output() {
+                print_json(&listed);
+            } else if listed.is_empty() {
+                summary!(
+                    "{}",
+                    if *missing {
+                        "No dependencies are missing"
+                    } else {
+                        "wdm.yml has no dependencies"
+                    }
+                );
+            } else {
+                // Most plugins declare no requirements, so the column only appears when one does
+                let show_requires = listed
+                    .iter()
+                    .any(|d| d.requires_wp.is_some() || d.requires_php.is_some());
+                let show_from = listed.iter().any(|d| d.from.is_some());
+                let rows: Vec<Vec<String>> = listed
+                    .iter()
+                    .map(|d| {
+                        let mut row = vec![
+                            d.name.clone(),
+                            d.required.clone().unwrap_or_else(|| "-".to_string()),
+                            d.locked
+                                .as_deref()
+                                .map(display_version)
+                                .unwrap_or("-")
+                                .to_string(),
+                        ];
+                        if show_requires {
+                            row.push(d.requires());
+                        }
+                        row.push(d.status.to_string());
+                        if show_from {
+                            row.push(d.from.clone().unwrap_or_else(|| "-".to_string()));
+                        }
+                        row
+                    })
+                    .collect();
+                let mut headers = vec!["Name", "Required", "Locked"];
+                if show_requires {
+                    headers.push("Requires");
+                }
+                headers.push("Status");
+                if show_from {
+                    headers.push("From");
+                }
+                print_table(&headers, &rows);
+            }
+
+            if *missing && !listed.is_empty() {
+                return Err(WdmError::AlreadyReported);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// The result of resolving and downloading a dependency, before anything is written to disk.
+enum FetchOutcome {
+    Downloaded {
+        version: String,
+        /// The hash recorded in wdm.lock when the locked version was used
+        locked_hash: Option<String>,
+        archive: PathBuf,
+        hash: String,
+        /// Where the archive was downloaded from, unless it came from the cache
+        resolved_url: Option<String>,
+        /// Whether `archive` was already in the cache instead of freshly downloaded
+        cached: bool,
+        /// How long resolving and downloading took
+        elapsed: Duration,
+    },
+    /// The install directory already holds the locked version, so installing
+    /// is skipped, with the message saying so
+    Skipped(String),
+    /// Nothing newer to install, with the message explaining why
+    UpToDate(String),
+    /// The version could not be resolved or the archive downloaded
+    Failed(WdmError),
+}
+
+/// Resolves the version of `dep` to install and downloads its archive.
+///
+/// With `use_cache`, a cached archive matching the locked hash is used instead
+/// of downloading it again. This only reads `lockfile`, the cache and the
+/// install directory, so it is safe to run for several dependencies at once.
+fn fetch_dependency(
+    dep: &Dependency,
+    lockfile: &Lockfile,
+    wordpress_path: &Path,
+    cache: &Cache,
+    use_cache: bool,
+    force: bool,
+) -> FetchOutcome {
+    let started = Instant::now();
+    let token = dependency_token(dep);
+
+    // Prefer the locked version as long as it still satisfies wdm.yml
+    let locked = find_locked(lockfile, dep);
+    let version = match &locked {
+        Some(locked) => locked.version.clone(),
+        None => match resolve_dependency_version(dep, &dep.version, token.as_deref()) {
+            Ok(ver) => ver,
+            Err(e) => return FetchOutcome::Failed(e),
+        },
+    };
+
+    if let Some(skipped) = skip_if_installed(dep, &version, lockfile, wordpress_path, force) {
+        return skipped;
+    }
+
+    // Identical bytes are already on disk, so skip the network entirely. A
+    // shared cache may also hold what another project downloaded before this
+    // one locked it.
+    if let Some((archive, hash)) = locked.as_ref().and_then(|l| cache.find_vendored(l)) {
+        return FetchOutcome::Downloaded {
+            version,
+            locked_hash: Some(hash.clone()),
+            archive,
+            hash,
+            resolved_url: None,
+            cached: true,
+            elapsed: started.elapsed(),
+        };
+    }
+    let archive = cache.archive_path(&dep.name, &dep.origin(), &version);
+    // Older releases could cache an error page under the archive's name
+    if archive.exists()
+        && dep.archive_format.is_none()
+        && ArchiveFormat::detect(&read_start(&archive, 4)).is_none()
+    {
+        warning!(
+            "Removed {:?} from the cache because it is not an archive",
+            archive
+        );
+        let _ = fs::remove_file(&archive);
+    }
+    // --no-cache downloads everything in full, without asking whether it changed
+    if !use_cache {
+        CacheValidators::forget(&archive);
+    }
+    if use_cache && archive.exists() {
+        if let Ok(hash) = hash_file(&archive) {
+            let locked_hash = locked.as_ref().map(|locked| locked.hash.clone());
+            let reusable = match &locked_hash {
+                Some(expected) => hash == *expected,
+                None => cache.shared,
+            };
+            if reusable {
+                return FetchOutcome::Downloaded {
+                    version,
+                    locked_hash,
+                    archive,
+                    hash,
+                    resolved_url: None,
+                    cached: true,
+                    elapsed: started.elapsed(),
+                };
+            }
+        }
+    }
+
+    match download_dependency(dep, &version, token.as_deref(), cache) {
+        Ok((archive, hash, resolved_url)) => FetchOutcome::Downloaded {
+            version,
+            locked_hash: locked.map(|locked| locked.hash),
+            archive,
+            hash,
+            resolved_url: Some(resolved_url),
+            cached: false,
+            elapsed: started.elapsed(),
+        },
+        Err(e) => FetchOutcome::Failed(e),
+    }
+}
+
+/// Works out what `fetch_dependency` and the install after it would do with
+/// `dep`, resolving versions the same way but never downloading anything.
+fn plan_dependency(
+    dep: &Dependency,
+    lockfile: &Lockfile,
+    wordpress_path: &Path,
+    force: bool,
+) -> PlannedInstall {
+    let mut planned = PlannedInstall {
+        name: dep.name.clone(),
+        action: PlannedAction::Install,
+        from: None,
+        to: None,
+        error: None,
+    };
+
+    let version = match planned_version(dep, lockfile) {
+        Ok(ver) => ver,
+        Err(e) => {
+            error!("{}", e);
+            planned.action = PlannedAction::Fail;
+            planned.error = Some(e.to_string());
+            return planned;
+        }
+    };
+
+    // Only a version that is actually on disk can be skipped or upgraded
+    let installed = lockfile
+        .dependencies
+        .iter()
+        .find(|d| same_name(&d.name, &dep.name))
+        .filter(|_| dep.install_dir(wordpress_path).exists())
+        .map(|d| d.version.clone());
+    planned.action = match &installed {
+        Some(installed) if *installed == version && !force => PlannedAction::Skip,
+        Some(_) => PlannedAction::Upgrade,
+        None => PlannedAction::Install,
+    };
+    if planned.action == PlannedAction::Upgrade {
+        planned.from = installed;
+    }
+    planned.to = Some(version);
+    planned
+}
+
+/// Returns the version installing `dep` would use: the locked one while it
+/// still satisfies wdm.yml, or else a fresh resolution. Nothing is downloaded.
+fn planned_version(dep: &Dependency, lockfile: &Lockfile) -> Result<String, WdmError> {
+    match find_locked(lockfile, dep) {
+        Some(locked) => Ok(locked.version),
+        None => {
+            let token = dependency_token(dep);
+            resolve_dependency_version(dep, &dep.version, token.as_deref())
+        }
+    }
+}
+
+/// Works out how resolving wdm.yml, the way `install` would, changes each
+/// entry of wdm.lock, for `wdm diff`. Only tags are looked up; nothing is
+/// downloaded and the WordPress path isn't looked at.
+fn lock_changes(config: &Config, lockfile: &Lockfile) -> Vec<LockChange> {
+    let dependencies = config.selected_dependencies(true);
+    let resolved = parallel_map(&dependencies, default_jobs(), |dep| {
+        planned_version(dep, lockfile)
+    });
+
+    let mut changes = Vec::new();
+    for (dep, resolved) in dependencies.iter().zip(resolved) {
+        let locked = lockfile
+            .dependencies
+            .iter()
+            .find(|d| same_name(&d.name, &dep.name));
+        let (change, to, error) = match resolved {
+            Err(e) => {
+                error!("{}", e);
+                (ChangeKind::Failed, None, Some(e.to_string()))
+            }
+            Ok(version) => {
+                let change = match locked {
+                    None => ChangeKind::Added,
+                    Some(_) if find_locked(lockfile, dep).is_some() => ChangeKind::Unchanged,
+                    Some(locked) => match (
+                        parse_loose_version(&locked.version),
+                        parse_loose_version(&version),
+                    ) {
+                        (Some(old), Some(new)) if new > old => ChangeKind::Upgraded,
+                        (Some(old), Some(new)) if new < old => ChangeKind::Downgraded,
+                        _ => ChangeKind::Changed,
+                    },
+                };
+                (change, Some(version), None)
+            }
+        };
+        changes.push(LockChange {
+            name: dep.name.clone(),
+            change,
+            from: locked.map(|d| d.version.clone()),
+            to,
+            error,
+        });
+    }
+    changes.extend(
+        lockfile
+            .dependencies
+            .iter()
+            .filter(|locked| {
+                !dependencies
+                    .iter()
+                    .any(|d| same_name(&d.name, &locked.name))
+            })
+            .map(|locked| LockChange {
+                name: locked.name.clone(),
+                change: ChangeKind::Removed,
+                from: Some(locked.version.clone()),
+                to: None,
+                error: None,
+            }),
+    );
+    changes
+}
+
+/// Looks up the locked version of `dep` in the vendor directory or
+/// `.wdm-cache` instead of downloading it.
+///
+/// Fails, with a message naming the dependency, when wdm.lock has no usable
+/// entry or the cached archive is missing or doesn't match the locked hash.
+fn fetch_cached_dependency(
+    dep: &Dependency,
+    lockfile: &Lockfile,
+    wordpress_path: &Path,
+    cache: &Cache,
+    force: bool,
+) -> Result<FetchOutcome, WdmError> {
+    let locked = find_locked(lockfile, dep).ok_or_else(|| WdmError::NotLocked {
+        dep: dep.name.clone(),
+        version_req: dep.version.clone(),
+    })?;
+    let (archive, hash) = match cache.find_vendored(&locked) {
+        Some(vendored) => vendored,
+        None => {
+            let archive = cache.archive_path(&dep.name, &dep.origin(), &locked.version);
+            if !archive.exists() {
+                return Err(WdmError::NotCached {
+                    dep: dep.name.clone(),
+                    path: archive,
+                });
+            }
+            let hash = hash_file(&archive)?;
+            if hash != locked.hash {
+                return Err(WdmError::CacheMismatch {
+                    dep: dep.name.clone(),
+                    path: archive,
+                });
+            }
+            (archive, hash)
+        }
+    };
+
+    Ok(
+        skip_if_installed(dep, &locked.version, lockfile, wordpress_path, force).unwrap_or(
+            FetchOutcome::Downloaded {
+                version: locked.version,
+                locked_hash: Some(locked.hash),
+                archive,
+                hash,
+                resolved_url: None,
+                cached: true,
+                elapsed: Duration::ZERO,
+            },
+        ),
+    )
+}
+
+/// Skips `dep` only if its install directory already holds `version`, unless
+/// `force` asks for it to be replaced anyway.
+fn skip_if_installed(
+    dep: &Dependency,
+    version: &str,
+    lockfile: &Lockfile,
+    wordpress_path: &Path,
+    force: bool,
+) -> Option<FetchOutcome> {
+    if force {
+        return None;
+    }
+    let plugin_install_dir = dep.install_dir(wordpress_path);
+    // A different subdirectory of the same tag is a different plugin
+    let installed_version = lockfile
+        .dependencies
+        .iter()
+        .find(|d| same_name(&d.name, &dep.name) && d.path == dep.path)
+        .map(|d| d.version.as_str());
+    (plugin_install_dir.exists() && installed_version == Some(version)).then(|| {
+        FetchOutcome::Skipped(format!(
+            "{} is already installed in {:?}",
+            dep.name, plugin_install_dir
+        ))
+    })
+}
+
+/// Returns the sha256 hex digest of the file at `path`.
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Runs `f` over `items` on up to `jobs` threads and returns the results in input order.
+fn parallel_map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                let result = f(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is processed"))
+        .collect()
+}
+
+/// Returns the default number of parallel jobs: the CPU count, or 4 if it is unknown.
+fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+/// Installs the dependencies `wdm add` just wrote to wdm.yml.
+///
+/// wdm.yml and wdm.lock are read once, the archives are downloaded in
+/// parallel, and wdm.lock is written once at the end. A dependency that fails
+/// doesn't stop the others: each one's report comes back with its error, if any.
+fn install_dependencies(
+    deps: &[&Dependency],
+    timeout: Option<u64>,
+    strict_compat: bool,
+    strict_headers: bool,
+    with_requirements: bool,
+) -> Result<Vec<(InstallReport, Option<WdmError>)>, WdmError> {
+    let config = load_config()?;
+    configure_http(&config.config, timeout);
+    let mut ctx = InstallContext::new(&config, load_lockfile()?, None)?;
+    ctx.strict_compat = strict_compat;
+    ctx.strict_headers = strict_headers;
+    ctx.with_requirements = with_requirements;
+
+    let fetched = parallel_map(deps, default_jobs(), |dep| {
+        fetch_dependency(
+            dep,
+            &ctx.lockfile,
+            &ctx.wordpress_path,
+            &ctx.cache,
+            true,
+            false,
+        )
+    });
+    let mut results = Vec::new();
+    for (dep, fetched) in deps.iter().copied().zip(fetched) {
+        progress!("Installing {}...", dep.name);
+        let outcome = install_one(&mut ctx, dep, fetched);
+        if let Ok(InstallOutcome::Installed {
+            version,
+            size,
+            elapsed,
+            ..
+        }) = &outcome
+        {
+            progress!(
+                "Installed {} {} ({})",
+                dep.name,
+                version,
+                describe_transfer(*size, *elapsed)
+            );
+        }
+        results.push((ctx.report(dep, &outcome), outcome.err()));
+    }
+    if results
+        .iter()
+        .any(|(report, _)| report.status == InstallStatus::Installed)
+    {
+        let changed = ctx.save_lockfile()?;
+        report_lockfile_write(&ctx.lockfile_path(), changed);
+    }
+
+    Ok(results)
+}
+
+/// How many levels of `Requires Plugins` `--with-requirements` follows, so a
+/// long chain of requirements can't keep adding plugins.
+const MAX_REQUIREMENT_DEPTH: usize = 5;
+
+/// Whether the plugin `slug` that another plugin requires is in wdm.yml or
+/// installed.
+fn requirement_met(config: &Config, wordpress_path: &Path, slug: &str) -> bool {
+    let dir = wordpress_path
+        .join(DependencyType::Plugin.content_dir())
+        .join(slug);
+    dir.is_dir()
+        || config
+            .selected_dependencies(true)
+            .iter()
+            .any(|d| same_name(&d.name, slug) || d.install_dir(wordpress_path) == dir)
+}
+
+/// Returns the plugins that the lock entries of `requirers` list in
+/// `Requires Plugins` but that neither wdm.yml nor the plugins directory
+/// has, each with the first dependency requiring it.
+fn missing_requirements(
+    config: &Config,
+    lockfile: &Lockfile,
+    wordpress_path: &Path,
+    requirers: &[String],
+) -> Vec<(String, String)> {
+    let mut missing: Vec<(String, String)> = Vec::new();
+    for locked in lockfile
+        .dependencies
+        .iter()
+        .filter(|d| requirers.iter().any(|name| same_name(name, &d.name)))
+    {
+        for slug in &locked.requires_plugins {
+            if !requirement_met(config, wordpress_path, slug)
+                && !missing.iter().any(|(missing, _)| missing == slug)
+            {
+                missing.push((slug.clone(), locked.name.clone()));
+            }
+        }
+    }
+    missing
+}
+
+/// Adds the plugins `requirers` need but that are missing to wdm.yml as
+/// WordPress.org dependencies and installs them, then does the same for what
+/// those require in turn. Each plugin is only added once, so requirements
+/// that loop back end there, and at most `MAX_REQUIREMENT_DEPTH` levels are
+/// followed.
+fn install_requirements(
+    mut requirers: Vec<String>,
+    timeout: Option<u64>,
+    strict_compat: bool,
+    strict_headers: bool,
+) -> Result<Vec<(InstallReport, Option<WdmError>)>, WdmError> {
+    let mut added: Vec<String> = Vec::new();
+    let mut results = Vec::new();
+    for depth in 0.. {
+        let mut config = load_config()?;
+        let (wordpress_path, _) = resolve_wordpress_path(&config.config, &manifest_root()?, None);
+        let missing: Vec<(String, String)> =
+            missing_requirements(&config, &load_lockfile()?, &wordpress_path, &requirers)
+                .into_iter()
+                .filter(|(slug, _)| !added.contains(slug))
+                .collect();
+        if missing.is_empty() {
+            break;
+        }
+        let slugs: Vec<String> = missing.iter().map(|(slug, _)| slug.clone()).collect();
+        if depth == MAX_REQUIREMENT_DEPTH {
+            warning!(
+                "Stopped adding required plugins after {} levels of Requires Plugins; still missing: {}",
+                MAX_REQUIREMENT_DEPTH,
+                slugs.join(", ")
+            );
+            break;
+        }
+
+        let deps: Vec<Dependency> = slugs
+            .iter()
+            .map(|slug| Dependency {
+                name: slug.clone(),
+                version: "latest".to_string(),
+                repo: String::new(),
+                token_env: None,
+                source: Some(Source::WordpressOrg),
+                url: None,
+                host: None,
+                asset: None,
+                kind: None,
+                install_path: None,
+                strip_prefix: None,
+                archive_format: None,
+                path: None,
+                hooks: Hooks::default(),
+                allow_prerelease: false,
+                tag_pattern: None,
+                integrity: None,
+                envs: Vec::new(),
+            })
+            .collect();
+        for (slug, requirer) in &missing {
+            progress!(
+                "Added {} from WordPress.org to wdm.yml, which {} requires",
+                slug,
+                requirer
+            );
+        }
+        config.dependencies.extend(deps.iter().cloned());
+        write_manifest(&find_manifest()?, &mut config)?;
+        let deps: Vec<&Dependency> = deps.iter().collect();
+        results.extend(install_dependencies(
+            &deps,
+            timeout,
+            strict_compat,
+            strict_headers,
+            true,
+        )?);
+        added.extend(slugs.iter().cloned());
+        requirers = slugs;
+    }
+    Ok(results)
+}
+
+/// Everything `install_one` needs besides the dependency itself.
+struct InstallContext<'a> {
+    config: &'a Config,
+    /// The directory holding wdm.yml, where wdm.lock is written
+    root_dir: PathBuf,
+    wordpress_path: PathBuf,
+    cache: Cache,
+    /// The lockfile as it will be written, updated after every install
+    lockfile: Lockfile,
+    /// Install archives that don't match the hash in wdm.lock, with a warning
+    insecure_skip_verify: bool,
+    /// The WordPress version from wdm.yml or wp-includes/version.php, if known
+    wordpress_version: Option<String>,
+    /// Refuse dependencies that require a newer WordPress or PHP than the site has
+    strict_compat: bool,
+    /// Refuse plugins whose header doesn't match their directory or version
+    strict_headers: bool,
+    /// Missing `Requires Plugins` are about to be added, so don't warn about them
+    with_requirements: bool,
+}
+
+impl<'a> InstallContext<'a> {
+    /// Prepares the cache and checks the WordPress path for installing into it,
+    /// which `wordpress_path` overrides like `--wordpress-path` does.
+    fn new(
+        config: &'a Config,
+        lockfile: Lockfile,
+        wordpress_path: Option<&Path>,
+    ) -> Result<Self, WdmError> {
+        Self::open(config, lockfile, wordpress_path, true)
+    }
+
+    /// Prepares the cache for `wdm lock`, which never touches the WordPress
+    /// path, so it doesn't have to exist.
+    fn for_lock(config: &'a Config, lockfile: Lockfile) -> Result<Self, WdmError> {
+        Self::open(config, lockfile, None, false)
+    }
+
+    fn open(
+        config: &'a Config,
+        lockfile: Lockfile,
+        wordpress_path: Option<&Path>,
+        installing: bool,
+    ) -> Result<Self, WdmError> {
+        let root_dir = resolve_root_dir()?;
+        let cache = resolve_cache(&config.config, &root_dir)?;
+        migrate_legacy_cache(&cache, &lockfile);
+        let _ = TAG_CACHE.set(TagCache {
+            dir: cache.tags_dir(),
+            ttl: Duration::from_secs(
+                config
+                    .config
+                    .tag_cache_ttl_secs
+                    .unwrap_or(DEFAULT_TAG_CACHE_TTL_SECS),
+            ),
+        });
+
+        let (wordpress_path, origin) =
+            resolve_wordpress_path(&config.config, &root_dir, wordpress_path);
+        if installing {
+            check_wordpress_path(&wordpress_path, origin)?;
+        }
+        let wordpress_version = config
+            .config
+            .wordpress_version
+            .clone()
+            .or_else(|| detect_wordpress_version(&wordpress_path));
+
+        Ok(InstallContext {
+            config,
+            root_dir,
+            wordpress_path,
+            cache,
+            lockfile,
+            insecure_skip_verify: false,
+            wordpress_version,
+            strict_compat: false,
+            strict_headers: false,
+            with_requirements: false,
+        })
+    }
+
+    /// Warns, or fails with `--strict`, when a plugin's headers suggest it was
+    /// installed under the wrong name or its tag doesn't match its `Version`.
+    ///
+    /// WordPress.org dependencies are named after their slug, so only their
+    /// version is checked.
+    fn check_header_consistency(
+        &self,
+        dep: &Dependency,
+        version: &str,
+        headers: &PackageHeaders,
+    ) -> Result<(), WdmError> {
+        let mut problems = Vec::new();
+        let kind = dep.kind.unwrap_or_default();
+        let directory = dep.install_dir(&self.wordpress_path);
+        let directory = directory
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let (DependencyType::Plugin, Some(main_file)) = (kind, &headers.main_file) {
+            let file_slug = main_file.trim_end_matches(".php");
+            let name_slug = headers.name.as_deref().map(slugify);
+            if dep.source != Some(Source::WordpressOrg)
+                && file_slug != directory
+                && name_slug.as_deref() != Some(directory.as_str())
+            {
+                problems.push(format!(
+                    "it is installed as '{}', but its main file is {} and its Plugin Name is '{}', so WordPress may know it as '{}'",
+                    directory,
+                    main_file,
+                    headers.name.as_deref().unwrap_or_default(),
+                    file_slug
+                ));
+            }
+        }
+        // Commits and branches have no version to compare
+        if let (Some(header), Some(resolved)) = (
+            &headers.version,
+            parse_loose_version(display_version(version)),
+        ) {
+            if parse_loose_version(header).is_some_and(|h| h != resolved) {
+                problems.push(format!(
+                    "{} was resolved, but its header says Version {}",
+                    display_version(version),
+                    header
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else if self.strict_headers {
+            Err(WdmError::HeaderMismatch {
+                dep: dep.name.clone(),
+                problems,
+            })
+        } else {
+            for problem in &problems {
+                warning!("{}: {}", dep.name, problem);
+            }
+            Ok(())
+        }
+    }
+
+    /// Warns, or fails with `--strict-compat`, when `headers` require a newer
+    /// WordPress or PHP than the site has.
+    fn check_compatibility(
+        &self,
+        dep: &Dependency,
+        version: &str,
+        headers: &PackageHeaders,
+    ) -> Result<(), WdmError> {
+        let problems: Vec<String> = [
+            ("WordPress", &headers.requires_wp, &self.wordpress_version),
+            (
+                "PHP",
+                &headers.requires_php,
+                &self.config.config.php_version,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(product, required, site)| {
+            let (required, site) = (required.as_deref()?, site.as_deref()?);
+            let (Some(minimum), Some(running)) =
+                (parse_loose_version(required), parse_loose_version(site))
+            else {
+                verbose!(
+                    "Could not compare {} {} with {} {}",
+                    product,
+                    required,
+                    product,
+                    site
+                );
+                return None;
+            };
+            // Release candidates and nightlies count as the release they lead up to
+            let running = Version::new(running.major, running.minor, running.patch);
+            (running < minimum).then(|| {
+                format!(
+                    "{} {} requires {} {}, but the site runs {}",
+                    dep.name,
+                    display_version(version),
+                    product,
+                    required,
+                    site
+                )
+            })
+        })
+        .collect();
+
+        if problems.is_empty() {
+            Ok(())
+        } else if self.strict_compat {
+            Err(WdmError::Incompatible {
+                dep: dep.name.clone(),
+                problems,
+            })
+        } else {
+            for problem in &problems {
+                warning!("{}", problem);
+            }
+            Ok(())
+        }
+    }
+
+    fn lockfile_path(&self) -> PathBuf {
+        self.root_dir.join("wdm.lock")
+    }
+
+    fn backup_retention(&self) -> usize {
+        self.config
+            .config
+            .backup_retention
+            .unwrap_or(DEFAULT_BACKUP_RETENTION)
+    }
+
+    /// Returns where to keep what `install_dir` holds before `version` of
+    /// `dep` replaces it, for `wdm rollback`. Only another version that wdm
+    /// installed and locked is kept, and only while `backup_retention`
+    /// allows any backups.
+    fn backup_for(&self, dep: &Dependency, install_dir: &Path, version: &str) -> Option<Backup> {
+        if self.backup_retention() == 0 {
+            return None;
+        }
+        let marker = InstallMarker::read(install_dir).filter(|m| same_name(&m.name, &dep.name))?;
+        if marker.version == version {
+            return None;
+        }
+        let entry = self.lockfile.dependencies.iter().find(|d| {
+            same_name(&d.name, &dep.name) && d.version == marker.version && d.hash == marker.hash
+        })?;
+        Some(Backup {
+            dir: self.cache.backup_dir(&dep.name, &marker.version),
+            entry: entry.clone(),
+        })
+    }
+
+    /// Deletes the backups of `dep` beyond the most recent `backup_retention`.
+    fn prune_backups(&self, dep: &Dependency) {
+        for backup in self
+            .cache
+            .backups(&dep.name)
+            .iter()
+            .skip(self.backup_retention())
+        {
+            verbose!("Removing the backup at {:?}", backup.dir);
+            backup.remove();
+        }
+    }
+
+    /// Writes wdm.lock, returning whether its contents changed.
+    fn save_lockfile(&mut self) -> Result<bool, WdmError> {
+        write_lockfile(&self.lockfile_path(), &mut self.lockfile)
+    }
+
+    /// Describes what `install_one` did with `dep`, for summaries and `--json`.
+    fn report(
+        &self,
+        dep: &Dependency,
+        outcome: &Result<InstallOutcome, WdmError>,
+    ) -> InstallReport {
+        let unchanged =
+            |status| InstallReport::unchanged(dep, &self.wordpress_path, &self.lockfile, status);
+        match outcome {
+            Ok(InstallOutcome::Installed { version, hash, .. }) => {
+                InstallReport::installed(dep, &self.wordpress_path, version, hash)
+            }
+            Ok(InstallOutcome::Skipped | InstallOutcome::UpToDate) => {
+                unchanged(InstallOutcome::status(outcome))
+            }
+            Err(e) => InstallReport::failed(dep, &self.wordpress_path, e),
+        }
+    }
+}
+
+/// What `install_one` did with a dependency.
+#[derive(Debug)]
+enum InstallOutcome {
+    /// The archive was extracted and the lock entry updated
+    Installed {
+        version: String,
+        hash: String,
+        /// The size of the archive
+        size: u64,
+        /// How long fetching and installing took
+        elapsed: Duration,
+    },
+    /// The install directory already held the locked version
+    Skipped,
+    /// There was nothing newer to install
+    UpToDate,
+}
+
+impl InstallOutcome {
+    /// The status `wdm install --json` reports for `outcome`.
+    fn status(outcome: &Result<InstallOutcome, WdmError>) -> InstallStatus {
+        match outcome {
+            Ok(InstallOutcome::Installed { .. }) => InstallStatus::Installed,
+            Ok(InstallOutcome::Skipped) => InstallStatus::Skipped,
+            Ok(InstallOutcome::UpToDate) => InstallStatus::UpToDate,
+            Err(_) => InstallStatus::Failed,
+        }
+    }
+}
+
+/// Installs a fetched dependency: verifies the archive against wdm.lock, runs
+/// the hooks, swaps the extracted files in for the previous install, and
+/// records the new lock entry in `ctx`. wdm.lock itself is left for the
+/// caller to write once every dependency is done.
+fn install_one(
+    ctx: &mut InstallContext,
+    dep: &Dependency,
+    fetched: FetchOutcome,
+) -> Result<InstallOutcome, WdmError> {
+    let started = Instant::now();
+    let (version, locked_hash, archive, hash, resolved_url, cached, fetch_elapsed) = match fetched {
+        FetchOutcome::Downloaded {
+            version,
+            locked_hash,
+            archive,
+            hash,
+            resolved_url,
+            cached,
+            elapsed,
+        } => (
+            version,
+            locked_hash,
+            archive,
+            hash,
+            resolved_url,
+            cached,
+            elapsed,
+        ),
+        FetchOutcome::Skipped(message) => {
+            progress!("{}", message);
+            return Ok(InstallOutcome::Skipped);
+        }
+        FetchOutcome::UpToDate(message) => {
+            progress!("{}", message);
+            return Ok(InstallOutcome::UpToDate);
+        }
+        FetchOutcome::Failed(e) => return Err(e),
+    };
+
+    // The integrity in wdm.yml was reviewed along with it, so nothing skips it
+    if let Err(e) = verify_integrity(dep, &version, &hash) {
+        if !archive.starts_with(&ctx.cache.vendor_dir) {
+            fs::remove_file(&archive)?;
+        }
+        return Err(e);
+    }
+    if let Some(locked_hash) = &locked_hash {
+        if let Err(e) = verify_hash(dep, &version, locked_hash, &hash) {
+            if !ctx.insecure_skip_verify {
+                fs::remove_file(&archive)?;
+                return Err(e);
+            }
+            error!("{}", e);
+            warning!(
+                "Installing {} anyway because of --insecure-skip-verify",
+                dep.name
+            );
+        }
+    }
+
+    let wordpress_path = &ctx.wordpress_path;
+    run_hooks(
+        ctx.config,
+        dep,
+        &version,
+        HookPhase::PreInstall,
+        wordpress_path,
+    )?;
+
+    if cached && archive.starts_with(&ctx.cache.vendor_dir) {
+        progress!("Using vendored {} {}", dep.name, display_version(&version));
+    } else if cached {
+        progress!("Using cached {} {}", dep.name, display_version(&version));
+    } else {
+        progress!("Saved {} to cache at {:?}", dep.name, archive);
+    }
+
+    // Extract the zip file and swap it in for whatever was installed before, unless
+    // the headers inside show it can't run on this site or is mislabeled
+    let mut headers = PackageHeaders::default();
+    let install_dir = dep.install_dir(wordpress_path);
+    let backup = ctx.backup_for(dep, &install_dir, &version);
+    let backup_dir = backup.as_ref().map(|b| b.dir.as_path());
+    let files = unpack_archive(
+        dep,
+        &archive,
+        &install_dir,
+        backup_dir,
+        |staged| {
+            headers = read_package_headers(staged, dep.kind.unwrap_or_default());
+            ctx.check_compatibility(dep, &version, &headers)?;
+            ctx.check_header_consistency(dep, &version, &headers)?;
+            InstallMarker::new(dep, &version, &hash).write(staged)
+        },
+        || {
+            run_hooks(
+                ctx.config,
+                dep,
+                &version,
+                HookPhase::PostInstall,
+                wordpress_path,
+            )
+        },
+    )?;
+    if let Some(backup) = backup.filter(|b| b.dir.is_dir()) {
+        backup.save()?;
+        ctx.prune_backups(dep);
+    }
+    let missing: Vec<&str> = headers
+        .requires_plugins
+        .iter()
+        .filter(|slug| !requirement_met(ctx.config, wordpress_path, slug))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() && !ctx.with_requirements {
+        warning!(
+            "{} requires plugins that are not installed: {}. Add them to wdm.yml, or run 'wdm install --with-requirements' to add them from WordPress.org",
+            dep.name,
+            missing.join(", ")
+        );
+    }
+
+    let entry = lock_entry(
+        ctx,
+        dep,
+        &version,
+        hash.clone(),
+        resolved_url,
+        files,
+        headers,
+    );
+    ctx.lockfile
+        .dependencies
+        .retain(|d| !same_name(&d.name, &dep.name));
+    ctx.lockfile.dependencies.push(entry);
+
+    Ok(InstallOutcome::Installed {
+        version,
+        hash,
+        size: fs::metadata(&archive).map(|m| m.len()).unwrap_or_default(),
+        elapsed: fetch_elapsed + started.elapsed(),
+    })
+}
+
+/// Locks a fetched dependency for `wdm lock`: verifies the archive against
+/// wdm.lock like `install_one`, but extracts it into the cache only long
+/// enough to read its headers and file list. Returns the locked version.
+fn lock_one(
+    ctx: &mut InstallContext,
+    dep: &Dependency,
+    fetched: FetchOutcome,
+) -> Result<(String, String), WdmError> {
+    let (version, locked_hash, archive, hash, resolved_url) = match fetched {
+        FetchOutcome::Downloaded {
+            version,
+            locked_hash,
+            archive,
+            hash,
+            resolved_url,
+            ..
+        } => (version, locked_hash, archive, hash, resolved_url),
+        // fetch_dependency is always forced, so it never skips
+        FetchOutcome::Skipped(message) | FetchOutcome::UpToDate(message) => {
+            unreachable!("{}", message)
+        }
+        FetchOutcome::Failed(e) => return Err(e),
+    };
+    if let Err(e) = verify_integrity(dep, &version, &hash) {
+        if !archive.starts_with(&ctx.cache.vendor_dir) {
+            fs::remove_file(&archive)?;
+        }
+        return Err(e);
+    }
+    if let Some(locked_hash) = &locked_hash {
+        if let Err(e) = verify_hash(dep, &version, locked_hash, &hash) {
+            fs::remove_file(&archive)?;
+            return Err(e);
+        }
+    }
+
+    let scratch_dir = ctx.cache.dir.join("wdm-lock");
+    let mut headers = PackageHeaders::default();
+    let unpacked = unpack_archive(
+        dep,
+        &archive,
+        &scratch_dir.join(cache_file_component(&dep.name)),
+        None,
+        |staged| {
+            headers = read_package_headers(staged, dep.kind.unwrap_or_default());
+            ctx.check_compatibility(dep, &version, &headers)?;
+            ctx.check_header_consistency(dep, &version, &headers)
+        },
+        || Ok(()),
+    );
+    let _ = fs::remove_dir_all(&scratch_dir);
+    let files = unpacked?;
+
+    let entry = lock_entry(
+        ctx,
+        dep,
+        &version,
+        hash.clone(),
+        resolved_url,
+        files,
+        headers,
+    );
+    ctx.lockfile
+        .dependencies
+        .retain(|d| !same_name(&d.name, &dep.name));
+    ctx.lockfile.dependencies.push(entry);
+    Ok((version, hash))
+}
+
+/// Resolves the newest version of `dep` for `wdm update` and downloads it,
+/// unless it is what `locked_version` already records. A dependency whose
+/// integrity wdm.yml pins is only moved off its locked version with
+/// `update_integrity`.
+fn fetch_update(
+    dep: &Dependency,
+    locked_version: Option<&str>,
+    cache: &Cache,
+    update_integrity: bool,
+) -> FetchOutcome {
+    let started = Instant::now();
+    let up_to_date = || FetchOutcome::UpToDate(format!("{} is up to date", dep.name));
+
+    // Exact pins can never resolve to anything else, so leave them alone
+    let is_pinned = exact_version(&dep.version).is_some()
+        || matches!(parse_git_ref(&dep.version), Some(GitRef::Commit(_)));
+    if locked_version.is_some() && is_pinned {
+        return up_to_date();
+    }
+
+    let token = dependency_token(dep);
+    let version = match resolve_dependency_version(dep, &dep.version, token.as_deref()) {
+        Ok(version) => version,
+        Err(e) => return FetchOutcome::Failed(e),
+    };
+    if locked_version == Some(version.as_str()) {
+        return up_to_date();
+    }
+    if let (Some(locked), Some(_), false) = (locked_version, &dep.integrity, update_integrity) {
+        return FetchOutcome::Failed(WdmError::IntegrityPinned {
+            dep: dep.name.clone(),
+            locked: locked.to_string(),
+            version,
+        });
+    }
+
+    match download_dependency(dep, &version, token.as_deref(), cache) {
+        Ok((archive, hash, resolved_url)) => FetchOutcome::Downloaded {
+            version,
+            locked_hash: None,
+            archive,
+            hash,
+            resolved_url: Some(resolved_url),
+            cached: false,
+            elapsed: started.elapsed(),
+        },
+        Err(e) => FetchOutcome::Failed(e),
+    }
+}
+
+/// Returns the lock entry for `dep` if its version still satisfies the
+/// requirement in wdm.yml.
+fn find_locked(lockfile: &Lockfile, dep: &Dependency) -> Option<LockedDependency> {
+    lockfile
+        .dependencies
+        .iter()
+        .find(|d| {
+            same_name(&d.name, &dep.name)
+                && d.repo == dep.repo
+                && d.source == dep.source.unwrap_or_default()
+                && d.url == dep.url
+                && d.host == dep.host
+                && d.asset == dep.asset
+                && d.path == dep.path
+        })
+        .filter(|d| d.version == dep.version || lock_satisfies(&d.version, dep))
+        .cloned()
+}
+
+/// Fails with `WdmError::HashMismatch` unless the downloaded archive hashes to
+/// what wdm.lock recorded.
+fn verify_hash(
+    dep: &Dependency,
+    version: &str,
+    expected: &str,
+    actual: &str,
+) -> Result<(), WdmError> {
+    if expected == actual {
+        return Ok(());
+    }
+    Err(WdmError::HashMismatch {
+        dep: dep.name.clone(),
+        version: version.to_string(),
+        url: dep.url.clone(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    })
+}
+
+/// Returns the sha256 hex digest an `integrity` value such as `sha256-<hex>`
+/// pins, or None if it isn't one.
+fn integrity_digest(integrity: &str) -> Option<String> {
+    let hex = integrity.trim().strip_prefix("sha256-")?;
+    (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| hex.to_ascii_lowercase())
+}
+
+/// Fails with `WdmError::IntegrityMismatch` unless the downloaded archive
+/// hashes to the integrity wdm.yml pins for `dep`, if it pins one.
+fn verify_integrity(dep: &Dependency, version: &str, actual: &str) -> Result<(), WdmError> {
+    let Some(integrity) = &dep.integrity else {
+        return Ok(());
+    };
+    if integrity_digest(integrity).as_deref() == Some(actual) {
+        return Ok(());
+    }
+    Err(WdmError::IntegrityMismatch {
+        dep: dep.name.clone(),
+        version: version.to_string(),
+        expected: integrity.trim().to_string(),
+        actual: format!("sha256-{}", actual),
+    })
+}
+
+/// Sets the `integrity` of the named dependencies in wdm.yml.
+fn record_integrity(integrities: &[(String, String)]) -> Result<(), WdmError> {
+    if integrities.is_empty() {
+        return Ok(());
+    }
+    let mut config = load_config()?;
+    for dep in config
+        .dependencies
+        .iter_mut()
+        .chain(config.dev_dependencies.iter_mut())
+    {
+        if let Some((_, integrity)) = integrities
+            .iter()
+            .find(|(name, _)| same_name(name, &dep.name))
+        {
+            progress!(
+                "Recorded integrity {} for {} in wdm.yml",
+                integrity,
+                dep.name
+            );
+            dep.integrity = Some(integrity.clone());
+        }
+    }
+    write_manifest(&find_manifest()?, &mut config)
+}
+
+/// Extracts `archive` into `plugin_install_dir`, adding the loader mu-plugins
+/// need, and returns the files it extracted. What was installed before is
+/// moved to `backup`, if given.
+///
+/// `inspect` sees the extracted files before they replace anything, and
+/// `installed` runs once they are in place. If `installed` fails, what was
+/// installed before is put back.
+fn unpack_archive(
+    dep: &Dependency,
+    archive: &Path,
+    plugin_install_dir: &Path,
+    backup: Option<&Path>,
+    inspect: impl FnOnce(&Path) -> Result<(), WdmError>,
+    installed: impl FnOnce() -> Result<(), WdmError>,
+) -> Result<Vec<String>, WdmError> {
+    let extraction_error = |source| WdmError::Extraction {
+        dep: dep.name.clone(),
+        source,
+    };
+
+    // Extract next to the install directory first, so a broken archive never
+    // leaves a half-written plugin behind
+    let staging_dir = sibling_dir(plugin_install_dir, "wdm-tmp");
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
+    let extracted = fs::create_dir_all(&staging_dir)
+        .map_err(|source| ExtractionError::CreateDir {
+            path: staging_dir.clone(),
+            source,
+        })
+        .and_then(|_| extract_archive(dep, archive, &staging_dir));
+    let files = match extracted {
+        Ok(files) => files,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging_dir);
+            return Err(extraction_error(e));
+        }
+    };
+    if let Err(e) = inspect(&staging_dir) {
+        let _ = fs::remove_dir_all(&staging_dir);
+        return Err(e);
+    }
+    let move_error = |source| {
+        extraction_error(ExtractionError::Move {
+            path: plugin_install_dir.to_path_buf(),
+            source,
+        })
+    };
+    let swapped = swap_dir(&staging_dir, plugin_install_dir).map_err(|source| {
+        let _ = fs::remove_dir_all(&staging_dir);
+        move_error(source)
+    })?;
+
+    // WordPress only loads top-level mu-plugin files, so generate a loader for it
+    let is_mu_plugin = dep.kind == Some(DependencyType::MuPlugin);
+    let finished = if is_mu_plugin {
+        write_mu_plugin_loader(&dep.name, plugin_install_dir).map(|_| ())
+    } else {
+        Ok(())
+    }
+    .and_then(|()| installed());
+    if let Err(e) = finished {
+        let restored = swapped.undo();
+        if is_mu_plugin {
+            let loader_path = mu_plugin_loader_path(&dep.name, plugin_install_dir);
+            if !restored || write_mu_plugin_loader(&dep.name, plugin_install_dir).is_err() {
+                let _ = fs::remove_file(loader_path);
+            }
+        }
+        return Err(e);
+    }
+    swapped.finish(backup).map_err(move_error)?;
+    Ok(files)
+}
+
+/// The file wdm writes at the root of every dependency it installs. It is
+/// dot-prefixed so that plugins listing their own files skip it.
+const INSTALL_MARKER: &str = ".wdm.yml";
+
+/// What `INSTALL_MARKER` records about an installed dependency, so directories
+/// wdm manages can be told from hand-installed ones even when wdm.lock is
+/// missing or out of date.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct InstallMarker {
+    name: String,
+    version: String,
+    source: Source,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    repo: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    hash: String,
+}
+
+impl InstallMarker {
+    fn new(dep: &Dependency, version: &str, hash: &str) -> Self {
+        InstallMarker {
+            name: dep.name.clone(),
+            version: version.to_string(),
+            source: dep.source.unwrap_or_default(),
+            repo: dep.repo.clone(),
+            url: dep.url.as_deref().map(strip_credentials),
+            hash: hash.to_string(),
+        }
+    }
+
+    /// Reads the marker of the dependency installed in `dir`, if there is one.
+    fn read(dir: &Path) -> Option<Self> {
+        serde_yaml::from_str(&fs::read_to_string(dir.join(INSTALL_MARKER)).ok()?).ok()
+    }
+
+    fn write(&self, dir: &Path) -> Result<(), WdmError> {
+        fs::write(dir.join(INSTALL_MARKER), serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Whether `dir` holds the dependency `name` as installed by wdm.
+fn is_managed_by_wdm(dir: &Path, name: &str) -> bool {
+    InstallMarker::read(dir).is_some_and(|marker| same_name(&marker.name, name))
+}
+
+/// What a plugin or theme declares about itself in its headers.
+#[derive(Debug, Default)]
+struct PackageHeaders {
+    /// The plugin's main file, which has the `Plugin Name` header
+    main_file: Option<String>,
+    /// `Plugin Name` or `Theme Name`
+    name: Option<String>,
+    version: Option<String>,
+    requires_wp: Option<String>,
+    requires_php: Option<String>,
+    /// The slugs in a plugin's `Requires Plugins`, which WordPress 6.5 won't
+    /// activate it without
+    requires_plugins: Vec<String>,
+}
+
+/// Reads the headers of the plugin or theme extracted to `dir`.
+///
+/// A theme's headers are in style.css and a plugin's in whichever top-level
+/// PHP file has a `Plugin Name`. Fields missing there are taken from
+/// readme.txt, as WordPress.org does.
+fn read_package_headers(dir: &Path, kind: DependencyType) -> PackageHeaders {
+    let mut top_level: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_file())
+                .collect()
+        })
+        .unwrap_or_default();
+    top_level.sort();
+    let has_name = |path: &Path, name: &str| {
+        path.file_name()
+            .is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name))
+    };
+
+    let (main_file, main_header) = match kind {
+        DependencyType::Theme => top_level
+            .iter()
+            .find(|path| has_name(path, "style.css"))
+            .and_then(|path| Some((None, read_header_block(path)?))),
+        DependencyType::Plugin | DependencyType::MuPlugin => top_level
+            .iter()
+            .filter(|path| path.extension().is_some_and(|e| e == "php"))
+            .filter_map(|path| Some((path, read_header_block(path)?)))
+            .find(|(_, contents)| file_header(contents, "Plugin Name").is_some())
+            .map(|(path, contents)| {
+                let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+                (file_name, contents)
+            }),
+    }
+    .unwrap_or_default();
+    let readme = top_level
+        .iter()
+        .find(|path| has_name(path, "readme.txt"))
+        .and_then(|path| read_header_block(path))
+        .unwrap_or_default();
+
+    let field = |name: &str| file_header(&main_header, name).or_else(|| file_header(&readme, name));
+    let name_field = match kind {
+        DependencyType::Theme => "Theme Name",
+        DependencyType::Plugin | DependencyType::MuPlugin => "Plugin Name",
+    };
+    PackageHeaders {
+        main_file,
+        name: file_header(&main_header, name_field),
+        version: file_header(&main_header, "Version"),
+        requires_wp: field("Requires at least"),
+        requires_php: field("Requires PHP"),
+        requires_plugins: match kind {
+            DependencyType::Plugin => file_header(&main_header, "Requires Plugins")
+                .map(|slugs| {
+                    slugs
+                        .split(',')
+                        .map(slugify)
+                        .filter(|slug| !slug.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            DependencyType::Theme | DependencyType::MuPlugin => Vec::new(),
+        },
+    }
+}
+
+/// Turns a plugin name into the slug WordPress would derive from it, e.g.
+/// `Create Block Theme` into `create-block-theme`.
+fn slugify(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Reads the first 8 KB of `path`, which is all WordPress looks at for headers.
+fn read_header_block(path: &Path) -> Option<String> {
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .ok()?
+        .take(8 * 1024)
+        .read_to_end(&mut head)
+        .ok()?;
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Finds a header field such as `Requires PHP: 7.4` the way WordPress's
+/// `get_file_data()` does: case-insensitively, on a line that may start with
+/// `<?php` and comment markers, ignoring a trailing `*/` or `?>`.
+fn file_header(contents: &str, field: &str) -> Option<String> {
+    let pattern = Regex::new(&format!(
+        r"(?mi)^(?:[ \t]*<\?php)?[ \t/*#@]*{}:(.*)$",
+        regex::escape(field)
+    ))
+    .expect("header patterns are valid");
+    let contents = contents.trim_start_matches('\u{feff}');
+    let value = pattern.captures(contents)?.get(1)?.as_str();
+    let value = match value.find("*/").into_iter().chain(value.find("?>")).min() {
+        Some(end) => &value[..end],
+        None => value,
+    };
+    Some(value.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Reads `$wp_version` from the wp-includes/version.php of the WordPress
+/// install at `wordpress_path`.
+fn detect_wordpress_version(wordpress_path: &Path) -> Option<String> {
+    let contents = fs::read_to_string(wordpress_path.join("wp-includes/version.php")).ok()?;
+    let pattern =
+        Regex::new(r#"\$wp_version\s*=\s*['"]([^'"]+)['"]"#).expect("the version pattern is valid");
+    Some(pattern.captures(&contents)?[1].to_string())
+}
+
+/// A hidden directory next to `dir`, such as `.akismet.wdm-tmp` for `akismet`.
+fn sibling_dir(dir: &Path, suffix: &str) -> PathBuf {
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    dir.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+/// Moves `staging_dir` to `dir`, replacing whatever was there. The previous
+/// contents are moved to `backup` when one is given, and deleted otherwise.
+fn replace_dir(staging_dir: &Path, dir: &Path, backup: Option<&Path>) -> std::io::Result<()> {
+    swap_dir(staging_dir, dir)?.finish(backup)
+}
+
+/// A directory whose contents `swap_dir` replaced, with the previous contents
+/// kept next to it until the new ones are known to work.
+struct SwappedDir {
+    dir: PathBuf,
+    previous_dir: Option<PathBuf>,
+}
+
+/// Moves `staging_dir` to `dir`, renaming whatever was there out of the way
+/// rather than deleting it, so it can be put back if the new contents can't be
+/// moved into place or turn out not to work.
+fn swap_dir(staging_dir: &Path, dir: &Path) -> std::io::Result<SwappedDir> {
+    if !dir.exists() {
+        fs::rename(staging_dir, dir)?;
+        return Ok(SwappedDir {
+            dir: dir.to_path_buf(),
+            previous_dir: None,
+        });
+    }
+
+    let previous_dir = sibling_dir(dir, "wdm-old");
+    if previous_dir.exists() {
+        fs::remove_dir_all(&previous_dir)?;
+    }
+    fs::rename(dir, &previous_dir)?;
+    if let Err(e) = fs::rename(staging_dir, dir) {
+        let _ = fs::rename(&previous_dir, dir);
+        return Err(e);
+    }
+    Ok(SwappedDir {
+        dir: dir.to_path_buf(),
+        previous_dir: Some(previous_dir),
+    })
+}
+
+impl SwappedDir {
+    /// Keeps the new contents, moving the previous ones to `backup` when one is
+    /// given and deleting them otherwise.
+    fn finish(self, backup: Option<&Path>) -> std::io::Result<()> {
+        let Some(previous_dir) = self.previous_dir else {
+            return Ok(());
+        };
+        match backup {
+            Some(backup) => progress!(
+                "Backing up previously installed {:?} to {:?}",
+                self.dir,
+                backup
+            ),
+            None => progress!("Removing previously installed {:?}", self.dir),
+        }
+        if let Some(backup) = backup {
+            if backup.exists() {
+                fs::remove_dir_all(backup)?;
+            }
+            // A rename can't cross filesystems, so a cache elsewhere means no backup
+            let moved = backup
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::rename(&previous_dir, backup));
+            match moved {
+                Ok(()) => return Ok(()),
+                Err(e) => warning!("Could not back up {:?} to {:?}: {}", self.dir, backup, e),
+            }
+        }
+        fs::remove_dir_all(&previous_dir)
+    }
+
+    /// Removes the new contents and puts the previous ones back. Returns
+    /// whether there were previous contents to put back.
+    fn undo(self) -> bool {
+        let _ = fs::remove_dir_all(&self.dir);
+        match self.previous_dir {
+            Some(previous_dir) => {
+                progress!("Restoring previously installed {:?}", self.dir);
+                fs::rename(&previous_dir, &self.dir).is_ok()
+            }
+            None => false,
+        }
+    }
+}
+
+/// Puts back the most recent backup of `name` and its lock entry, leaving
+/// wdm.yml alone.
+fn rollback(name: &str) -> Result<(), WdmError> {
+    let (config, _lock) = load_config_locked()?;
+    let root_dir = resolve_root_dir()?;
+    let cache = resolve_cache(&config.config, &root_dir)?;
+    let mut lockfile = load_lockfile()?;
+    let (wordpress_path, origin) = resolve_wordpress_path(&config.config, &root_dir, None);
+    check_wordpress_path(&wordpress_path, origin)?;
+
+    let backup = cache
+        .backups(name)
+        .into_iter()
+        .next()
+        .ok_or_else(|| WdmError::NoBackup(name.to_string()))?;
+    let restored = backup.entry.clone();
+    let install_dir = restored.install_dir(&wordpress_path);
+    if install_dir.exists() && !is_managed_by_wdm(&install_dir, &restored.name) {
+        return Err(WdmError::UnmanagedDirectory(install_dir));
+    }
+    let replaced = lockfile
+        .dependencies
+        .iter()
+        .find(|d| same_name(&d.name, &restored.name))
+        .map(|d| d.version.clone());
+
+    replace_dir(&backup.dir, &install_dir, None)?;
+    backup.remove();
+    lockfile
+        .dependencies
+        .retain(|d| !same_name(&d.name, &restored.name));
+    lockfile.dependencies.push(restored.clone());
+    write_lockfile(&root_dir.join("wdm.lock"), &mut lockfile)?;
+    summary!(
+        "Rolled back {} {} -> {}",
+        restored.name,
+        replaced.as_deref().map(display_version).unwrap_or("none"),
+        display_version(&restored.version)
+    );
+
+    // wdm.yml still asks for what was installed before the rollback
+    let dep = config
+        .selected_dependencies(true)
+        .into_iter()
+        .find(|d| same_name(&d.name, &restored.name));
+    match dep {
+        None => warning!(
+            "wdm.yml no longer lists {}, so 'wdm install --prune' will remove it",
+            restored.name
+        ),
+        Some(dep) if find_locked(&lockfile, dep).is_none() => warning!(
+            "wdm.yml asks for {} '{}', which {} does not satisfy, so the next 'wdm install' will replace it again; change its version in wdm.yml to keep it",
+            dep.name,
+            dep.version,
+            display_version(&restored.version)
+        ),
+        Some(dep) if exact_version(&dep.version).is_none() => warning!(
+            "wdm.yml still allows newer versions of {} ('{}'), so 'wdm update' will install them again; run 'wdm freeze --only {}' to keep {}",
+            dep.name,
+            dep.version,
+            dep.name,
+            display_version(&restored.version)
+        ),
+        Some(_) => {}
+    }
+    if json_output() {
+        print_json(&json!({
+            "name": restored.name,
+            "from": replaced,
+            "version": restored.version,
+            "path": install_dir,
+        }));
+    }
+    Ok(())
+}
+
+/// The directory a bundle written by `wdm pack` keeps the WordPress files in,
+/// next to `wdm.yml` and `wdm.lock`.
+const BUNDLE_WORDPRESS_DIR: &str = "wordpress";
+
+/// Writes the install directory of every dependency in wdm.lock, along with
+/// wdm.yml and wdm.lock, to the .tar.gz at `output`.
+fn pack(output: &Path, force: bool) -> Result<(), WdmError> {
+    let config = load_config()?;
+    let root_dir = manifest_root()?;
+    let lockfile = load_lockfile()?;
+    let (wordpress_path, _) = resolve_wordpress_path(&config.config, &root_dir, None);
+    if lockfile.dependencies.is_empty() {
+        return Err(WdmError::InvalidArgument(
+            "wdm.lock records no dependencies to pack. Run 'wdm install' first.".to_string(),
+        ));
+    }
+    if output.exists() && !force {
+        return Err(WdmError::WouldOverwrite(vec![output.to_path_buf()]));
+    }
+    // A bundle missing a plugin would only be noticed once it is deployed
+    let missing: Vec<String> = lockfile
+        .dependencies
+        .iter()
+        .filter(|locked| !locked.install_dir(&wordpress_path).is_dir())
+        .map(|locked| format!("\n  {} is not installed", locked.name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(WdmError::InvalidArgument(format!(
+            "Cannot pack:{}\nRun 'wdm install' first.",
+            missing.concat()
+        )));
+    }
+
+    let partial = output.with_extension("part");
+    let write = || -> Result<(), WdmError> {
+        let file = fs::File::create(&partial)?;
+        let mut bundle = tar::Builder::new(flate2::write::GzEncoder::new(
+            file,
+            flate2::Compression::default(),
+        ));
+        bundle.follow_symlinks(false);
+        bundle.append_path_with_name(find_manifest()?, "wdm.yml")?;
+        bundle.append_path_with_name(root_dir.join("wdm.lock"), "wdm.lock")?;
+        for locked in &lockfile.dependencies {
+            let relative = locked.install_dir(Path::new(""));
+            bundle.append_dir_all(
+                Path::new(BUNDLE_WORDPRESS_DIR).join(&relative),
+                locked.install_dir(&wordpress_path),
+            )?;
+            progress!(
+                "Packed {} {} from {}",
+                locked.name,
+                display_version(&locked.version),
+                relative.display()
+            );
+        }
+        bundle.into_inner()?.finish()?;
+        Ok(())
+    };
+    if let Err(e) = write().and_then(|()| Ok(fs::rename(&partial, output)?)) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    let size = fs::metadata(output)?.len();
+    summary!(
+        "Packed {} dependencies into {} ({})",
+        lockfile.dependencies.len(),
+        output.display(),
+        format_size(size)
+    );
+    if json_output() {
+        print_json(&json!({
+            "archive": output,
+            "dependencies": lockfile.dependencies.iter().map(|d| &d.name).collect::<Vec<_>>(),
+            "size": size,
+        }));
+    }
+    Ok(())
+}
+
+/// Restores a bundle written by `wdm pack`: the dependencies go into the
+/// WordPress directory, and wdm.yml and wdm.lock into the current directory.
+///
+/// Each restored directory is checked against wdm.lock through the marker
+/// wdm installed it with and the files wdm.lock lists for it.
+fn unpack(archive: &Path, wordpress_path: Option<&Path>, force: bool) -> Result<(), WdmError> {
+    let open = || -> Result<tar::Archive<flate2::read::GzDecoder<fs::File>>, WdmError> {
+        Ok(tar::Archive::new(flate2::read::GzDecoder::new(
+            fs::File::open(archive)?,
+        )))
+    };
+    let not_a_bundle = || {
+        WdmError::InvalidArgument(format!(
+            "{} was not made by 'wdm pack': it has no wdm.yml and wdm.lock",
+            archive.display()
+        ))
+    };
+
+    // The bundled wdm.yml says where WordPress is, relative to the project
+    let mut manifest = None;
+    for entry in open()?.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.as_ref() == Path::new("wdm.yml") {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            manifest = Some(contents);
+            break;
+        }
+    }
+    let manifest = manifest.ok_or_else(not_a_bundle)?;
+    let mut config: Config =
+        serde_yaml::from_str(&manifest).map_err(|source| WdmError::ManifestParse {
+            path: archive.join("wdm.yml"),
+            source,
+        })?;
+    config.interpolate()?;
+    // Restored into the project around the current directory, or a new one here
+    let manifest_path = match find_manifest() {
+        Err(WdmError::ManifestNotFound) => PathBuf::from("wdm.yml"),
+        found => found?,
+    };
+    let root_dir = manifest_dir(&manifest_path).to_path_buf();
+    let _lock = lock_project(&root_dir)?;
+    let (wordpress_path, _) = resolve_wordpress_path(&config.config, &root_dir, wordpress_path);
+    fs::create_dir_all(&wordpress_path)?;
+
+    // Unpacked next to where it goes, so moving it into place is a rename
+    let staging = tempfile::Builder::new()
+        .prefix(".wdm-unpack")
+        .tempdir_in(&wordpress_path)?;
+    open()?.unpack(staging.path())?;
+    let staged_lock = staging.path().join("wdm.lock");
+    if !staged_lock.is_file() {
+        return Err(not_a_bundle());
+    }
+    let lockfile = parse_lockfile(&staged_lock)?;
+
+    let mut conflicts = Vec::new();
+    for locked in &lockfile.dependencies {
+        let relative = locked.install_dir(Path::new(""));
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(WdmError::InvalidInstallPath {
+                dep: locked.name.clone(),
+                path: relative.display().to_string(),
+                reason: "must stay inside the WordPress root",
+            });
+        }
+        let dir = wordpress_path.join(&relative);
+        if dir.exists() && !is_managed_by_wdm(&dir, &locked.name) {
+            conflicts.push(dir);
+        }
+    }
+    let destinations = [
+        ("wdm.yml", manifest_path.clone()),
+        ("wdm.lock", root_dir.join("wdm.lock")),
+    ];
+    for (name, path) in &destinations {
+        if path.exists() && fs::read(path)? != fs::read(staging.path().join(name))? {
+            conflicts.push(path.clone());
+        }
+    }
+    if !conflicts.is_empty() && !force {
+        return Err(WdmError::WouldOverwrite(conflicts));
+    }
+
+    let mut problems = Vec::new();
+    for locked in &lockfile.dependencies {
+        let relative = locked.install_dir(Path::new(""));
+        let staged = staging.path().join(BUNDLE_WORDPRESS_DIR).join(&relative);
+        let dir = wordpress_path.join(&relative);
+        if !staged.is_dir() {
+            problems.push(format!("{}: the bundle does not contain it", locked.name));
+            continue;
+        }
+        if let Some(parent) = dir.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        replace_dir(&staged, &dir, None)?;
+
+        let missing = locked
+            .files
+            .iter()
+            .filter(|file| !dir.join(file).exists())
+            .count();
+        match InstallMarker::read(&dir) {
+            _ if missing > 0 => problems.push(format!(
+                "{}: {} of the files wdm.lock lists are missing from {:?}",
+                locked.name, missing, dir
+            )),
+            Some(marker) if marker.hash != locked.hash || marker.version != locked.version => {
+                problems.push(format!(
+                    "{}: {:?} holds {} {}, which does not match wdm.lock",
+                    locked.name,
+                    dir,
+                    marker.name,
+                    display_version(&marker.version)
+                ))
+            }
+            Some(_) => progress!(
+                "Restored {} {} into {:?}",
+                locked.name,
+                display_version(&locked.version),
+                dir
+            ),
+            None => warning!(
+                "Restored {} {} into {:?}, but could not verify it: wdm did not install it",
+                locked.name,
+                display_version(&locked.version),
+                dir
+            ),
+        }
+    }
+    for (name, path) in &destinations {
+        fs::copy(staging.path().join(name), path)?;
+    }
+
+    for problem in &problems {
+        error!("{}", problem);
+    }
+    summary!(
+        "Restored {} dependencies into {:?}, {} failed verification",
+        lockfile.dependencies.len() - problems.len(),
+        wordpress_path,
+        problems.len()
+    );
+    if json_output() {
+        print_json(&json!({
+            "wordpress_path": wordpress_path,
+            "dependencies": lockfile.dependencies.iter().map(|d| &d.name).collect::<Vec<_>>(),
+            "problems": problems,
+        }));
+    }
+    if !problems.is_empty() {
+        return Err(WdmError::AlreadyReported);
+    }
+    Ok(())
+}
+
+/// Checks whether a locked tag satisfies the version requirement of `dep` in wdm.yml.
+///
+/// `latest` is satisfied by any locked version; moving it forward is the job of
+/// `wdm update`.
+fn lock_satisfies(locked_version: &str, dep: &Dependency) -> bool {
+    let version_req = dep.version.as_str();
+    match parse_git_ref(version_req) {
+        // A locked branch commit is kept until `wdm update` moves it
+        Some(GitRef::Branch(_)) => return is_commit_sha(locked_version),
+        Some(GitRef::Commit(sha)) => return locked_version.starts_with(&sha),
+        None => {}
+    }
+
+    let pattern = dep.tag_pattern().ok().flatten();
+    let Some(locked) = parse_tag(locked_version, pattern.as_ref()) else {
+        return false;
+    };
+    if version_req == "latest" {
+        return true;
+    }
+    if let Some(specific_version) = exact_version(version_req) {
+        return specific_version == locked;
+    }
+    // A locked prerelease was chosen on purpose (with allow_prerelease or
+    // `update --pre`), so it is kept as long as its release would satisfy the range
+    VersionReq::parse(version_req)
+        .map(|req| version_matches(&req, &locked, true))
+        .unwrap_or(false)
+}
+
+/// Whether `--no-hooks` was passed.
+static NO_HOOKS: AtomicBool = AtomicBool::new(false);
+
+/// When a hook runs relative to installing a dependency.
+#[derive(Debug, Clone, Copy)]
+enum HookPhase {
+    PreInstall,
+    PostInstall,
+}
+
+impl std::fmt::Display for HookPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            HookPhase::PreInstall => "pre_install",
+            HookPhase::PostInstall => "post_install",
+        })
+    }
+}
+
+/// Runs the dependency's own hooks for `phase`, then the global ones.
+///
+/// Hooks see `WDM_NAME`, `WDM_VERSION` and `WDM_PATH` (the absolute install
+/// directory). Pre-install hooks run in the WordPress directory, post-install
+/// hooks in the install directory once the new version is in place.
+fn run_hooks(
+    config: &Config,
+    dep: &Dependency,
+    version: &str,
+    phase: HookPhase,
+    wordpress_path: &Path,
+) -> Result<(), WdmError> {
+    let select = |hooks: &Hooks| match phase {
+        HookPhase::PreInstall => hooks.pre_install.clone(),
+        HookPhase::PostInstall => hooks.post_install.clone(),
+    };
+    let commands: Vec<String> = select(&dep.hooks)
+        .into_iter()
+        .chain(select(&config.hooks))
+        .collect();
+    if commands.is_empty() {
+        return Ok(());
+    }
+    if NO_HOOKS.load(Ordering::Relaxed) {
+        warning!(
+            "Skipping {} {} hooks because of --no-hooks",
+            commands.len(),
+            phase
+        );
+        return Ok(());
+    }
+
+    let plugin_install_dir = std::path::absolute(dep.install_dir(wordpress_path))?;
+    let cwd = match phase {
+        HookPhase::PreInstall => wordpress_path,
+        HookPhase::PostInstall => plugin_install_dir.as_path(),
+    };
+    // Tags lose their `v` like everywhere else, but commits stay unabbreviated
+    let hook_version = if is_commit_sha(version) {
+        version
+    } else {
+        display_version(version)
+    };
+    for command in commands {
+        progress!("Running {} hook for {}: {}", phase, dep.name, command);
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        // Hook output is progress, not a result, so keep it off stdout
+        let status = shell
+            .arg(&command)
+            .current_dir(cwd)
+            .env("WDM_NAME", &dep.name)
+            .env("WDM_VERSION", hook_version)
+            .env("WDM_PATH", &plugin_install_dir)
+            .stdout(std::io::stderr())
+            .status();
+        let reason = match status {
+            Ok(status) if status.success() => continue,
+            Ok(status) => status.to_string(),
+            Err(e) => e.to_string(),
+        };
+        return Err(WdmError::Hook {
+            dep: dep.name.clone(),
+            phase,
+            command,
+            reason,
+        });
+    }
+    Ok(())
+}
+
+/// Returns the path of the loader file generated for a mu-plugin.
+fn mu_plugin_loader_path(name: &str, plugin_install_dir: &Path) -> PathBuf {
+    plugin_install_dir
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(format!("{}-loader.php", normalize_name(name)))
+}
+
+/// Writes `<name>-loader.php` next to an extracted mu-plugin directory.
+///
+/// The loader requires the plugin's main file, which is the top-level PHP file
+/// declaring a `Plugin Name:` header.
+fn write_mu_plugin_loader(name: &str, plugin_install_dir: &Path) -> Result<PathBuf, WdmError> {
+    let main_file =
+        find_plugin_main_file(plugin_install_dir).ok_or_else(|| WdmError::NoPluginHeader {
+            dep: name.to_string(),
+            dir: plugin_install_dir.to_path_buf(),
+        })?;
+
+    let loader = format!(
+        "<?php\n/**\n * Plugin Name: {name} (wdm loader)\n * Description: Loads the {name} must-use plugin installed by wdm.\n */\n\nrequire_once __DIR__ . '/{name}/{main_file}';\n",
+        name = name,
+        main_file = main_file
+    );
+
+    let loader_path = mu_plugin_loader_path(name, plugin_install_dir);
+    fs::write(&loader_path, loader).map_err(|source| WdmError::MuPluginLoader {
+        dep: name.to_string(),
+        source,
+    })?;
+    Ok(loader_path)
+}
+
+/// Finds the top-level PHP file carrying the WordPress plugin header.
+fn find_plugin_main_file(plugin_dir: &Path) -> Option<String> {
+    let mut candidates: Vec<PathBuf> = fs::read_dir(plugin_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "php"))
+        .collect();
+    candidates.sort();
+
+    candidates.into_iter().find_map(|path| {
+        let contents = fs::read(&path).ok()?;
+        // WordPress only reads the first 8 KiB when looking for headers
+        let header = String::from_utf8_lossy(&contents[..contents.len().min(8192)]);
+        if header.contains("Plugin Name:") {
+            path.file_name().map(|n| n.to_string_lossy().into_owned())
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the single top-level directory shared by every entry name, if any.
+///
+/// Directory entries end in `/`.
+fn single_top_level_dir(names: impl IntoIterator<Item = impl AsRef<str>>) -> Option<String> {
+    let mut root: Option<String> = None;
+    let mut nested = false;
+    for name in names {
+        let name = name.as_ref();
+        let mut components = Path::new(name).components();
+        let first = components
+            .next()?
+            .as_os_str()
+            .to_string_lossy()
+            .into_owned();
+        nested |= components.next().is_some() || name.ends_with('/');
+        match &root {
+            Some(existing) if *existing != first => return None,
+            Some(_) => {}
+            None => root = Some(first),
+        }
+    }
+    root.filter(|_| nested)
+}
+
+/// The kinds of archive wdm can extract.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    #[serde(rename = "zip")]
+    Zip,
+    /// A gzip-compressed tarball
+    #[serde(rename = "tar.gz", alias = "tgz")]
+    TarGz,
+}
+
+impl ArchiveFormat {
+    /// Recognizes an archive by its first bytes, since URLs and cache file
+    /// names don't reliably say what they hold.
+    fn detect(start: &[u8]) -> Option<ArchiveFormat> {
+        // A local file header, or the end of central directory record an empty zip consists of
+        if start.starts_with(b"PK\x03\x04") || start.starts_with(b"PK\x05\x06") {
+            Some(ArchiveFormat::Zip)
+        } else if start.starts_with(&[0x1f, 0x8b]) {
+            Some(ArchiveFormat::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extracts a downloaded archive from the cache into `plugin_install_dir`.
+///
+/// The format is detected from the archive's contents unless the dependency
+/// sets `archive_format`. When every entry sits inside one top-level folder,
+/// that folder is stripped so the plugin files land directly in the install
+/// directory; otherwise the archive is extracted as-is. `strip_prefix` in
+/// wdm.yml overrides the detection. With a `path`, only the entries under that
+/// subdirectory of the stripped folder are extracted, re-rooted into the
+/// install directory. Any entry that cannot be read or written fails the
+/// extraction.
+/// Returns the extracted files as sorted `/`-separated relative paths.
+fn extract_archive(
+    dep: &Dependency,
+    archive: &Path,
+    plugin_install_dir: &Path,
+) -> Result<Vec<String>, ExtractionError> {
+    let format = match dep.archive_format {
+        Some(format) => format,
+        None => {
+            ArchiveFormat::detect(&read_start(archive, 4)).ok_or(ExtractionError::UnknownFormat)?
+        }
+    };
+    match format {
+        ArchiveFormat::Zip => extract_zip(dep, archive, plugin_install_dir),
+        ArchiveFormat::TarGz => extract_tar_gz(dep, archive, plugin_install_dir),
+    }
+}
+
+/// Extracts a zip archive, as described for `extract_archive`.
+fn extract_zip(
+    dep: &Dependency,
+    archive: &Path,
+    plugin_install_dir: &Path,
+) -> Result<Vec<String>, ExtractionError> {
+    let file = fs::File::open(archive).map_err(ExtractionError::Read)?;
+    let mut zip = ZipArchive::new(file)?;
+    let mut extraction = Extraction::new(dep, zip.file_names(), plugin_install_dir)?;
+
+    for i in 0..zip.len() {
+        let mut file = zip
+            .by_index(i)
+            .map_err(|source| ExtractionError::Entry { index: i, source })?;
+        let enclosed = file.enclosed_name().map(Path::to_path_buf);
+        let Some((outpath, relative)) = extraction.target(file.name(), enclosed) else {
+            continue;
+        };
+        let mode = file.unix_mode();
+        if file.name().ends_with('/') {
+            extraction.directory(outpath, mode)?;
+        } else {
+            let name = file.name().to_string();
+            extraction.file(&name, &mut file, outpath, &relative, mode)?;
+        }
+    }
+
+    extraction.finish()
+}
+
+/// Extracts a gzip-compressed tarball, as described for `extract_archive`.
+///
+/// Only regular files and directories are extracted; links and special files
+/// are skipped with a warning, since they could point outside the install
+/// directory.
+fn extract_tar_gz(
+    dep: &Dependency,
+    archive: &Path,
+    plugin_install_dir: &Path,
+) -> Result<Vec<String>, ExtractionError> {
+    let open = || -> Result<tar::Archive<flate2::read::GzDecoder<fs::File>>, ExtractionError> {
+        let file = fs::File::open(archive).map_err(ExtractionError::Read)?;
+        Ok(tar::Archive::new(flate2::read::GzDecoder::new(file)))
+    };
+    let read_error = ExtractionError::Read;
+    let entry_name = |entry: &tar::Entry<_>| -> Result<String, ExtractionError> {
+        let path = entry.path().map_err(read_error)?;
+        let path = path.to_string_lossy();
+        let name = path
+            .trim_start_matches("./")
+            .trim_end_matches('/')
+            .to_string();
+        Ok(if entry.header().entry_type().is_dir() {
+            format!("{}/", name)
+        } else {
+            name
+        })
+    };
+
+    // Tarballs can only be read front to back, so the names are listed first
+    let mut names = Vec::new();
+    for entry in open()?.entries().map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let kind = entry.header().entry_type();
+        let name = entry_name(&entry)?;
+        if (kind.is_file() || kind.is_dir()) && name != "/" {
+            names.push(name);
+        }
+    }
+    let mut extraction = Extraction::new(dep, &names, plugin_install_dir)?;
+
+    for entry in open()?.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        let kind = entry.header().entry_type();
+        let name = entry_name(&entry)?;
+        // GitHub's tarballs start with a pax header carrying the commit, and
+        // `tar -C dir .` adds an entry for `./` itself
+        if kind.is_pax_global_extensions() || kind.is_pax_local_extensions() || name == "/" {
+            continue;
+        }
+        if !kind.is_file() && !kind.is_dir() {
+            warning!(
+                "Skipping {} in the archive of {}: only files and directories are extracted",
+                name,
+                dep.name
+            );
+            continue;
+        }
+        let enclosed = Some(PathBuf::from(name.trim_end_matches('/')));
+        let Some((outpath, relative)) = extraction.target(&name, enclosed) else {
+            continue;
+        };
+        let mode = entry.header().mode().ok();
+        if kind.is_dir() {
+            extraction.directory(outpath, mode)?;
+        } else {
+            extraction.file(&name, &mut entry, outpath, &relative, mode)?;
+        }
+    }
+
+    extraction.finish()
+}
+
+/// What every archive format shares when extracting: deciding where each
+/// entry goes, refusing entries that would escape the install directory, and
+/// restoring permissions.
+struct Extraction<'a> {
+    dep: &'a Dependency,
+    plugin_install_dir: &'a Path,
+    /// The folder entries are extracted from, relative to the archive root
+    prefix: PathBuf,
+    subdirectory: Option<&'a str>,
+    progress: Progress,
+    /// Directory modes are applied last so a read-only directory can still be filled
+    directory_modes: Vec<(PathBuf, Option<u32>)>,
+    unsafe_entries: Vec<String>,
+    files: Vec<String>,
+}
+
+impl<'a> Extraction<'a> {
+    /// Plans the extraction of an archive holding the entries `names`.
+    fn new(
+        dep: &'a Dependency,
+        names: impl IntoIterator<Item = impl AsRef<str>>,
+        plugin_install_dir: &'a Path,
+    ) -> Result<Self, ExtractionError> {
+        let names: Vec<String> = names.into_iter().map(|n| n.as_ref().to_string()).collect();
+        // The folder name can't be predicted reliably: it depends on how the tag
+        // is spelled, the commit, or whoever built a release asset
+        let root = match &dep.strip_prefix {
+            Some(prefix) => prefix.trim_matches('/').to_string(),
+            None => single_top_level_dir(&names).unwrap_or_default(),
+        };
+        let subdirectory = dep.path.as_deref().map(|path| path.trim_matches('/'));
+        if let Some(path) = subdirectory {
+            if !is_safe_relative_path(Path::new(path)) {
+                return Err(ExtractionError::PathOutsideArchive(path.to_string()));
+            }
+        }
+        Ok(Extraction {
+            dep,
+            plugin_install_dir,
+            prefix: Path::new(&root).join(subdirectory.unwrap_or_default()),
+            subdirectory,
+            progress: Progress::start(
+                format!("Extracting {}", dep.name),
+                Some(names.len() as u64),
+                ProgressUnit::Files,
+            ),
+            directory_modes: Vec::new(),
+            unsafe_entries: Vec::new(),
+            files: Vec::new(),
+        })
+    }
+
+    /// Where the entry `name` is extracted to, and its path relative to the
+    /// install directory, or `None` if it is left out. `enclosed` is the
+    /// entry's path as the format reports it, `None` if it can't be trusted.
+    fn target(&mut self, name: &str, enclosed: Option<PathBuf>) -> Option<(PathBuf, PathBuf)> {
+        self.progress.advance(1);
+        // Never trust entry names: a crafted archive can point anywhere on disk
+        let Some(relative) = enclosed.filter(|name| is_safe_relative_path(name)) else {
+            warning!(
+                "Skipping {} in the archive of {}: it would be written outside the install directory",
+                name,
+                self.dep.name
+            );
+            self.unsafe_entries.push(name.to_string());
+            return None;
+        };
+        match relative.strip_prefix(&self.prefix) {
+            Ok(path) => Some((self.plugin_install_dir.join(path), path.to_path_buf())),
+            Err(_) => {
+                verbose!("Skipping {} in the archive of {}", name, self.dep.name);
+                None
+            }
+        }
+    }
+
+    fn directory(&mut self, outpath: PathBuf, mode: Option<u32>) -> Result<(), ExtractionError> {
+        fs::create_dir_all(&outpath).map_err(|source| ExtractionError::CreateDir {
+            path: outpath.clone(),
+            source,
+        })?;
+        self.directory_modes.push((outpath, mode));
+        Ok(())
+    }
+
+    fn file(
+        &mut self,
+        name: &str,
+        contents: &mut impl Read,
+        outpath: PathBuf,
+        relative: &Path,
+        mode: Option<u32>,
+    ) -> Result<(), ExtractionError> {
+        if let Some(p) = outpath.parent() {
+            fs::create_dir_all(p).map_err(|source| ExtractionError::CreateDir {
+                path: p.to_path_buf(),
+                source,
+            })?;
+        }
+        let mut outfile =
+            fs::File::create(&outpath).map_err(|source| ExtractionError::CreateFile {
+                path: outpath.clone(),
+                source,
+            })?;
+        std::io::copy(contents, &mut outfile).map_err(|source| ExtractionError::Write {
+            name: name.to_string(),
+            source,
+        })?;
+        if let Err(e) = set_unix_mode(&outpath, mode) {
+            error!("Error setting permissions of {:?}: {}", outpath, e);
+        }
+        verbose!("Extracted {:?}", outpath);
+        self.files.push(lock_file_path(relative));
+        Ok(())
+    }
+
+    /// Restores directory permissions and returns the extracted files.
+    fn finish(mut self) -> Result<Vec<String>, ExtractionError> {
+        for (path, mode) in self.directory_modes.into_iter().rev() {
+            if let Err(e) = set_unix_mode(&path, mode) {
+                error!("Error setting permissions of {:?}: {}", path, e);
+            }
+        }
+
+        if !self.unsafe_entries.is_empty() {
+            return Err(ExtractionError::UnsafeEntries(self.unsafe_entries));
+        }
+        if self.files.is_empty() {
+            if let Some(path) = self.subdirectory {
+                return Err(ExtractionError::NothingUnder(path.to_string()));
+            }
+        }
+        self.files.sort();
+        self.files.dedup();
+        Ok(self.files)
+    }
+}
+
+/// Spells a relative path the same way on every platform, for wdm.lock.
+fn lock_file_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Deletes the `files` wdm extracted into `dir`, then every directory left
+/// empty. Returns the files that remain because wdm did not install them.
+fn remove_installed_files(dir: &Path, files: &[String]) -> std::io::Result<Vec<String>> {
+    for file in files {
+        // wdm.lock is edited by hand too, so never follow a path out of `dir`
+        if !is_safe_relative_path(Path::new(file)) {
+            continue;
+        }
+        match fs::remove_file(dir.join(file)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    let mut leftovers = Vec::new();
+    prune_empty_dirs(dir, Path::new(""), &mut leftovers)?;
+    leftovers.sort();
+    Ok(leftovers)
+}
+
+/// Removes `dir` if nothing but empty directories is left in it, collecting the
+/// paths of the files that remain into `leftovers`.
+fn prune_empty_dirs(
+    dir: &Path,
+    relative: &Path,
+    leftovers: &mut Vec<String>,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            prune_empty_dirs(&entry.path(), &path, leftovers)?;
+        } else {
+            leftovers.push(lock_file_path(&path));
+        }
+    }
+    if fs::read_dir(dir)?.next().is_none() {
+        fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Whether `path` only descends: no `..`, no root, and no drive prefix.
+fn is_safe_relative_path(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Applies the Unix permissions recorded for an archive entry, such as the
+/// execute bit of helper scripts. Entries without a recorded mode keep the default.
+#[cfg(unix)]
+fn set_unix_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777)),
+        None => Ok(()),
+    }
+}
+
+/// Windows has no Unix permissions to restore.
+#[cfg(not(unix))]
+fn set_unix_mode(_path: &Path, _mode: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Formats a size in bytes for humans, e.g. `1.5 MB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Prints `rows` as a left-aligned table under `headers`.
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
+
+/// Returns the directory holding `manifest_path`, which is `.` for a bare `wdm.yml`.
+fn manifest_dir(manifest_path: &Path) -> &Path {
+    manifest_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Returns the directory that holds wdm.yml.
+fn manifest_root() -> Result<PathBuf, WdmError> {
+    Ok(find_manifest()?
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf())
+}
+
+/// Returns the keys of the config section, as wdm.yml spells them.
+///
+/// They are read from the `Deserialize` impl of `ConfigData`, so new fields are
+/// picked up by `wdm config` without being listed again here.
+fn config_keys() -> &'static [&'static str] {
+    /// A deserializer that only records the field names of the struct asked for.
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for FieldNames<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("only structs have field names"))
+        }
+
+        fn deserialize_struct<V: serde::de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("field names recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+            byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map
+            enum identifier ignored_any
+        }
+    }
+
+    static KEYS: OnceLock<&'static [&'static str]> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        let mut fields: &'static [&'static str] = &[];
+        let _ = ConfigData::deserialize(FieldNames(&mut fields));
+        fields
+    })
+}
+
+/// Fails with the list of supported keys if `key` is not one of them.
+fn check_config_key(key: &str) -> Result<(), WdmError> {
+    if config_keys().contains(&key) {
+        Ok(())
+    } else {
+        Err(WdmError::UnknownConfigKey(key.to_string()))
+    }
+}
+
+/// Returns the config keys that are set, with their values.
+fn config_values(config: &ConfigData) -> Result<serde_yaml::Mapping, WdmError> {
+    Ok(match serde_yaml::to_value(config)? {
+        serde_yaml::Value::Mapping(values) => values
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .collect(),
+        _ => serde_yaml::Mapping::new(),
+    })
+}
+
+/// Formats a config value the way it would be typed on the command line.
+fn display_config_value(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string(),
+    }
+}
+
+/// Resolves the root directory where wdm.yml is located.
+fn resolve_root_dir() -> Result<PathBuf, WdmError> {
+    let root_dir = manifest_root()?;
+
+    // Ensure that .wdm-cache directory is at root_dir
+    let cache_dir = root_dir.join(".wdm-cache");
+    if !cache_dir.exists() {
+        fs::create_dir_all(&cache_dir)?;
+    }
+
+    Ok(root_dir)
+}
+
+/// Where the WordPress directory was taken from, for reporting it.
+#[derive(Clone, Copy)]
+enum WordPressPathOrigin {
+    Flag,
+    Env,
+    Config,
+    Default,
+}
+
+impl std::fmt::Display for WordPressPathOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            WordPressPathOrigin::Flag => "--wordpress-path",
+            WordPressPathOrigin::Env => "WDM_WORDPRESS_PATH",
+            WordPressPathOrigin::Config => "wdm.yml",
+            WordPressPathOrigin::Default => "default",
+        })
+    }
+}
+
+/// Picks the WordPress directory from `--wordpress-path`, then `WDM_WORDPRESS_PATH`,
+/// then `config.wordpress_path`, defaulting to the project root itself.
+///
+/// The flag and the environment variable are relative to the current directory like
+/// any other shell path, while wdm.yml is relative to the project root. Absolute paths
+/// replace the root when joined, so they are used verbatim.
+fn resolve_wordpress_path(
+    config: &ConfigData,
+    root_dir: &Path,
+    flag: Option<&Path>,
+) -> (PathBuf, WordPressPathOrigin) {
+    if let Some(path) = flag {
+        return (path.to_path_buf(), WordPressPathOrigin::Flag);
+    }
+    if let Some(path) = env::var_os("WDM_WORDPRESS_PATH").filter(|p| !p.is_empty()) {
+        return (PathBuf::from(path), WordPressPathOrigin::Env);
+    }
+    match &config.wordpress_path {
+        Some(path) => (root_dir.join(path), WordPressPathOrigin::Config),
+        None => (root_dir.join("."), WordPressPathOrigin::Default),
+    }
+}
+
+/// Makes sure the WordPress directory (or at least its parent) exists and
+/// reports the absolute directory dependencies are installed into.
+fn check_wordpress_path(
+    wordpress_path: &Path,
+    origin: WordPressPathOrigin,
+) -> Result<(), WdmError> {
+    let target = match fs::canonicalize(wordpress_path) {
+        Ok(target) => target,
+        Err(_) => {
+            let parent = wordpress_path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            match (fs::canonicalize(parent), wordpress_path.file_name()) {
+                (Ok(parent), Some(name)) => parent.join(name),
+                _ => {
+                    return Err(WdmError::WordPressPathNotFound(
+                        wordpress_path.to_path_buf(),
+                    ))
+                }
+            }
+        }
+    };
+    progress!("Installing into {} (from {})", target.display(), origin);
+    Ok(())
+}
+
+/// Resolves the appropriate Git tag based on the version requirement.
+///
+/// Tags are listed with `git ls-remote` or through the GitHub REST API, as
+/// chosen by `github_resolver`. Both return the same tag names, so the
+/// version picked doesn't depend on the backend.
+///
+/// # Arguments
+///
+/// * `dep` - The dependency; its `repo` is in the format "owner/repo".
+/// * `version_req` - The version requirement string (e.g., "^2.0.0").
+/// * `token` - Optional authentication token for private repositories.
+///
+/// # Returns
+///
+/// * `Ok(String)` containing the resolved tag, spelled as in the repository.
+/// * `Err(ResolveError)` saying why no tag could be picked.
+fn resolve_github_version(
+    dep: &Dependency,
+    version_req: &str,
+    token: Option<&str>,
+) -> Result<String, ResolveError> {
+    let repo = dep.repo.as_str();
+    let host = GithubHost::of(dep);
+    let repo_url = format!("{}/{}.git", host.base_url, repo);
+
+    let resolver = github_resolver(token);
+    match (parse_git_ref(version_req), resolver) {
+        (Some(GitRef::Branch(branch)), Resolver::Api) => {
+            return Ok(resolve_github_api_branch(&host, repo, &branch, token)?)
+        }
+        (Some(GitRef::Branch(branch)), Resolver::Git) => {
+            return Ok(resolve_github_branch(&repo_url, &branch)?)
+        }
+        // A commit pin is already exact
+        (Some(GitRef::Commit(sha)), _) => return Ok(sha),
+        (None, _) => {}
+    }
+
+    let tags = cached_tags(dep, || match resolver {
+        Resolver::Api => list_github_api_tags(&host, repo, token),
+        Resolver::Git => list_git_tags(&repo_url),
+    })?;
+    select_tag(tags.iter().map(String::as_str), version_req, dep)
+}
+
+/// Lists every tag name of a repository with `git ls-remote`.
+fn list_git_tags(repo_url: &str) -> Result<Vec<String>, SourceError> {
+    // Execute 'git ls-remote --tags <repo_url>' and capture the output without displaying it
+    verbose!("git ls-remote --tags {}", repo_url);
+    let output = git_command(repo_url)
+        .args(["ls-remote", "--tags", repo_url])
+        .stdout(Stdio::piped()) // Capture stdout
+        .stderr(Stdio::piped()) // Capture stderr
+        .output()
+        .map_err(SourceError::GitUnavailable)?;
+
+    if !output.status.success() {
+        return Err(SourceError::Git(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+
+    // git follows the redirect GitHub serves for a renamed repository, and says so
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(redirect) = stderr.lines().find_map(|line| {
+        line.split_once("redirecting to ")
+            .map(|(_, url)| url.trim())
+    }) {
+        if let (Some(old), Some(new)) = (repo_in_git_url(repo_url), repo_in_git_url(redirect)) {
+            if !old.eq_ignore_ascii_case(&new) {
+                note_moved_repo(&old, &new);
+            }
+        }
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut tags = Vec::new();
+
+    for line in stdout.lines() {
+        // Each line is of the format: <hash>\trefs/tags/<tag>
+        if let Some(pos) = line.find('\t') {
+            let tag_ref = &line[pos + 1..];
+            if let Some(tag) = tag_ref.strip_prefix("refs/tags/") {
+                // Handle annotated tags by stripping the ^{}
+                let tag = tag.trim_end_matches("^{}").to_string();
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+
+    Ok(tags)
+}
+
+/// The `owner/repo` at the end of a git URL such as `https://github.com/owner/repo.git`.
+fn repo_in_git_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let path = url.path().trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, name) = path.rsplit_once('/')?;
+    let owner = owner.rsplit('/').next()?;
+    Some(format!("{}/{}", owner, name))
+}
+
+/// Chooses how GitHub tags are listed.
+///
+/// A token always goes through the API, since `git ls-remote` cannot
+/// authenticate against private repositories without a credential helper.
+/// Otherwise `config.resolver` decides, and when it is unset git is used if
+/// it is installed and the API if it isn't.
+fn github_resolver(token: Option<&str>) -> Resolver {
+    if token.is_some() {
+        return Resolver::Api;
+    }
+    match DEPENDENCY_DEFAULTS.get().and_then(|d| d.resolver) {
+        Some(Resolver::Git) => {
+            if !git_installed() {
+                static WARNED: AtomicBool = AtomicBool::new(false);
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    warning!(
+                        "git is not installed or not on PATH, but wdm.yml sets resolver: git; \
+                         set resolver: api to list tags through the GitHub API instead"
+                    );
+                }
+            }
+            Resolver::Git
+        }
+        Some(Resolver::Api) => Resolver::Api,
+        None if git_installed() => Resolver::Git,
+        None => {
+            verbose!("git is not installed; listing tags through the GitHub API");
+            Resolver::Api
+        }
+    }
+}
+
+/// Whether git can be run, checked once per run.
+fn git_installed() -> bool {
+    static INSTALLED: OnceLock<bool> = OnceLock::new();
+    *INSTALLED.get_or_init(|| check_git_installed().is_ok())
+}
+
+/// Picks the tag matching `version_req` out of a repository's tag names.
+///
+/// Tags are parsed leniently (see `parse_tag`), or with the dependency's
+/// `tag_pattern`, and the winning tag is returned exactly as the repository
+/// spells it so it can be downloaded.
+fn select_tag<'a>(
+    tags: impl Iterator<Item = &'a str>,
+    version_req: &str,
+    dep: &Dependency,
+) -> Result<String, ResolveError> {
+    let pattern = dep.tag_pattern()?;
+    let versions: Vec<(Version, String)> = tags
+        .filter_map(|tag| parse_tag(tag, pattern.as_ref()).map(|ver| (ver, tag.to_string())))
+        .collect();
+
+    if versions.is_empty() {
+        return Err(ResolveError::NoVersions("repository tags"));
+    }
+
+    select_version(versions, version_req, dep.allow_prerelease)
+}
+
+/// Prefixes some repositories put in front of the version in tag names, stripped in order.
+const TAG_PREFIXES: [&str; 3] = ["release-", "version-", "v"];
+
+/// Parses the version out of a tag name such as `v2.5.0`, `2.5`,
+/// `release-2.5.0`, or `2.5.0.1`.
+///
+/// With a `pattern`, its first capture group is parsed instead; tags it
+/// doesn't match are ignored.
+fn parse_tag(tag: &str, pattern: Option<&Regex>) -> Option<Version> {
+    match pattern {
+        Some(pattern) => parse_loose_version(pattern.captures(tag)?.get(1)?.as_str()),
+        None => parse_loose_version(strip_tag_prefix(tag)),
+    }
+}
+
+/// Strips the `TAG_PREFIXES` from a tag name, e.g. `release-v2.5` becomes `2.5`.
+fn strip_tag_prefix(tag: &str) -> &str {
+    TAG_PREFIXES.iter().fold(tag.trim(), |tag, prefix| {
+        tag.strip_prefix(prefix).unwrap_or(tag)
+    })
+}
+
+/// Lists every tag name of a repository through the GitHub REST API.
+fn list_github_api_tags(
+    host: &GithubHost,
+    repo: &str,
+    token: Option<&str>,
+) -> Result<Vec<String>, SourceError> {
+    const PER_PAGE: usize = 100;
+    let mut tags = Vec::new();
+
+    for page in 1.. {
+        let url = format!(
+            "{}/repos/{}/tags?per_page={}&page={}",
+            host.api_url, repo, PER_PAGE, page
+        );
+        let body = github_api_get(&url, token)?;
+        let page_tags: Vec<String> = body
+            .as_array()
+            .ok_or(SourceError::UnexpectedResponse("GitHub API"))?
+            .iter()
+            .filter_map(|tag| tag.get("name").and_then(|n| n.as_str()))
+            .map(str::to_string)
+            .collect();
+
+        let is_last_page = page_tags.len() < PER_PAGE;
+        tags.extend(page_tags);
+        if is_last_page {
+            break;
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Resolves the commit a branch currently points at through the GitHub REST API.
+fn resolve_github_api_branch(
+    host: &GithubHost,
+    repo: &str,
+    branch: &str,
+    token: Option<&str>,
+) -> Result<String, SourceError> {
+    let url = format!("{}/repos/{}/branches/{}", host.api_url, repo, branch);
+    github_api_get(&url, token)?
+        .pointer("/commit/sha")
+        .and_then(|sha| sha.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| SourceError::BranchNotFound(branch.to_string()))
+}
+
+/// Sends a GET request to the GitHub REST API, authenticated when a token is
+/// given, and parses the JSON body.
+fn github_api_get(url: &str, token: Option<&str>) -> Result<serde_json::Value, SourceError> {
+    let client = http_client();
+    let response = send_with_retries(url, || {
+        let request = client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json");
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    })?;
+
+    if response.status().is_success() {
+        note_moved_api_repo(url, response.url().as_str(), token);
+    }
+    api_json(response, "GitHub API")
+}
+
+/// Notices when the GitHub API answered a `repos/<owner>/<repo>/...` request
+/// from `repositories/<id>/...`, which is where it redirects a renamed or
+/// transferred repository, and looks up the repository's new name.
+fn note_moved_api_repo(requested: &str, fetched: &str, token: Option<&str>) {
+    let Some((_, rest)) = requested.split_once("/repos/") else {
+        return;
+    };
+    let mut segments = rest.split(['/', '?']);
+    let (Some(owner), Some(name)) = (segments.next(), segments.next()) else {
+        return;
+    };
+    let Some(at) = fetched.find("/repositories/") else {
+        return;
+    };
+    let id_end = fetched[at + "/repositories/".len()..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(fetched.len(), |end| at + "/repositories/".len() + end);
+    let repository_url = &fetched[..id_end];
+    match github_api_get(repository_url, token) {
+        Ok(repository) => match repository.get("full_name").and_then(|n| n.as_str()) {
+            Some(new) if !new.eq_ignore_ascii_case(&format!("{}/{}", owner, name)) => {
+                note_moved_repo(&format!("{}/{}", owner, name), new)
+            }
+            _ => {}
+        },
+        Err(e) => verbose!("Could not look up {}: {}", repository_url, e),
+    }
+}
+
+/// Downloads the asset matching `dep.asset` from the GitHub release for `tag`.
+///
+/// Public assets are fetched from their `browser_download_url`, while private
+/// ones have to go through the assets API with the token.
+fn download_github_asset(
+    dep: &Dependency,
+    tag: &str,
+    token: Option<&str>,
+    dest: &Path,
+) -> Result<(String, String), FetchError> {
+    let pattern = dep.asset.as_deref().unwrap_or_default();
+    let host = GithubHost::of(dep);
+    const PER_PAGE: usize = 100;
+    let mut release = None;
+
+    for page in 1.. {
+        let url = format!(
+            "{}/repos/{}/releases?per_page={}&page={}",
+            host.api_url, dep.repo, PER_PAGE, page
+        );
+        let body = github_api_get(&url, token)?;
+        let releases = body
+            .as_array()
+            .ok_or(SourceError::UnexpectedResponse("GitHub API"))?;
+        release = releases
+            .iter()
+            .find(|r| r.get("tag_name").and_then(|t| t.as_str()) == Some(tag))
+            .cloned();
+        if release.is_some() || releases.len() < PER_PAGE {
+            break;
+        }
+    }
+
+    let release = release.ok_or_else(|| SourceError::NoRelease(tag.to_string()))?;
+    let asset = release
+        .get("assets")
+        .and_then(|assets| assets.as_array())
+        .into_iter()
+        .flatten()
+        .find(|asset| {
+            asset
+                .get("name")
+                .and_then(|n| n.as_str())
+                .is_some_and(|name| glob_matches(pattern, name))
+        })
+        .ok_or_else(|| SourceError::NoAsset {
+            pattern: pattern.to_string(),
+            tag: tag.to_string(),
+            repo: dep.repo.clone(),
+        })?;
+
+    // The API names the asset URL itself, so make sure it is the host the token is for
+    let token = token.filter(|_| {
+        asset
+            .get("url")
+            .and_then(|u| u.as_str())
+            .is_some_and(|url| host.owns(url))
+    });
+    match (token, asset.get("url").and_then(|u| u.as_str())) {
+        (Some(token), Some(api_url)) => fetch_archive_with(api_url, dest, dep, |request| {
+            request
+                .header(reqwest::header::ACCEPT, "application/octet-stream")
+                .bearer_auth(token)
+        })
+        .map(|(hash, _)| (hash, api_url.to_string())),
+        _ => {
+            let download_url = asset
+                .get("browser_download_url")
+                .and_then(|u| u.as_str())
+                .ok_or(SourceError::AssetWithoutUrl)?;
+            fetch_archive(download_url, None, dest, dep)
+                .map(|(hash, _)| (hash, download_url.to_string()))
+        }
+    }
+}
+
+/// Matches `name` against a glob `pattern` supporting `*` and `?` wildcards.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` seen and the name index it is currently matching up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Resolves the commit a branch currently points at using `git ls-remote`.
+fn resolve_github_branch(repo_url: &str, branch: &str) -> Result<String, SourceError> {
+    verbose!("git ls-remote {} refs/heads/{}", repo_url, branch);
+    let output = git_command(repo_url)
+        .args(["ls-remote", repo_url, &format!("refs/heads/{}", branch)])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(SourceError::GitUnavailable)?;
+
+    if !output.status.success() {
+        return Err(SourceError::Git(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.split('\t').next())
+        .filter(|sha| is_commit_sha(sha))
+        .map(str::to_string)
+        .ok_or_else(|| SourceError::BranchNotFound(branch.to_string()))
+}
+
+/// A version that tracks a git branch or a specific commit instead of a tag.
+#[derive(Debug, PartialEq, Eq)]
+enum GitRef {
+    Branch(String),
+    Commit(String),
+}
+
+/// Parses `branch=<name>` and `commit=<sha>` version strings.
+fn parse_git_ref(version_req: &str) -> Option<GitRef> {
+    let version_req = version_req.trim();
+    if let Some(branch) = version_req.strip_prefix("branch=") {
+        Some(GitRef::Branch(branch.trim().to_string()))
+    } else {
+        version_req
+            .strip_prefix("commit=")
+            .map(|sha| GitRef::Commit(sha.trim().to_lowercase()))
+    }
+}
+
+/// Checks whether `version` looks like a full or abbreviated commit SHA.
+fn is_commit_sha(version: &str) -> bool {
+    version.len() >= 7 && version.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Formats a resolved version for display: tags lose their `v` and commits
+/// are abbreviated.
+fn display_version(version: &str) -> &str {
+    if version.len() == 40 && is_commit_sha(version) {
+        &version[..7]
+    } else {
+        version.trim_start_matches('v')
+    }
+}
+
+/// Resolves the version to install for `dep`, dispatching on its source.
+fn resolve_dependency_version(
+    dep: &Dependency,
+    version_req: &str,
+    token: Option<&str>,
+) -> Result<String, WdmError> {
+    match dep.source.unwrap_or_default() {
+        Source::Github => resolve_github_version(dep, version_req, token),
+        Source::WordpressOrg => resolve_wordpress_org_version(dep, version_req),
+        Source::Gitlab => resolve_gitlab_version(dep, version_req, token),
+        Source::Bitbucket => resolve_bitbucket_version(dep, version_req, token),
+        // There is nothing to resolve; the version is only a label for the lock
+        Source::Url => Ok(dep.version.clone()),
+    }
+    .map_err(|source| WdmError::VersionResolution {
+        dep: dep.name.clone(),
+        from: dep.source.unwrap_or_default(),
+        source,
+    })
+}
+
+/// How many versions `pick_version` offers at a time.
+const PICKER_VERSIONS: usize = 10;
+
+/// Lists the versions `dep` was released under, newest first, through the same
+/// tag listings `resolve_dependency_version` uses. Each comes with the tag (or
+/// WordPress.org version) it was released as.
+fn available_versions(
+    dep: &Dependency,
+    token: Option<&str>,
+) -> Result<Vec<(Version, String)>, WdmError> {
+    let resolution = |source: ResolveError| WdmError::VersionResolution {
+        dep: dep.name.clone(),
+        from: dep.source.unwrap_or_default(),
+        source,
+    };
+    let source = dep.source.unwrap_or_default();
+    let pattern = dep.tag_pattern().map_err(resolution)?;
+    let tags = match source {
+        Source::Github => {
+            let host = GithubHost::of(dep);
+            cached_tags(dep, || match github_resolver(token) {
+                Resolver::Api => list_github_api_tags(&host, &dep.repo, token),
+                Resolver::Git => list_git_tags(&format!("{}/{}.git", host.base_url, dep.repo)),
+            })
+        }
+        Source::Gitlab => cached_tags(dep, || list_gitlab_tags(&gitlab_project_url(dep), token)),
+        Source::Bitbucket => cached_tags(dep, || {
+            let repo_url = format!("{}/2.0/repositories/{}", bitbucket_api_url(), dep.repo);
+            list_bitbucket_tags(&repo_url, token)
+        }),
+        Source::WordpressOrg => wordpress_org_info(&dep.name, dep.kind).map(|info| {
+            info.get("versions")
+                .and_then(|v| v.as_object())
+                .map(|versions| versions.keys().cloned().collect())
+                .unwrap_or_default()
+        }),
+        Source::Url => Ok(Vec::new()),
+    }
+    .map_err(|e| resolution(e.into()))?;
+
+    let mut versions: Vec<(Version, String)> = tags
+        .into_iter()
+        .filter_map(|tag| {
+            let version = match source {
+                Source::WordpressOrg => parse_loose_version(&tag),
+                _ => parse_tag(&tag, pattern.as_ref()),
+            };
+            version.map(|ver| (ver, tag))
+        })
+        .collect();
+    versions.sort_by(|a, b| b.0.cmp(&a.0));
+    if versions.is_empty() {
+        return Err(resolution(ResolveError::NoVersions(match source {
+            Source::WordpressOrg => "the releases WordPress.org lists",
+            _ => "repository tags",
+        })));
+    }
+    Ok(versions)
+}
+
+/// The version `latest` would resolve to out of `versions`, sorted newest first.
+fn newest_version(dep: &Dependency, versions: &[(Version, String)]) -> Result<Version, WdmError> {
+    versions
+        .iter()
+        .map(|(ver, _)| ver)
+        .find(|ver| dep.allow_prerelease || ver.pre.is_empty())
+        .cloned()
+        .ok_or_else(|| WdmError::VersionResolution {
+            dep: dep.name.clone(),
+            from: dep.source.unwrap_or_default(),
+            source: ResolveError::OnlyPrereleases,
+        })
+}
+
+/// Asks which of `versions`, sorted newest first, to add `dep` at. Prereleases
+/// stay behind a "show prereleases" choice unless `dep` allows them or there
+/// is nothing else; an empty answer takes the newest.
+fn pick_version(dep: &Dependency, versions: &[(Version, String)]) -> Result<Version, WdmError> {
+    pick_version_with(dep, versions, |choices| {
+        eprint!("{}", choices);
+        Ok(prompt("Version [1]: ")?)
+    })
+}
+
+/// `pick_version`, with `ask` showing the choices and returning the answer.
+fn pick_version_with(
+    dep: &Dependency,
+    versions: &[(Version, String)],
+    mut ask: impl FnMut(&str) -> Result<String, WdmError>,
+) -> Result<Version, WdmError> {
+    let mut prereleases =
+        dep.allow_prerelease || versions.iter().all(|(ver, _)| !ver.pre.is_empty());
+    loop {
+        let shown: Vec<&(Version, String)> = versions
+            .iter()
+            .filter(|(ver, _)| prereleases || ver.pre.is_empty())
+            .take(PICKER_VERSIONS)
+            .collect();
+        let hidden = !prereleases && versions.iter().any(|(ver, _)| !ver.pre.is_empty());
+        let answer = ask(&version_choices(&dep.name, &shown, hidden))?;
+        match answer.as_str() {
+            "" => return Ok(shown[0].0.clone()),
+            "p" | "P" if hidden => prereleases = true,
+            _ => match answer
+                .parse::<usize>()
+                .ok()
+                .and_then(|choice| shown.get(choice.checked_sub(1)?))
+            {
+                Some((ver, _)) => return Ok(ver.clone()),
+                None => warning!("'{}' is not one of the choices", answer),
+            },
+        }
+    }
+}
+
+/// Formats the numbered list `pick_version` offers, e.g.
+///
+/// ```text
+/// Versions of my-plugin:
+///    1) 2.1.0 (latest)
+///    2) 2.0.0 (tag release-2.0)
+///    p) show prereleases
+/// ```
+///
+/// Tags that aren't spelled like their version, give or take a `v`, are shown
+/// next to it.
+fn version_choices(name: &str, shown: &[&(Version, String)], hidden_prereleases: bool) -> String {
+    let latest = shown.iter().position(|(ver, _)| ver.pre.is_empty());
+    let mut choices = format!("Versions of {}:\n", name);
+    for (i, (ver, tag)) in shown.iter().enumerate() {
+        let version = ver.to_string();
+        let mut notes = Vec::new();
+        if Some(i) == latest {
+            notes.push("latest".to_string());
+        }
+        if display_version(tag) != version {
+            notes.push(format!("tag {}", tag));
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", notes.join(", "))
+        };
+        choices.push_str(&format!("{:>4}) {}{}\n", i + 1, version, notes));
+    }
+    if hidden_prereleases {
+        choices.push_str("   p) show prereleases\n");
+    }
+    choices
+}
+
+/// The caret requirement that `--range` writes for `version`, e.g. `^2.1.0`.
+/// Build metadata, such as the fourth number of `2.5.0.1`, isn't allowed in a
+/// requirement and is left out.
+fn caret_range(version: &Version) -> String {
+    let mut version = version.clone();
+    version.build = BuildMetadata::EMPTY;
+    format!("^{}", version)
+}
+
+/// Downloads the archive of `dep` at `version`, dispatching on its source.
+///
+/// The archive is streamed into `cache` (see `Cache::archive_path`); the path
+/// is returned together with the sha256 hex digest of its contents.
+fn download_dependency(
+    dep: &Dependency,
+    version: &str,
+    token: Option<&str>,
+    cache: &Cache,
+) -> Result<(PathBuf, String, String), WdmError> {
+    let dest = cache.archive_path(&dep.name, &dep.origin(), version);
+    let fetch = |url: String| fetch_archive(&url, token, &dest, dep).map(|(hash, _)| (hash, url));
+    // A renamed or transferred repository redirects to its new name
+    let fetch_github = |url: String| {
+        fetch_archive(&url, token, &dest, dep)
+            .map(|(hash, fetched)| (hash, follow_moved_repo(dep, &url, &fetched)))
+    };
+    let (hash, resolved_url) = match dep.source.unwrap_or_default() {
+        Source::Github if dep.asset.is_some() => download_github_asset(dep, version, token, &dest),
+        // Authenticated downloads go through the API, which accepts the token
+        Source::Github if token.is_some() => {
+            let git_ref = if parse_git_ref(&dep.version).is_some() {
+                version.to_string()
+            } else {
+                format!("refs/tags/{}", version)
+            };
+            fetch_github(format!(
+                "{}/repos/{}/zipball/{}",
+                GithubHost::of(dep).api_url,
+                dep.repo,
+                git_ref
+            ))
+        }
+        // Branches and commits are downloaded by SHA rather than by tag
+        Source::Github if parse_git_ref(&dep.version).is_some() => fetch_github(format!(
+            "{}/{}/archive/{}.zip",
+            GithubHost::of(dep).base_url,
+            dep.repo,
+            version
+        )),
+        Source::Github => fetch_github(github_tag_archive_url(dep, version)),
+        Source::WordpressOrg => fetch(wordpress_org_download_url(dep, version)),
+        Source::Url => match &dep.url {
+            Some(url) => fetch(url.clone()),
+            None => Err(SourceError::MissingUrl.into()),
+        },
+        Source::Gitlab => {
+            let url = format!(
+                "{}/repository/archive.zip?sha={}",
+                gitlab_project_url(dep),
+                version
+            );
+            fetch_archive_with(&url, &dest, dep, |request| {
+                with_gitlab_token(request, token)
+            })
+            .map(|(hash, _)| (hash, url))
+        }
+        Source::Bitbucket => {
+            let url = format!("{}/{}/get/{}.zip", bitbucket_base_url(), dep.repo, version);
+            fetch_archive_with(&url, &dest, dep, |request| {
+                with_bitbucket_credentials(request, token)
+            })
+            .map(|(hash, _)| (hash, url))
+        }
+    }
+    .map_err(|e| match e {
+        FetchError::Failed(source) => WdmError::Download {
+            dep: dep.name.clone(),
+            from: dep.source.unwrap_or_default(),
+            source,
+        },
+        FetchError::NotAnArchive {
+            url,
+            content_type,
+            first_line,
+        } => WdmError::NotAnArchive {
+            dep: dep.name.clone(),
+            url: redact(&strip_credentials(&url)),
+            content_type,
+            first_line: first_line.map(|line| redact(&line)),
+        },
+    })?;
+    Ok((dest, hash, strip_credentials(&resolved_url)))
+}
+
+/// GitHub repositories that turned out to have moved during this run, as
+/// `(old, new)` pairs of `owner/repo`.
+static MOVED_REPOS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Records that the GitHub repository `old` is now `new`, warning the first
+/// time each move is seen.
+fn note_moved_repo(old: &str, new: &str) {
+    let mut moved = MOVED_REPOS.lock().unwrap_or_else(|e| e.into_inner());
+    if moved.iter().any(|(o, _)| o.eq_ignore_ascii_case(old)) {
+        return;
+    }
+    warning!(
+        "{} → {}: the repository was renamed or transferred; update its repo in wdm.yml, \
+         or run 'wdm install --update-manifest'",
+        old,
+        new
+    );
+    moved.push((old.to_string(), new.to_string()));
+}
+
+/// The `owner/repo` a GitHub archive URL downloads from, for the forms wdm
+/// requests (`<owner>/<repo>/archive/...` and `repos/<owner>/<repo>/zipball/...`)
+/// and the `codeload` host GitHub redirects them to.
+fn repo_in_archive_url(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let repo = |i: usize| Some(format!("{}/{}", segments.get(i)?, segments.get(i + 1)?));
+    if url.host_str()?.starts_with("codeload.") {
+        return repo(0);
+    }
+    if segments.get(2) == Some(&"archive") {
+        return repo(0);
+    }
+    let at = segments.iter().position(|s| *s == "repos")?;
+    (segments.get(at + 3) == Some(&"zipball"))
+        .then(|| repo(at + 1))
+        .flatten()
+}
+
+/// Notices when GitHub redirected the download of `requested` to a renamed
+/// repository, returning the URL to record in wdm.lock, spelled with the
+/// repository's new name.
+fn follow_moved_repo(dep: &Dependency, requested: &str, fetched: &str) -> String {
+    match repo_in_archive_url(fetched) {
+        Some(repo) if !repo.eq_ignore_ascii_case(&dep.repo) => {
+            note_moved_repo(&dep.repo, &repo);
+            requested.replacen(&format!("/{}/", dep.repo), &format!("/{}/", repo), 1)
+        }
+        _ => requested.to_string(),
+    }
+}
+
+/// Points wdm.yml and wdm.lock at the new name of every GitHub repository
+/// that moved, whether noticed during this run or recorded in a lock entry's
+/// `resolved_url` by an earlier one.
+fn update_moved_repos(lockfile: &mut Lockfile) -> Result<(), WdmError> {
+    let mut moved = MOVED_REPOS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    for locked in &lockfile.dependencies {
+        if locked.source != Source::Github || locked.asset.is_some() {
+            continue;
+        }
+        let Some(repo) = locked.resolved_url.as_deref().and_then(repo_in_archive_url) else {
+            continue;
+        };
+        let known = moved
+            .iter()
+            .any(|(old, _)| old.eq_ignore_ascii_case(&locked.repo));
+        if !known && !repo.eq_ignore_ascii_case(&locked.repo) {
+            moved.push((locked.repo.clone(), repo));
+        }
+    }
+    let new_name = |repo: &str| {
+        moved
+            .iter()
+            .find(|(old, _)| old.eq_ignore_ascii_case(repo))
+            .map(|(_, new)| new.clone())
+    };
+
+    let mut config = load_config()?;
+    let mut changed = false;
+    for dep in config
+        .dependencies
+        .iter_mut()
+        .chain(config.dev_dependencies.iter_mut())
+    {
+        if dep.source.unwrap_or_default() != Source::Github {
+            continue;
+        }
+        if let Some(repo) = new_name(&dep.repo) {
+            progress!("Updated {} in wdm.yml: {} → {}", dep.name, dep.repo, repo);
+            dep.repo = repo;
+            changed = true;
+        }
+    }
+    if changed {
+        write_manifest(&find_manifest()?, &mut config)?;
+    }
+    for locked in &mut lockfile.dependencies {
+        if locked.source != Source::Github {
+            continue;
+        }
+        if let Some(repo) = new_name(&locked.repo) {
+            locked.repo = repo;
+        }
+    }
+    Ok(())
+}
+
+/// Renames archives cached as `<name>.zip` by older versions of wdm to the
+/// versioned layout, when their hash shows which locked version they hold.
+///
+/// Entries that don't match the lock are left alone; `wdm remove --purge`
+/// still deletes them.
+fn migrate_legacy_cache(cache: &Cache, lockfile: &Lockfile) {
+    for locked in &lockfile.dependencies {
+        let legacy = cache.dir.join(format!("{}.zip", locked.name));
+        let versioned = cache.archive_path(&locked.name, &locked.origin(), &locked.version);
+        if !legacy.exists() || versioned.exists() {
+            continue;
+        }
+        if hash_file(&legacy).ok().as_ref() == Some(&locked.hash)
+            && fs::rename(&legacy, &versioned).is_ok()
+        {
+            progress!("Migrated cached {:?} to {:?}", legacy, versioned);
+        }
+    }
+}
+
+/// Where downloaded archives are kept.
+struct Cache {
+    dir: PathBuf,
+    /// Whether other projects use this cache too
+    shared: bool,
+    /// Where `wdm vendor` copies archives to, looked at before `dir`
+    vendor_dir: PathBuf,
+    /// Where install keeps replaced versions for `wdm rollback`, always in
+    /// the project's own `.wdm-cache`
+    backups_dir: PathBuf,
+}
+
+impl Cache {
+    /// The directory `cached_tags` keeps tag listings in.
+    fn tags_dir(&self) -> PathBuf {
+        self.dir.join("tags")
+    }
+
+    /// Returns where the archive of `name` at `version` is cached.
+    ///
+    /// The project's own `.wdm-cache` uses `<name>-<version>.zip`. A shared
+    /// cache is keyed by where the archive comes from instead (e.g.
+    /// `owner-repo-version.zip`), since each project may name the same
+    /// dependency differently. Tarballs are cached under the same `.zip` name,
+    /// as extraction tells the formats apart by their contents.
+    fn archive_path(&self, name: &str, origin: &str, version: &str) -> PathBuf {
+        let key = if self.shared {
+            origin.to_string()
+        } else {
+            normalize_name(name)
+        };
+        self.dir.join(format!(
+            "{}-{}.zip",
+            cache_file_component(&key),
+            cache_file_component(version)
+        ))
+    }
+
+    /// Returns where `wdm vendor` puts the archive of `name` at `version`:
+    /// `<name>-<version>.zip` in the vendor directory.
+    fn vendored_path(&self, name: &str, version: &str) -> PathBuf {
+        self.vendor_dir.join(format!(
+            "{}-{}.zip",
+            cache_file_component(&normalize_name(name)),
+            cache_file_component(version)
+        ))
+    }
+
+    /// Returns where install keeps the replaced `version` of `name`:
+    /// `backups/<name>/<version>/`.
+    fn backup_dir(&self, name: &str, version: &str) -> PathBuf {
+        self.backups_dir
+            .join(cache_file_component(&normalize_name(name)))
+            .join(cache_file_component(version))
+    }
+
+    /// Returns the backups of `name`, the most recent first.
+    fn backups(&self, name: &str) -> Vec<Backup> {
+        let dir = self
+            .backups_dir
+            .join(cache_file_component(&normalize_name(name)));
+        let Ok(entries) = fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+        let mut backups: Vec<(SystemTime, Backup)> = entries
+            .filter_map(|entry| {
+                let record = entry.ok()?.path();
+                let version_dir = dir.join(record.file_name()?.to_str()?.strip_suffix(".yml")?);
+                let entry = serde_yaml::from_str(&fs::read_to_string(&record).ok()?).ok()?;
+                let modified = fs::metadata(&record).and_then(|m| m.modified()).ok()?;
+                version_dir.is_dir().then_some((
+                    modified,
+                    Backup {
+                        dir: version_dir,
+                        entry,
+                    },
+                ))
+            })
+            .collect();
+        backups.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        backups.into_iter().map(|(_, backup)| backup).collect()
+    }
+
+    /// Returns the vendored archive of the locked version of `name`, with its
+    /// hash, as long as it still matches wdm.lock.
+    fn find_vendored(&self, locked: &LockedDependency) -> Option<(PathBuf, String)> {
+        let path = self.vendored_path(&locked.name, &locked.version);
+        if !path.is_file() {
+            return None;
+        }
+        match hash_file(&path) {
+            Ok(hash) if hash == locked.hash => Some((path, hash)),
+            Ok(_) => {
+                warning!(
+                    "Ignoring {:?}: it does not match the hash of {} {} in wdm.lock",
+                    path,
+                    locked.name,
+                    display_version(&locked.version)
+                );
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// A replaced install kept for `wdm rollback`: its directory, and next to it
+/// (`<version>.yml`) the lock entry that restoring it brings back.
+struct Backup {
+    dir: PathBuf,
+    entry: LockedDependency,
+}
+
+impl Backup {
+    fn record_path(&self) -> PathBuf {
+        let version = self.dir.file_name().unwrap_or_default().to_string_lossy();
+        self.dir.with_file_name(format!("{}.yml", version))
+    }
+
+    fn save(&self) -> Result<(), WdmError> {
+        fs::write(self.record_path(), serde_yaml::to_string(&self.entry)?)?;
+        Ok(())
+    }
+
+    fn remove(&self) {
+        let _ = fs::remove_dir_all(&self.dir);
+        let _ = fs::remove_file(self.record_path());
+    }
+}
+
+/// Makes `value` safe to use in a cache file name.
+///
+/// Repositories like `owner/repo` and tags such as `release/1.0` must not turn
+/// into subdirectories.
+fn cache_file_component(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// Identifies where an archive is downloaded from, independently of the name
+/// a project gives the dependency.
+fn archive_origin(
+    source: Source,
+    name: &str,
+    repo: &str,
+    url: Option<&str>,
+    host: Option<&str>,
+    asset: Option<&str>,
+) -> String {
+    match source {
+        Source::Github => {
+            // Keep archives of same-named repositories on different servers apart
+            let repo = match github_host_name(host) {
+                Some(host) => format!("{}/{}", host, repo),
+                None => repo.to_string(),
+            };
+            match asset {
+                Some(asset) => format!("{}-{}", repo, asset),
+                None => repo,
+            }
+        }
+        Source::Gitlab => format!("{}/{}", host.unwrap_or("gitlab.com"), repo),
+        Source::Bitbucket => format!("bitbucket/{}", repo),
+        Source::WordpressOrg => format!("wordpress.org/{}", name),
+        Source::Url => {
+            let digest = format!("{:x}", Sha256::digest(url.unwrap_or_default()));
+            format!("url/{}", &digest[..16])
+        }
+    }
+}
+
+impl Dependency {
+    /// See `archive_origin`.
+    fn origin(&self) -> String {
+        archive_origin(
+            self.source.unwrap_or_default(),
+            &self.name,
+            &self.repo,
+            self.url.as_deref(),
+            self.host.as_deref(),
+            self.asset.as_deref(),
+        )
+    }
+}
+
+impl LockedDependency {
+    /// Where this entry was installed, following the same rules as `Dependency::install_dir`.
+    fn install_dir(&self, wordpress_path: &Path) -> PathBuf {
+        match &self.install_path {
+            Some(install_path) => wordpress_path.join(install_path),
+            None => wordpress_path
+                .join(self.kind.content_dir())
+                .join(normalize_name(&self.name)),
+        }
+    }
+
+    /// See `archive_origin`.
+    fn origin(&self) -> String {
+        archive_origin(
+            self.source,
+            &self.name,
+            &self.repo,
+            self.url.as_deref(),
+            self.host.as_deref(),
+            self.asset.as_deref(),
+        )
+    }
+}
+
+/// Picks the cache directory: `WDM_CACHE_DIR`, then `cache_dir` from wdm.yml,
+/// then the user cache directory (e.g. `~/.cache/wdm`) when
+/// `use_global_cache` is on, and the project's `.wdm-cache` otherwise.
+fn resolve_cache(config: &ConfigData, root_dir: &Path) -> Result<Cache, WdmError> {
+    let cache = cache_location(config, root_dir)?;
+    fs::create_dir_all(&cache.dir)?;
+    Ok(cache)
+}
+
+/// Like `resolve_cache`, but without creating the directory, for read-only commands.
+fn cache_location(config: &ConfigData, root_dir: &Path) -> Result<Cache, WdmError> {
+    let dir = match (env::var_os("WDM_CACHE_DIR"), &config.cache_dir) {
+        (Some(dir), _) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        (_, Some(dir)) => Some(root_dir.join(dir)),
+        _ if config.use_global_cache == Some(true) => Some(
+            dirs::cache_dir()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "Could not determine the user cache directory",
+                    )
+                })?
+                .join("wdm"),
+        ),
+        _ => None,
+    };
+
+    let vendor_dir = root_dir.join(config.vendor_dir.as_deref().unwrap_or(DEFAULT_VENDOR_DIR));
+    let backups_dir = root_dir.join(".wdm-cache").join("backups");
+    let cache = match dir {
+        Some(dir) => Cache {
+            dir,
+            shared: true,
+            vendor_dir,
+            backups_dir,
+        },
+        None => Cache {
+            dir: root_dir.join(".wdm-cache"),
+            shared: false,
+            vendor_dir,
+            backups_dir,
+        },
+    };
+    Ok(cache)
+}
+
+/// Where `wdm vendor` puts archives unless `vendor_dir` says otherwise.
+const DEFAULT_VENDOR_DIR: &str = "wdm-vendor";
+
+/// How many replaced versions of each dependency are kept unless
+/// `backup_retention` says otherwise.
+const DEFAULT_BACKUP_RETENTION: usize = 3;
+
+/// How long tag listings are reused by default, in seconds.
+const DEFAULT_TAG_CACHE_TTL_SECS: u64 = 15 * 60;
+
+/// Where tag listings are cached and for how long, set up by `InstallContext::new`.
+/// Commands that never create an `InstallContext`, like `outdated`, always
+/// ask the repositories.
+struct TagCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+static TAG_CACHE: OnceLock<TagCache> = OnceLock::new();
+
+/// Whether cached tags are ignored (and replaced), from `--refresh`,
+/// `wdm update`, and `install --frozen`.
+static REFRESH_TAGS: AtomicBool = AtomicBool::new(false);
+
+/// How many tag listings were needed, and how many of them came from the cache.
+static TAG_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+static TAG_CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// A repository's tags as cached in `<cache>/tags/<origin>.yml`.
+#[derive(Serialize, Deserialize)]
+struct CachedTags {
+    /// Seconds since the Unix epoch when the tags were listed
+    fetched_at: u64,
+    tags: Vec<String>,
+}
+
+/// Returns the tags of `dep`'s repository, reusing a listing cached less than
+/// `tag_cache_ttl_secs` ago instead of calling `list`.
+///
+/// Listings are keyed by provider, host, and repository, so dependencies
+/// sharing a repository share its listing. A fresh listing is cached only when
+/// the cache directory exists, which read-only commands never create.
+fn cached_tags(
+    dep: &Dependency,
+    list: impl FnOnce() -> Result<Vec<String>, SourceError>,
+) -> Result<Vec<String>, SourceError> {
+    TAG_LOOKUPS.fetch_add(1, Ordering::Relaxed);
+    let Some(cache) = TAG_CACHE.get().filter(|cache| !cache.ttl.is_zero()) else {
+        return list();
+    };
+    let origin = archive_origin(
+        dep.source.unwrap_or_default(),
+        &dep.name,
+        &dep.repo,
+        None,
+        dep.host.as_deref(),
+        None,
+    );
+    let path = cache
+        .dir
+        .join(format!("{}.yml", cache_file_component(&origin)));
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if !REFRESH_TAGS.load(Ordering::Relaxed) {
+        let cached = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_yaml::from_str::<CachedTags>(&contents).ok());
+        if let Some(cached) = cached {
+            let age = now.saturating_sub(cached.fetched_at);
+            if age < cache.ttl.as_secs() {
+                verbose!("Using the tags of {} listed {}s ago", origin, age);
+                TAG_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+                return Ok(cached.tags);
+            }
+        }
+    }
+
+    let tags = list()?;
+    if cache.dir.parent().is_some_and(Path::is_dir) {
+        let cached = CachedTags {
+            fetched_at: now,
+            tags: tags.clone(),
+        };
+        // A cache that can't be written only costs a lookup next time
+        let written = fs::create_dir_all(&cache.dir).and_then(|_| {
+            fs::write(
+                &path,
+                serde_yaml::to_string(&cached).expect("tags are always serializable"),
+            )
+        });
+        if let Err(e) = written {
+            verbose!("Could not cache the tags of {}: {}", origin, e);
+        }
+    }
+    Ok(tags)
+}
+
+/// Prints how many tag listings were served from the cache, with `--verbose`.
+fn report_tag_cache() {
+    let lookups = TAG_LOOKUPS.load(Ordering::Relaxed);
+    if lookups > 0 && TAG_CACHE.get().is_some() {
+        verbose!(
+            "{} of {} tag lookups served from the cache",
+            TAG_CACHE_HITS.load(Ordering::Relaxed),
+            lookups
+        );
+    }
+}
+
+/// Resolves the tag, or the commit for branches, of a Bitbucket repository matching `version_req`.
+fn resolve_bitbucket_version(
+    dep: &Dependency,
+    version_req: &str,
+    token: Option<&str>,
+) -> Result<String, ResolveError> {
+    let repo_url = format!("{}/2.0/repositories/{}", bitbucket_api_url(), dep.repo);
+
+    match parse_git_ref(version_req) {
+        Some(GitRef::Branch(branch)) => {
+            bitbucket_api_get(&format!("{}/refs/branches/{}", repo_url, branch), token)?
+                .pointer("/target/hash")
+                .and_then(|sha| sha.as_str())
+                .map(str::to_string)
+                .ok_or_else(|| SourceError::BranchNotFound(branch).into())
+        }
+        Some(GitRef::Commit(sha)) => Ok(sha),
+        None => {
+            let tags = cached_tags(dep, || list_bitbucket_tags(&repo_url, token))?;
+            select_tag(tags.iter().map(String::as_str), version_req, dep)
+        }
+    }
+}
+
+/// Lists every tag name of a Bitbucket repository.
+fn list_bitbucket_tags(repo_url: &str, token: Option<&str>) -> Result<Vec<String>, SourceError> {
+    let mut tags = Vec::new();
+
+    // Each page links to the next one until the last page, which has no `next`
+    let mut next = Some(format!("{}/refs/tags?pagelen=100", repo_url));
+    while let Some(url) = next {
+        let body = bitbucket_api_get(&url, token)?;
+        tags.extend(
+            body.get("values")
+                .and_then(|values| values.as_array())
+                .ok_or(SourceError::UnexpectedResponse("Bitbucket API"))?
+                .iter()
+                .filter_map(|tag| tag.get("name").and_then(|n| n.as_str()))
+                .map(str::to_string),
+        );
+        next = body
+            .get("next")
+            .and_then(|next| next.as_str())
+            .map(str::to_string);
+    }
+
+    Ok(tags)
+}
+
+/// Adds Bitbucket credentials to `request` when a token is available.
+///
+/// Tokens formatted as `user:app_password` are sent with basic auth; anything
+/// else is treated as an access token.
+fn with_bitbucket_credentials(
+    request: reqwest::blocking::RequestBuilder,
+    token: Option<&str>,
+) -> reqwest::blocking::RequestBuilder {
+    match token.map(|token| (token, token.split_once(':'))) {
+        Some((_, Some((user, app_password)))) => request.basic_auth(user, Some(app_password)),
+        Some((token, None)) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// Sends a GET request to the Bitbucket REST API and parses the JSON body.
+fn bitbucket_api_get(url: &str, token: Option<&str>) -> Result<serde_json::Value, SourceError> {
+    let client = http_client();
+    let response = send_with_retries(url, || {
+        let request = client.get(url);
+        with_bitbucket_credentials(request, token)
+    })?;
+
+    api_json(response, "Bitbucket API")
+}
+
+/// Resolves the tag, or the commit for branches, of a GitLab project matching `version_req`.
+fn resolve_gitlab_version(
+    dep: &Dependency,
+    version_req: &str,
+    token: Option<&str>,
+) -> Result<String, ResolveError> {
+    let project_url = gitlab_project_url(dep);
+
+    match parse_git_ref(version_req) {
+        Some(GitRef::Branch(branch)) => gitlab_api_get(
+            &format!("{}/repository/branches/{}", project_url, branch),
+            token,
+        )?
+        .pointer("/commit/id")
+        .and_then(|sha| sha.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| SourceError::BranchNotFound(branch).into()),
+        Some(GitRef::Commit(sha)) => Ok(sha),
+        None => {
+            let tags = cached_tags(dep, || list_gitlab_tags(&project_url, token))?;
+            select_tag(tags.iter().map(String::as_str), version_req, dep)
+        }
+    }
+}
+
+/// Lists every tag name of a GitLab project.
+fn list_gitlab_tags(project_url: &str, token: Option<&str>) -> Result<Vec<String>, SourceError> {
+    const PER_PAGE: usize = 100;
+    let mut tags = Vec::new();
+
+    for page in 1.. {
+        let url = format!(
+            "{}/repository/tags?per_page={}&page={}",
+            project_url, PER_PAGE, page
+        );
+        let body = gitlab_api_get(&url, token)?;
+        let page_tags: Vec<String> = body
+            .as_array()
+            .ok_or(SourceError::UnexpectedResponse("GitLab API"))?
+            .iter()
+            .filter_map(|tag| tag.get("name").and_then(|n| n.as_str()))
+            .map(str::to_string)
+            .collect();
+
+        let is_last_page = page_tags.len() < PER_PAGE;
+        tags.extend(page_tags);
+        if is_last_page {
+            break;
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Returns the API URL of a GitLab project, e.g.
+/// `https://gitlab.com/api/v4/projects/group%2Fproject`.
+///
+/// `host` defaults to `gitlab.com` and may include a scheme; `https://` is
+/// assumed otherwise.
+fn gitlab_project_url(dep: &Dependency) -> String {
+    let host = dep.host.as_deref().unwrap_or("gitlab.com");
+    let base = if host.starts_with("http://") || host.starts_with("https://") {
+        host.trim_end_matches('/').to_string()
+    } else {
+        format!("https://{}", host.trim_end_matches('/'))
+    };
+    format!(
+        "{}/api/v4/projects/{}",
+        base,
+        dep.repo.trim_matches('/').replace('/', "%2F")
+    )
+}
+
+/// Adds GitLab's `PRIVATE-TOKEN` header to `request` when a token is available.
+fn with_gitlab_token(
+    request: reqwest::blocking::RequestBuilder,
+    token: Option<&str>,
+) -> reqwest::blocking::RequestBuilder {
+    match token {
+        Some(token) => request.header("PRIVATE-TOKEN", token),
+        None => request,
+    }
+}
+
+/// Sends a GET request to the GitLab REST API and parses the JSON body.
+fn gitlab_api_get(url: &str, token: Option<&str>) -> Result<serde_json::Value, SourceError> {
+    let client = http_client();
+    let response = send_with_retries(url, || {
+        let request = client.get(url);
+        with_gitlab_token(request, token)
+    })?;
+
+    api_json(response, "GitLab API")
+}
+
+/// Parses the JSON body of a successful response from `api`, or fails with
+/// the status of any other.
+fn api_json(
+    response: reqwest::blocking::Response,
+    api: &'static str,
+) -> Result<serde_json::Value, SourceError> {
+    if !response.status().is_success() {
+        return Err(SourceError::Status(response.status()));
+    }
+    response
+        .json()
+        .map_err(|source| SourceError::InvalidJson { api, source })
+}
+
+/// Picks the version matching `version_req` out of the available candidates.
+///
+/// Each candidate pairs the parsed version used for comparison with the string
+/// to return for it (the tag or version as the source spells it). Prereleases
+/// are only picked for `latest` and ranges when `allow_prerelease` is set, but
+/// an exact pin such as `3.0.0-rc.1` always finds its tag.
+fn select_version(
+    mut versions: Vec<(Version, String)>,
+    version_req: &str,
+    allow_prerelease: bool,
+) -> Result<String, ResolveError> {
+    // Sort versions in descending order
+    versions.sort_by(|a, b| b.0.cmp(&a.0));
+    verbose!(
+        "Versions considered for '{}': {}",
+        version_req,
+        versions
+            .iter()
+            .map(|(_, tag)| tag.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    // Determine the desired version based on version_req
+    if version_req == "latest" {
+        versions
+            .into_iter()
+            .find(|(ver, _)| allow_prerelease || ver.pre.is_empty())
+            .map(|(_, tag)| tag)
+            .ok_or(ResolveError::OnlyPrereleases)
+    } else if let Some(specific_version) = exact_version(version_req) {
+        versions
+            .into_iter()
+            .find(|(ver, _)| *ver == specific_version)
+            .map(|(_, tag)| tag)
+            .ok_or_else(|| ResolveError::VersionNotFound(version_req.to_string()))
+    } else {
+        let req =
+            VersionReq::parse(version_req).map_err(|source| ResolveError::InvalidRequirement {
+                requirement: version_req.to_string(),
+                source,
+            })?;
+        versions
+            .into_iter()
+            .find(|(ver, _)| version_matches(&req, ver, allow_prerelease))
+            .map(|(_, tag)| tag)
+            .ok_or_else(|| ResolveError::NoMatch(version_req.to_string()))
+    }
+}
+
+/// Checks `ver` against `req`. With `allow_prerelease`, a prerelease matches
+/// whenever the release it leads up to does, so `^3.0` accepts `3.0.0-rc.1`;
+/// otherwise semver's rule applies and only a requirement naming a prerelease
+/// of the same version (`>=3.0.0-beta`) accepts one.
+fn version_matches(req: &VersionReq, ver: &Version, allow_prerelease: bool) -> bool {
+    req.matches(ver)
+        || (allow_prerelease
+            && !ver.pre.is_empty()
+            && req.matches(&Version::new(ver.major, ver.minor, ver.patch)))
+}
+
+/// Whether `wdm freeze` leaves `dep` alone: it is pinned to an exact version
+/// or commit already, or is a `url` dependency whose version is only a label.
+fn is_frozen(dep: &Dependency) -> bool {
+    dep.source == Some(Source::Url)
+        || matches!(parse_git_ref(&dep.version), Some(GitRef::Commit(_)))
+        || (parse_git_ref(&dep.version).is_none() && exact_version(&dep.version).is_some())
+}
+
+/// The exact pin `wdm freeze` writes for `dep` locked at `locked_version`.
+///
+/// Branches are pinned to the commit they were locked at. Tags are kept as
+/// the repository spells them when `exact_version` can read them back, and
+/// otherwise replaced by the version their `tag_pattern` captures.
+fn frozen_version(dep: &Dependency, locked_version: &str) -> String {
+    if parse_git_ref(&dep.version).is_some() {
+        return format!("commit={}", locked_version);
+    }
+    if exact_version(locked_version).is_some() {
+        return locked_version.to_string();
+    }
+    dep.tag_pattern()
+        .ok()
+        .flatten()
+        .and_then(|pattern| parse_tag(locked_version, Some(&pattern)))
+        .map(|version| version.to_string())
+        .unwrap_or_else(|| locked_version.to_string())
+}
+
+/// Parses an exact version pin such as `1.8.0`, `v1.8.0`, `5.3`, or `release-2.5.0`.
+///
+/// Returns `None` for `latest` and for ranges like `^2.0`.
+fn exact_version(version_req: &str) -> Option<Version> {
+    let trimmed = strip_tag_prefix(version_req);
+    if trimmed.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        parse_loose_version(trimmed)
+    } else {
+        Version::parse(trimmed).ok()
+    }
+}
+
+/// Checks that `version_req` is something wdm can resolve: `latest`, an exact
+/// version, a semver requirement, or a `branch=`/`commit=` reference. The
+/// `version` of a url dependency is only a label, so anything goes there.
+fn check_version_requirement(version_req: &str, source: Source) -> Result<(), RequirementError> {
+    if source == Source::Url || version_req == "latest" || exact_version(version_req).is_some() {
+        return Ok(());
+    }
+    match parse_git_ref(version_req) {
+        Some(GitRef::Branch(branch)) if branch.is_empty() => Err(RequirementError::EmptyBranch),
+        Some(GitRef::Commit(sha)) if !is_commit_sha(&sha) => Err(RequirementError::InvalidCommit),
+        Some(_) => Ok(()),
+        None => VersionReq::parse(version_req)
+            .map(|_| ())
+            .map_err(Into::into),
+    }
+}
+
+/// Parses a version, padding missing minor and patch components with zeros.
+///
+/// WordPress.org versions and plugin tags are frequently two components long
+/// (e.g. `5.3`), and some have a fourth (`2.5.0.1`). The fourth is kept as
+/// build metadata, so it sorts after `2.5.0` but satisfies the same ranges.
+fn parse_loose_version(version: &str) -> Option<Version> {
+    let version = version.trim().trim_start_matches('v');
+    if let Ok(ver) = Version::parse(version) {
+        return Some(ver);
+    }
+
+    let (core, pre) = match version.split_once('-') {
+        Some((core, pre)) => (core, Some(pre)),
+        None => (version, None),
+    };
+    let parts: Vec<&str> = core.split('.').collect();
+    if parts.len() > 4 {
+        return None;
+    }
+    let mut numbers = [0u64; 4];
+    for (slot, part) in numbers.iter_mut().zip(&parts) {
+        *slot = part.parse().ok()?;
+    }
+    let mut ver = Version::new(numbers[0], numbers[1], numbers[2]);
+    if parts.len() == 4 {
+        ver.build = BuildMetadata::new(&numbers[3].to_string()).ok()?;
+    }
+    if let Some(pre) = pre {
+        ver.pre = Prerelease::new(pre).ok()?;
+    }
+    Some(ver)
+}
+
+/// Resolves a version of a plugin or theme from the WordPress.org directories.
+///
+/// # Arguments
+///
+/// * `dep` - The dependency; its `name` is the slug on WordPress.org, and
+///   themes are looked up in the theme directory.
+/// * `version_req` - The version requirement string (e.g., "^5.3").
+///
+/// # Returns
+///
+/// * `Ok(String)` containing the version exactly as WordPress.org lists it.
+/// * `Err(ResolveError)` saying why no version could be picked.
+fn resolve_wordpress_org_version(
+    dep: &Dependency,
+    version_req: &str,
+) -> Result<String, ResolveError> {
+    let slug = dep.name.as_str();
+    let info = wordpress_org_info(slug, dep.kind)?;
+
+    // `versions` maps each released version to its download URL; `trunk` is skipped
+    let versions: Vec<(Version, String)> = info
+        .get("versions")
+        .and_then(|v| v.as_object())
+        .map(|versions| {
+            versions
+                .keys()
+                .filter_map(|v| parse_loose_version(v).map(|ver| (ver, v.clone())))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if versions.is_empty() {
+        return Err(ResolveError::NoVersions("the releases WordPress.org lists"));
+    }
+
+    select_version(versions, version_req, dep.allow_prerelease)
+}
+
+/// Fetches what the WordPress.org plugin (or, for themes, theme) directory
+/// knows about `slug`, including the versions it has released.
+fn wordpress_org_info(
+    slug: &str,
+    kind: Option<DependencyType>,
+) -> Result<serde_json::Value, SourceError> {
+    let info_url = if kind == Some(DependencyType::Theme) {
+        format!(
+            "{}/themes/info/1.2/?action=theme_information&request[slug]={}&request[fields][versions]=1",
+            wordpress_org_api_url(),
+            slug
+        )
+    } else {
+        format!(
+            "{}/plugins/info/1.2/?action=plugin_information&request[slug]={}",
+            wordpress_org_api_url(),
+            slug
+        )
+    };
+
+    let client = http_client();
+    let response = send_with_retries(&info_url, || client.get(&info_url))?;
+
+    let info = api_json(response, "WordPress.org API")?;
+    if let Some(error) = info.get("error").and_then(|e| e.as_str()) {
+        return Err(SourceError::WordpressOrg(error.to_string()));
+    }
+    Ok(info)
+}
+
+/// Returns where a plugin or theme zip is downloaded from on WordPress.org.
+fn wordpress_org_download_url(dep: &Dependency, version: &str) -> String {
+    let directory = if dep.kind == Some(DependencyType::Theme) {
+        "theme"
+    } else {
+        "plugin"
+    };
+    format!(
+        "{}/{}/{}.{}.zip",
+        wordpress_org_downloads_url(),
+        directory,
+        dep.name,
+        version
+    )
+}
+
+/// Whether a Composer requirement is PHP itself, an extension, or plumbing
+/// such as composer/installers rather than something to install into WordPress.
+fn is_composer_platform_package(package: &str) -> bool {
+    const PLUMBING: [&str; 5] = [
+        "composer/installers",
+        "johnpbloch/wordpress",
+        "johnpbloch/wordpress-core",
+        "johnpbloch/wordpress-core-installer",
+        "roots/wordpress",
+    ];
+    package == "php"
+        || package.starts_with("php-")
+        || package.starts_with("ext-")
+        || package.starts_with("lib-")
+        || package.starts_with("composer-")
+        || PLUMBING.contains(&package)
+}
+
+/// Maps a wpackagist requirement to a WordPress.org dependency, or explains
+/// why the package can't be imported.
+fn composer_dependency(package: &str, constraint: &str) -> Result<Dependency, ImportError> {
+    let (kind, slug) = match package.split_once('/') {
+        Some(("wpackagist-plugin", slug)) => (None, slug),
+        Some(("wpackagist-theme", slug)) => (Some(DependencyType::Theme), slug),
+        Some(("wpackagist-muplugin", slug)) => (Some(DependencyType::MuPlugin), slug),
+        _ => return Err(ImportError::NotWpackagist),
+    };
+    Ok(Dependency {
+        name: slug.to_string(),
+        version: composer_constraint(constraint)?,
+        repo: String::new(),
+        token_env: None,
+        source: Some(Source::WordpressOrg),
+        url: None,
+        host: None,
+        asset: None,
+        kind,
+        install_path: None,
+        strip_prefix: None,
+        archive_format: None,
+        path: None,
+        hooks: Hooks::default(),
+        allow_prerelease: false,
+        tag_pattern: None,
+        integrity: None,
+        envs: Vec::new(),
+    })
+}
+
+/// A plugin `wdm adopt` found but couldn't add to wdm.yml.
+#[derive(Serialize)]
+struct AdoptCandidate {
+    name: String,
+    version: Option<String>,
+    reason: String,
+}
+
+impl AdoptCandidate {
+    fn unmatched(name: &str, version: Option<String>, reason: &str) -> Self {
+        AdoptCandidate {
+            name: name.to_string(),
+            version,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Builds the dependency `wdm adopt` adds for the plugin installed in the
+/// directory `slug`, pinned to the `version` from its header. A repository
+/// from `mapping` wins; otherwise the plugin must be on WordPress.org under
+/// the same slug, with that version released.
+fn adopted_dependency(
+    slug: &str,
+    version: &str,
+    mapping: &BTreeMap<String, String>,
+) -> Result<Dependency, ImportError> {
+    let mut dependency = Dependency {
+        name: slug.to_string(),
+        version: version.to_string(),
+        repo: String::new(),
+        token_env: None,
+        source: None,
+        url: None,
+        host: None,
+        asset: None,
+        kind: None,
+        install_path: None,
+        strip_prefix: None,
+        archive_format: None,
+        path: None,
+        hooks: Hooks::default(),
+        allow_prerelease: false,
+        tag_pattern: None,
+        integrity: None,
+        envs: Vec::new(),
+    };
+    if let Some(repo) = mapping.get(slug) {
+        dependency.repo = repo.clone();
+        return Ok(dependency);
+    }
+
+    let info = wordpress_org_info(slug, None)?;
+    if info.get("slug").and_then(|s| s.as_str()) != Some(slug) {
+        return Err(ImportError::NotOnWordpressOrg(slug.to_string()));
+    }
+    let wanted = parse_loose_version(version);
+    let released = info
+        .get("versions")
+        .and_then(|v| v.as_object())
+        .and_then(|versions| {
+            versions
+                .keys()
+                .find(|v| *v == version || (wanted.is_some() && parse_loose_version(v) == wanted))
+        })
+        .ok_or_else(|| ImportError::NotReleased(version.to_string()))?;
+    dependency.version = released.clone();
+    dependency.source = Some(Source::WordpressOrg);
+    Ok(dependency)
+}
+
+/// Builds a composer.json that installs the same dependencies as `config`.
+///
+/// WordPress.org dependencies become wpackagist requirements. Everything else
+/// gets a `package` repository with a single version and the archive URL, so
+/// it needs an exact pin in wdm.yml or an entry in wdm.lock. With `locked`,
+/// every requirement is the version recorded in wdm.lock.
+fn composer_manifest(
+    config: &Config,
+    lockfile: &Lockfile,
+    locked: bool,
+) -> Result<serde_json::Value, WdmError> {
+    let mut repositories = Vec::new();
+    let mut require = serde_json::Map::new();
+    let mut require_dev = serde_json::Map::new();
+    let mut uses_wpackagist = false;
+    let mut unresolved = Vec::new();
+    require.insert("composer/installers".into(), json!("^2.0"));
+    for (dep, dev) in config
+        .dependencies
+        .iter()
+        .map(|d| (d, false))
+        .chain(config.dev_dependencies.iter().map(|d| (d, true)))
+    {
+        let kind = dep.kind.unwrap_or_default();
+        let lock_entry = find_locked(lockfile, dep);
+        let pinned = || exact_version(&dep.version).map(|_| dep.version.clone());
+        let version = if locked {
+            lock_entry.as_ref().map(|l| l.version.clone())
+        } else {
+            pinned().or_else(|| lock_entry.as_ref().map(|l| l.version.clone()))
+        };
+        let (package, constraint) = if dep.source == Some(Source::WordpressOrg) {
+            uses_wpackagist = true;
+            let vendor = match kind {
+                DependencyType::Plugin => "wpackagist-plugin",
+                DependencyType::Theme => "wpackagist-theme",
+                DependencyType::MuPlugin => "wpackagist-muplugin",
+            };
+            let constraint = match (locked, version) {
+                (true, Some(version)) => version,
+                (true, None) => {
+                    unresolved.push(dep.name.clone());
+                    continue;
+                }
+                (false, _) if dep.version == "latest" => "*".to_string(),
+                (false, _) => dep.version.clone(),
+            };
+            (format!("{}/{}", vendor, dep.name), constraint)
+        } else {
+            let Some(version) = version else {
+                unresolved.push(dep.name.clone());
+                continue;
+            };
+            let dist_url = match (&lock_entry, dep.source.unwrap_or_default()) {
+                (Some(entry), _) if entry.version == version && entry.resolved_url.is_some() => {
+                    entry.resolved_url.clone()
+                }
+                (_, Source::Github) if dep.asset.is_none() => {
+                    Some(github_tag_archive_url(dep, &version))
+                }
+                (_, Source::Url) => dep.url.clone(),
+                _ => None,
+            };
+            let Some(dist_url) = dist_url else {
+                unresolved.push(dep.name.clone());
+                continue;
+            };
+            let package = match dep.repo.split('/').collect::<Vec<_>>().as_slice() {
+                [vendor, name] => format!("{}/{}", vendor, name).to_lowercase(),
+                _ => format!("wdm/{}", dep.name.trim().to_lowercase()),
+            };
+            let version = match Version::parse(display_version(&version)) {
+                Ok(_) => display_version(&version).to_string(),
+                Err(_) => format!("dev-{}", version),
+            };
+            repositories.push(json!({
+                "type": "package",
+                "package": {
+                    "name": package,
+                    "version": version,
+                    "type": match kind {
+                        DependencyType::Plugin => "wordpress-plugin",
+                        DependencyType::Theme => "wordpress-theme",
+                        DependencyType::MuPlugin => "wordpress-muplugin",
+                    },
+                    "dist": { "type": "zip", "url": dist_url },
+                },
+            }));
+            (package, version)
+        };
+        if dev {
+            require_dev.insert(package, json!(constraint));
+        } else {
+            require.insert(package, json!(constraint));
+        }
+    }
+
+    if !unresolved.is_empty() {
+        return Err(WdmError::InvalidArgument(format!(
+            "{} {} no version in wdm.lock to export. Run 'wdm install' first.",
+            unresolved.join(", "),
+            if unresolved.len() == 1 { "has" } else { "have" }
+        )));
+    }
+    if uses_wpackagist {
+        repositories.insert(
+            0,
+            json!({ "type": "composer", "url": "https://wpackagist.org" }),
+        );
+    }
+    let mut composer = json!({
+        "repositories": repositories,
+        "require": require,
+        "config": { "allow-plugins": { "composer/installers": true } },
+    });
+    if !require_dev.is_empty() {
+        composer["require-dev"] = json!(require_dev);
+    }
+    Ok(composer)
+}
+
+/// Translates a Composer version constraint such as `^5.3`, `~2.1`, or `*`
+/// into a requirement wdm understands.
+fn composer_constraint(constraint: &str) -> Result<String, ImportError> {
+    // Stability flags such as `@dev` don't change which versions match
+    let constraint = constraint.split('@').next().unwrap_or_default().trim();
+    if constraint.is_empty() || constraint == "*" {
+        return Ok("latest".to_string());
+    }
+    if constraint.contains('|') {
+        return Err(ImportError::AlternativeConstraints);
+    }
+    if constraint.starts_with("dev-") || constraint.ends_with("-dev") {
+        return Err(ImportError::DevelopmentBranch);
+    }
+    if exact_version(constraint).is_some() {
+        return Ok(constraint.to_string());
+    }
+
+    // Composer separates constraints that must all hold with spaces or commas
+    let comparators: Vec<String> = constraint
+        .split([' ', ','])
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.strip_prefix('~') {
+            // Composer's `~2.1` allows any 2.x from 2.1; semver's only allows 2.1.x
+            Some(version) if version.split('.').count() == 2 => {
+                let major = version.split('.').next().unwrap_or_default();
+                match major.parse::<u64>() {
+                    Ok(major) => format!(">={}, <{}.0.0", version, major + 1),
+                    Err(_) => part.to_string(),
+                }
+            }
+            _ => part.to_string(),
+        })
+        .collect();
+    let requirement = comparators.join(", ");
+    VersionReq::parse(&requirement)
+        .map(|_| requirement)
+        .map_err(|_| ImportError::UnknownConstraint(constraint.to_string()))
+}
+
+/// Returns the URL of the ZIP archive GitHub serves for a tag of `dep`.
+fn github_tag_archive_url(dep: &Dependency, version: &str) -> String {
+    format!(
+        "{}/{}/archive/refs/tags/{}.zip",
+        GithubHost::of(dep).base_url,
+        dep.repo,
+        version
+    )
+}
+
+/// Downloads an archive over HTTP into `dest`, sending `token` as a bearer token if given.
+///
+/// # Returns
+///
+/// * `Ok((String, String))` containing the sha256 hex digest of the archive
+///   and the URL it was served from.
+/// * `Err(FetchError)` saying why no archive was downloaded.
+fn fetch_archive(
+    download_url: &str,
+    token: Option<&str>,
+    dest: &Path,
+    dep: &Dependency,
+) -> Result<(String, String), FetchError> {
+    let auth_value = match token {
+        Some(token) => Some(
+            HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(SourceError::InvalidToken)?,
+        ),
+        None => None,
+    };
+
+    // If a token is provided, add it to the headers for private repositories
+    fetch_archive_with(download_url, dest, dep, |request| match &auth_value {
+        Some(auth_value) => request.header(reqwest::header::AUTHORIZATION, auth_value.clone()),
+        None => request,
+    })
+}
+
+/// Downloads an archive into `dest`, letting `authorize` add whatever credentials
+/// the host expects.
+///
+/// The body is streamed to disk and hashed as it arrives rather than held in
+/// memory, and only moved into place once it is complete. Connection errors,
+/// interrupted transfers and 5xx responses are retried (see `retry`).
+///
+/// Returns the hash along with the URL the archive was served from once
+/// redirects were followed. Unless `dep` sets its `archive_format`, a body
+/// that isn't an archive, such as an HTML error page served with a 200
+/// status, never reaches `dest`. Progress is shown against the
+/// `Content-Length` of the response.
+///
+/// When `dest` already holds an archive downloaded from the same URL, the
+/// request asks the server whether it changed since (see `CacheValidators`),
+/// and a 304 reuses `dest` as it is.
+fn fetch_archive_with(
+    download_url: &str,
+    dest: &Path,
+    dep: &Dependency,
+    authorize: impl Fn(reqwest::blocking::RequestBuilder) -> reqwest::blocking::RequestBuilder,
+) -> Result<(String, String), FetchError> {
+    let client = http_client();
+    // Other wdm processes may be downloading the same archive into a shared cache
+    let partial = dest.with_extension(format!(
+        "zip.{}-{}.part",
+        std::process::id(),
+        PARTIAL_DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let validators = CacheValidators::load(dest, download_url);
+
+    let transfer = retry(download_url, || {
+        let mut request = authorize(client.get(download_url));
+        if let Some(validators) = &validators {
+            if let Some(etag) = &validators.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        log_request(&request);
+        let response = request.send().map_err(request_failure)?;
+
+        match response.status().as_u16() {
+            304 if validators.is_some() => {
+                let hash = hash_file(dest).map_err(|source| {
+                    Failure::Permanent(SourceError::Cache {
+                        action: "read",
+                        path: dest.to_path_buf(),
+                        source,
+                    })
+                })?;
+                Ok(Ok(Transfer::NotModified {
+                    hash,
+                    fetched_url: response.url().to_string(),
+                }))
+            }
+            200..=299 => {
+                let fetched_url = response.url().to_string();
+                let content_type = response
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .and_then(|t| t.to_str().ok())
+                    .map(str::to_string);
+                let not_an_archive = |start: &[u8]| FetchError::NotAnArchive {
+                    url: fetched_url.clone(),
+                    content_type: content_type.clone(),
+                    first_line: first_text_line(start),
+                };
+                // An error page announces itself, so don't even download it
+                let verify = dep.archive_format.is_none();
+                if verify && content_type.as_deref().is_some_and(is_text_content_type) {
+                    let mut start = Vec::new();
+                    let _ = response.take(1024).read_to_end(&mut start);
+                    return Ok(Err(not_an_archive(&start)));
+                }
+                let response_headers = response.headers().clone();
+                let mut progress = Progress::start(
+                    format!("Downloading {}", dep.name),
+                    response.content_length(),
+                    ProgressUnit::Bytes,
+                );
+                let hash = stream_to_file(response, &partial, &mut progress).map_err(|e| {
+                    let _ = fs::remove_file(&partial);
+                    Failure::Transient(e)
+                })?;
+                let start = read_start(&partial, 1024);
+                if verify && ArchiveFormat::detect(&start).is_none() {
+                    let _ = fs::remove_file(&partial);
+                    return Ok(Err(not_an_archive(&start)));
+                }
+                Ok(Ok(Transfer::Downloaded {
+                    hash,
+                    fetched_url: fetched_url.clone(),
+                    validators: CacheValidators::of(download_url, &response_headers),
+                }))
+            }
+            _ if response.status().is_server_error() => {
+                Err(Failure::Transient(SourceError::Status(response.status())))
+            }
+            _ => Err(Failure::Permanent(SourceError::Status(response.status()))),
+        }
+    })??;
+
+    match transfer {
+        Transfer::NotModified { hash, fetched_url } => {
+            verbose!(
+                "{} has not changed since it was cached, reusing {}",
+                download_url,
+                dest.display()
+            );
+            Ok((hash, fetched_url))
+        }
+        Transfer::Downloaded {
+            hash,
+            fetched_url,
+            validators,
+        } => {
+            fs::rename(&partial, dest).map_err(|source| SourceError::Cache {
+                action: "write",
+                path: dest.to_path_buf(),
+                source,
+            })?;
+            match validators {
+                Some(validators) => validators.save(dest),
+                None => CacheValidators::forget(dest),
+            }
+            Ok((hash, fetched_url))
+        }
+    }
+}
+
+/// What a single download in `fetch_archive_with` ended with.
+enum Transfer {
+    /// A new archive is waiting in the partial file
+    Downloaded {
+        hash: String,
+        fetched_url: String,
+        validators: Option<CacheValidators>,
+    },
+    /// The server said the cached archive is still current
+    NotModified { hash: String, fetched_url: String },
+}
+
+/// The `ETag` and `Last-Modified` a cached archive was served with, kept in a
+/// sidecar file next to it, so that downloading the same URL again can ask
+/// the server whether anything changed instead of transferring it again.
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheValidators {
+    url: String,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+impl CacheValidators {
+    /// The sidecar file holding the validators of `archive`.
+    fn path(archive: &Path) -> PathBuf {
+        let mut path = archive.as_os_str().to_owned();
+        path.push(".meta.json");
+        PathBuf::from(path)
+    }
+
+    /// Whether `path` is a sidecar file rather than an archive.
+    fn is_sidecar(path: &Path) -> bool {
+        path.to_string_lossy().ends_with(".meta.json")
+    }
+
+    /// Reads the validators of `archive`, if it exists and was downloaded from `url`.
+    fn load(archive: &Path, url: &str) -> Option<Self> {
+        if !archive.is_file() {
+            return None;
+        }
+        let validators: Self = fs::read(Self::path(archive))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())?;
+        (validators.url == url).then_some(validators)
+    }
+
+    /// Picks the validators out of a response to `url`, if it had any.
+    fn of(url: &str, headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = CacheValidators {
+            url: url.to_string(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+        };
+        (validators.etag.is_some() || validators.last_modified.is_some()).then_some(validators)
+    }
+
+    /// Records the validators of `archive`. The sidecar only saves requests,
+    /// so failing to write it is not an error.
+    fn save(&self, archive: &Path) {
+        if let Ok(data) = serde_json::to_vec(self) {
+            let _ = fs::write(Self::path(archive), data);
+        }
+    }
+
+    /// Drops the validators of `archive`, so it is downloaded in full next time.
+    fn forget(archive: &Path) {
+        let _ = fs::remove_file(Self::path(archive));
+    }
+}
+
+/// Why `fetch_archive_with` did not produce an archive.
+enum FetchError {
+    /// The request failed
+    Failed(SourceError),
+    /// The server answered successfully, but not with an archive
+    NotAnArchive {
+        url: String,
+        content_type: Option<String>,
+        first_line: Option<String>,
+    },
+}
+
+impl From<SourceError> for FetchError {
+    fn from(e: SourceError) -> Self {
+        FetchError::Failed(e)
+    }
+}
+
+/// Whether a `Content-Type` says the body is a page or message rather than a file.
+fn is_text_content_type(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    content_type.starts_with("text/")
+        || content_type.starts_with("application/json")
+        || content_type.starts_with("application/xhtml")
+}
+
+/// Reads up to `len` bytes from the start of `path`.
+fn read_start(path: &Path, len: u64) -> Vec<u8> {
+    let mut start = Vec::new();
+    if let Ok(file) = fs::File::open(path) {
+        let _ = file.take(len).read_to_end(&mut start);
+    }
+    start
+}
+
+/// The first non-blank line of `start`, if it looks like text, shortened to
+/// fit in an error message.
+fn first_text_line(start: &[u8]) -> Option<String> {
+    if start.contains(&0) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(start);
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty())?;
+    if line.contains(char::REPLACEMENT_CHARACTER) {
+        return None;
+    }
+    const MAX: usize = 100;
+    Some(match line.char_indices().nth(MAX) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    })
+}
+
+/// Sends the GET request built by `build`, retrying connection errors and 5xx
+/// responses. Any other response is returned for the caller to interpret.
+fn send_with_retries(
+    url: &str,
+    build: impl Fn() -> reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response, SourceError> {
+    retry(url, || {
+        let request = build();
+        log_request(&request);
+        let response = request.send().map_err(request_failure)?;
+        if response.status().is_server_error() {
+            Err(Failure::Transient(SourceError::Status(response.status())))
+        } else {
+            Ok(response)
+        }
+    })
+}
+
+/// Prints the method, URL and headers of `request` when `--verbose` was
+/// passed, without revealing credentials.
+fn log_request(request: &reqwest::blocking::RequestBuilder) {
+    HTTP_REQUESTS.fetch_add(1, Ordering::Relaxed);
+    if verbosity() < Verbosity::Verbose {
+        return;
+    }
+    let Some(Ok(request)) = request.try_clone().map(|r| r.build()) else {
+        return;
+    };
+    verbose!("{} {}", request.method(), request.url());
+    for (name, value) in request.headers() {
+        let value = value.to_str().unwrap_or("[binary]");
+        let value = if *name == reqwest::header::AUTHORIZATION {
+            match value.split_once(' ') {
+                Some((scheme, credentials)) => format!("{} {}", scheme, mask_secret(credentials)),
+                None => mask_secret(value),
+            }
+        } else if name == "private-token" {
+            mask_secret(value)
+        } else {
+            value.to_string()
+        };
+        verbose!("  {}: {}", name, value);
+    }
+}
+
+/// How many HTTP requests have been sent, for the timings `--verbose` prints.
+static HTTP_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Why an HTTP attempt failed, which decides whether it is worth retrying.
+enum Failure {
+    /// Connection errors, timeouts, interrupted transfers and 5xx responses
+    Transient(SourceError),
+    /// Errors that would fail the same way again, such as 401, 403 and 404
+    Permanent(SourceError),
+}
+
+/// Runs `attempt` until it succeeds, it fails permanently, or the configured
+/// number of attempts is used up.
+///
+/// Retries back off exponentially from the configured base delay, with up to
+/// 50% random jitter so parallel downloads don't retry in lockstep.
+fn retry<T>(url: &str, mut attempt: impl FnMut() -> Result<T, Failure>) -> Result<T, SourceError> {
+    let settings = http_settings();
+    let mut delay = settings.retry_delay;
+
+    for number in 1.. {
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(Failure::Permanent(e)) => return Err(e),
+            Err(Failure::Transient(e)) if number >= settings.attempts => return Err(e),
+            Err(Failure::Transient(e)) => {
+                let jitter = delay.mul_f64(random_fraction() / 2.0);
+                warning!(
+                    "Request to {} failed ({}), retrying in {:.1}s (attempt {}/{})",
+                    url,
+                    e,
+                    (delay + jitter).as_secs_f64(),
+                    number + 1,
+                    settings.attempts
+                );
+                std::thread::sleep(delay + jitter);
+                delay *= 2;
+            }
+        }
+    }
+
+    unreachable!("the loop only ends by returning")
+}
+
+/// Returns a pseudo-random number in `[0, 1)`, good enough for retry jitter.
+fn random_fraction() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    f64::from(nanos % 1000) / 1000.0
+}
+
+/// HTTP behaviour configured in the `config` section of wdm.yml.
+#[derive(Debug, Clone, Copy)]
+struct HttpSettings {
+    attempts: u32,
+    retry_delay: Duration,
+    timeout: Duration,
+    connect_timeout: Duration,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            attempts: 3,
+            retry_delay: Duration::from_millis(500),
+            timeout: Duration::from_secs(300),
+            connect_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+static HTTP_SETTINGS: OnceLock<HttpSettings> = OnceLock::new();
+
+/// Applies the HTTP options from wdm.yml to every request made afterwards.
+///
+/// `timeout` comes from the `--timeout` flag and takes precedence over
+/// `http_timeout_secs`.
+fn configure_http(config: &ConfigData, timeout: Option<u64>) {
+    let defaults = HttpSettings::default();
+    let _ = HTTP_SETTINGS.set(HttpSettings {
+        attempts: config.http_attempts.unwrap_or(defaults.attempts).max(1),
+        retry_delay: config
+            .http_retry_delay_ms
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.retry_delay),
+        timeout: timeout
+            .or(config.http_timeout_secs)
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.timeout),
+        connect_timeout: config
+            .http_connect_timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.connect_timeout),
+    });
+    let proxy = ProxySettings {
+        proxy: config.proxy.clone().filter(|proxy| !proxy.is_empty()),
+        no_proxy: config.no_proxy.clone(),
+    };
+    if PROXY_SETTINGS.set(proxy).is_ok() {
+        let mut proxies: Vec<String> = Vec::new();
+        for (proxy, source) in ["https://", "http://"].into_iter().filter_map(proxy_for) {
+            let proxy = format!("{} from {}", proxy, source);
+            if !proxies.contains(&proxy) {
+                proxies.push(proxy);
+            }
+        }
+        let except = proxy_settings()
+            .no_proxy
+            .or_else(|| env::var("NO_PROXY").or_else(|_| env::var("no_proxy")).ok())
+            .filter(|hosts| !hosts.is_empty() && !proxies.is_empty())
+            .map(|hosts| format!(", except for {}", hosts))
+            .unwrap_or_default();
+        if proxies.is_empty() {
+            verbose!("Not using a proxy");
+        } else {
+            verbose!("Using proxy {}{}", proxies.join(" and "), except);
+        }
+    }
+    TLS_SETTINGS.get_or_init(|| load_tls_settings(config));
+}
+
+/// The TLS options from wdm.yml, for servers whose certificate isn't signed
+/// by a public CA.
+#[derive(Clone, Default)]
+struct TlsSettings {
+    /// `ca_cert_path`, resolved against the directory holding wdm.yml
+    ca_cert_path: Option<PathBuf>,
+    /// The certificates read from it
+    certificates: Vec<reqwest::Certificate>,
+    accept_invalid_certs: bool,
+}
+
+static TLS_SETTINGS: OnceLock<TlsSettings> = OnceLock::new();
+
+/// Returns the TLS settings from wdm.yml, or none if it was not read.
+fn tls_settings() -> TlsSettings {
+    TLS_SETTINGS.get().cloned().unwrap_or_default()
+}
+
+/// Reads the certificates `ca_cert_path` points at, warning about any problem
+/// with them and about `danger_accept_invalid_certs`.
+fn load_tls_settings(config: &ConfigData) -> TlsSettings {
+    let mut tls = TlsSettings {
+        accept_invalid_certs: config.danger_accept_invalid_certs == Some(true),
+        ..TlsSettings::default()
+    };
+    if tls.accept_invalid_certs {
+        warning!(
+            "danger_accept_invalid_certs is set in wdm.yml: TLS certificates are not verified, \
+             so anyone on the network can replace what wdm downloads and installs"
+        );
+    }
+    if let Some(path) = config
+        .ca_cert_path
+        .as_deref()
+        .filter(|path| !path.is_empty())
+    {
+        let path = manifest_root()
+            .map(|root| root.join(path))
+            .unwrap_or_else(|_| PathBuf::from(path));
+        let certificates = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|pem| reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| e.to_string()));
+        match certificates {
+            Ok(certificates) if !certificates.is_empty() => {
+                verbose!(
+                    "Trusting {} certificate(s) from {}",
+                    certificates.len(),
+                    path.display()
+                );
+                tls.certificates = certificates;
+                tls.ca_cert_path = Some(path);
+            }
+            Ok(_) => warning!(
+                "Ignoring ca_cert_path {}: it contains no PEM certificates",
+                path.display()
+            ),
+            Err(e) => warning!("Ignoring ca_cert_path {}: {}", path.display(), e),
+        }
+    }
+    tls
+}
+
+/// The proxy set in wdm.yml, which takes precedence over the environment.
+#[derive(Debug, Clone, Default)]
+struct ProxySettings {
+    proxy: Option<String>,
+    no_proxy: Option<String>,
+}
+
+static PROXY_SETTINGS: OnceLock<ProxySettings> = OnceLock::new();
+
+/// Returns the proxy settings from wdm.yml, or none if it was not read.
+fn proxy_settings() -> ProxySettings {
+    PROXY_SETTINGS.get().cloned().unwrap_or_default()
+}
+
+/// Returns the proxy requests to `url` go through, and where it was set:
+/// `proxy` in wdm.yml, or else the variable reqwest reads for the URL's scheme.
+fn proxy_for(url: &str) -> Option<(String, &'static str)> {
+    if let Some(proxy) = proxy_settings().proxy {
+        return Some((proxy, "wdm.yml"));
+    }
+    let variables = if url.starts_with("http://") {
+        ["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+    } else {
+        ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+    };
+    variables.into_iter().find_map(|variable| {
+        let proxy = env::var(variable).ok().filter(|proxy| !proxy.is_empty())?;
+        Some((proxy, variable))
+    })
+}
+
+/// Starts a `git` command for `url` that goes through the same proxy as the
+/// HTTP client, so tag listings and downloads never take different routes.
+fn git_command(url: &str) -> Command {
+    let mut command = Command::new("git");
+    if let Some((proxy, _)) = proxy_for(url) {
+        command.args(["-c", &format!("http.proxy={}", proxy)]);
+        if let Some(no_proxy) = proxy_settings()
+            .no_proxy
+            .filter(|_| proxy_settings().proxy.is_some())
+        {
+            command.env("no_proxy", no_proxy);
+        }
+    }
+    // git reads these variables ahead of its config, so they are set instead of `-c`
+    let tls = tls_settings();
+    if tls.accept_invalid_certs {
+        command.env("GIT_SSL_NO_VERIFY", "1");
+    } else if let Some(path) = tls.ca_cert_path {
+        // git trusts only the file it is given, which would break github.com
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        if host.as_deref() != Some("github.com") {
+            command.env("GIT_SSL_CAINFO", path);
+        }
+    }
+    command
+}
+
+/// The `User-Agent` every request is sent with.
+const USER_AGENT: &str = "wdm-cli";
+
+/// What every HTTP request wdm makes shares. There is one per process, so
+/// downloads, API lookups and tag listings reuse connections and TLS sessions
+/// instead of starting over for each dependency.
+struct HttpContext {
+    /// Applies the `User-Agent`, the configured timeouts, the proxy from
+    /// wdm.yml or the usual environment variables, and the TLS options
+    client: reqwest::blocking::Client,
+}
+
+static HTTP_CONTEXT: OnceLock<HttpContext> = OnceLock::new();
+
+impl HttpContext {
+    fn new(settings: HttpSettings, proxy: ProxySettings, tls: TlsSettings) -> Self {
+        let mut builder = reqwest::blocking::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(settings.timeout)
+            .connect_timeout(settings.connect_timeout);
+        // Without one in wdm.yml, reqwest reads HTTPS_PROXY, HTTP_PROXY and NO_PROXY itself
+        if let Some(url) = &proxy.proxy {
+            match reqwest::Proxy::all(url) {
+                Ok(all) => {
+                    let no_proxy = match &proxy.no_proxy {
+                        Some(hosts) => reqwest::NoProxy::from_string(hosts),
+                        None => reqwest::NoProxy::from_env(),
+                    };
+                    builder = builder.proxy(all.no_proxy(no_proxy));
+                }
+                Err(e) => warning!("Ignoring the proxy {} from wdm.yml: {}", url, e),
+            }
+        }
+        for certificate in tls.certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if tls.accept_invalid_certs {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let client = builder.build().unwrap_or_default();
+        HttpContext { client }
+    }
+}
+
+/// Returns the shared HTTP client, built on first use from the settings
+/// `configure_http` recorded.
+fn http_client() -> &'static reqwest::blocking::Client {
+    &HTTP_CONTEXT
+        .get_or_init(|| HttpContext::new(http_settings(), proxy_settings(), tls_settings()))
+        .client
+}
+
+/// Classifies a request that got no response; a rejected certificate won't
+/// be accepted on a second try, so it isn't retried.
+fn request_failure(e: reqwest::Error) -> Failure {
+    if certificate_error(&e).is_some() {
+        Failure::Permanent(SourceError::Request(e))
+    } else {
+        Failure::Transient(SourceError::Request(e))
+    }
+}
+
+/// Returns why the server's certificate was rejected, if that is what failed.
+fn certificate_error(e: &reqwest::Error) -> Option<String> {
+    // reqwest only names the URL; the reason is further down the chain
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        if cause.to_string().contains("certificate") {
+            return Some(cause.to_string());
+        }
+        source = cause.source();
+    }
+    None
+}
+
+/// Describes a failed request, spelling out timeouts with the limit that was hit.
+fn describe_request_error(e: &reqwest::Error) -> String {
+    if e.is_timeout() {
+        return format!("timed out after {}s", http_settings().timeout.as_secs());
+    }
+    if let Some(cause) = certificate_error(e) {
+        return format!(
+            "Failed to send HTTP request: {}: {}\n  hint: if the server's certificate is signed by a private CA, point ca_cert_path in wdm.yml at it",
+            e, cause
+        );
+    }
+    format!("Failed to send HTTP request: {}", e)
+}
+
+/// Formats the message printed when downloading `dep` fails.
+fn describe_download(dep: &str, error: &SourceError) -> String {
+    match error {
+        SourceError::TimedOut(_) => format!("Download of {} {}", dep, error),
+        SourceError::Request(e) if e.is_timeout() => format!("Download of {} {}", dep, error),
+        _ => format!("Error downloading {}: {}", dep, error),
+    }
+}
+
+/// Describes an error status, spelling out the ones with a common cause.
+fn describe_status(status: reqwest::StatusCode) -> String {
+    match status.as_u16() {
+        401 => "Unauthorized: Invalid or insufficient token permissions.".to_string(),
+        403 => "Forbidden: Access denied.".to_string(),
+        404 => "Not Found: It does not exist or access is denied.".to_string(),
+        _ => format!("HTTP {}", status),
+    }
+}
+
+/// Returns the configured HTTP settings, or the defaults if wdm.yml was not read.
+fn http_settings() -> HttpSettings {
+    HTTP_SETTINGS.get().copied().unwrap_or_default()
+}
+
+/// Numbers in-progress downloads so each one gets its own temporary file.
+static PARTIAL_DOWNLOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Copies `reader` into a new file at `path`, returning the sha256 hex digest of the data.
+fn stream_to_file(
+    mut reader: impl Read,
+    path: &Path,
+    progress: &mut Progress,
+) -> Result<String, SourceError> {
+    let cache_error = |action| {
+        move |source| SourceError::Cache {
+            action,
+            path: path.to_path_buf(),
+            source,
+        }
+    };
+    let mut file = fs::File::create(path).map_err(cache_error("create"))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+
+    loop {
+        let n = reader.read(&mut buf).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::TimedOut {
+                SourceError::TimedOut(http_settings().timeout)
+            } else {
+                SourceError::Body(e.into())
+            }
+        })?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        file.write_all(&buf[..n]).map_err(cache_error("write"))?;
+        progress.advance(n as u64);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Settings in the config section of wdm.yml that fill in for fields a
+/// dependency leaves out, recorded when wdm.yml is loaded.
+struct DependencyDefaults {
+    /// `config.github_host`, for GitHub dependencies without a `host`
+    github_host: Option<String>,
+    /// `config.default_token_env`, for dependencies without a `token_env`
+    token_env: Option<String>,
+    /// `config.resolver`, for GitHub dependencies without a token
+    resolver: Option<Resolver>,
+}
+
+static DEPENDENCY_DEFAULTS: OnceLock<DependencyDefaults> = OnceLock::new();
+
+/// The URLs a GitHub dependency is fetched from: github.com, or a GitHub
+/// Enterprise Server named by the dependency's `host` or `config.github_host`.
+struct GithubHost {
+    base_url: String,
+    api_url: String,
+}
+
+impl GithubHost {
+    fn of(dep: &Dependency) -> GithubHost {
+        match github_host_name(dep.host.as_deref()) {
+            Some(host) => {
+                let base_url = if host.starts_with("http://") || host.starts_with("https://") {
+                    host.trim_end_matches('/').to_string()
+                } else {
+                    format!("https://{}", host.trim_end_matches('/'))
+                };
+                GithubHost {
+                    api_url: format!("{}/api/v3", base_url),
+                    base_url,
+                }
+            }
+            None => GithubHost {
+                base_url: github_base_url(),
+                api_url: github_api_url(),
+            },
+        }
+    }
+
+    /// Whether `url` points at this host, so a token meant for it may be sent there.
+    fn owns(&self, url: &str) -> bool {
+        [&self.base_url, &self.api_url].iter().any(|base| {
+            url.strip_prefix(base.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+/// The GitHub Enterprise Server a GitHub dependency with `host` comes from,
+/// falling back to `config.github_host`; `None` means github.com.
+fn github_host_name(host: Option<&str>) -> Option<String> {
+    host.map(str::to_string)
+        .or_else(|| DEPENDENCY_DEFAULTS.get()?.github_host.clone())
+        .filter(|host| !host.trim().is_empty())
+}
+
+/// The variables GitHub tooling conventionally keeps a github.com token in.
+const GITHUB_TOKEN_VARS: [&str; 2] = ["GITHUB_TOKEN", "GH_TOKEN"];
+
+/// Finds the token for `dep`, returning the variable it came from as well.
+///
+/// The dependency's own `token_env` is tried first, then `default_token_env`
+/// for dependencies from GitHub, GitLab, or Bitbucket, and finally
+/// `GITHUB_TOKEN` and `GH_TOKEN` for github.com. The first variable with a
+/// non-empty value wins. Other sources never get a default, so a token is
+/// never sent to an arbitrary download URL.
+fn token_source(dep: &Dependency, default_token_env: Option<&str>) -> Option<(String, String)> {
+    let source = dep.source.unwrap_or_default();
+    let repository = matches!(source, Source::Github | Source::Gitlab | Source::Bitbucket);
+    let github_com = source == Source::Github && github_host_name(dep.host.as_deref()).is_none();
+    dep.token_env
+        .as_deref()
+        .into_iter()
+        .chain(default_token_env.filter(|_| repository))
+        .chain(GITHUB_TOKEN_VARS.into_iter().filter(|_| github_com))
+        .find_map(|var| {
+            env::var(var)
+                .ok()
+                .filter(|token| !token.is_empty())
+                .map(|token| (var.to_string(), token))
+        })
+}
+
+/// Tokens read by `dependency_token`, which `redact` masks wherever they
+/// would otherwise be printed.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Remembers `token` so that `redact` masks it. A Bitbucket `user:app_password`
+/// is also registered by its password, which is what ends up in URLs.
+fn register_secret(token: &str) {
+    let mut secrets = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    let password = token.split_once(':').map(|(_, password)| password);
+    for secret in std::iter::once(token).chain(password) {
+        if !secret.is_empty() && !secrets.iter().any(|s| s == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+    // Replace longer secrets first, so one can't leave part of another behind
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+/// Shows enough of a token to tell which one was used: its first four
+/// characters followed by `…`. Short values are hidden entirely.
+fn mask_secret(secret: &str) -> String {
+    if secret.chars().count() < 12 {
+        return "…".to_string();
+    }
+    format!("{}…", secret.chars().take(4).collect::<String>())
+}
+
+/// Masks credentials in text about to be printed: tokens registered with
+/// `register_secret`, passwords in `user:password@host` URLs, and query
+/// parameters such as `access_token=`.
+fn redact(text: &str) -> String {
+    static URL_CREDENTIALS: OnceLock<Regex> = OnceLock::new();
+    let userinfo = URL_CREDENTIALS.get_or_init(|| {
+        Regex::new(r"(://[^/\s:@]+:)([^/\s@]+)@").expect("the credentials pattern is valid")
+    });
+    static QUERY_TOKEN: OnceLock<Regex> = OnceLock::new();
+    let query = QUERY_TOKEN.get_or_init(|| {
+        Regex::new(
+            r"(?i)([?&](?:access_token|private_token|token|api_key|key|sig|signature)=)([^&\s#]+)",
+        )
+        .expect("the query pattern is valid")
+    });
+    let mut text = text.to_string();
+    for secret in SECRETS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        if text.contains(secret.as_str()) {
+            text = text.replace(secret.as_str(), &mask_secret(secret));
+        }
+    }
+    let text = userinfo.replace_all(&text, |c: &regex::Captures| {
+        format!("{}{}@", &c[1], mask_secret(&c[2]))
+    });
+    query
+        .replace_all(&text, |c: &regex::Captures| {
+            format!("{}{}", &c[1], mask_secret(&c[2]))
+        })
+        .into_owned()
+}
+
+/// Drops the password from `user:password@host` URLs and token query
+/// parameters, so that a URL can be written to wdm.lock.
+fn strip_credentials(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) => {
+            let _ = parsed.set_password(None);
+            let sensitive = |key: &str| {
+                matches!(
+                    key.to_ascii_lowercase().as_str(),
+                    "access_token"
+                        | "private_token"
+                        | "token"
+                        | "api_key"
+                        | "key"
+                        | "sig"
+                        | "signature"
+                )
+            };
+            if parsed.query_pairs().any(|(key, _)| sensitive(&key)) {
+                let kept: Vec<(String, String)> = parsed
+                    .query_pairs()
+                    .filter(|(key, _)| !sensitive(key))
+                    .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                    .collect();
+                if kept.is_empty() {
+                    parsed.set_query(None);
+                } else {
+                    parsed.query_pairs_mut().clear().extend_pairs(kept);
+                }
+            }
+            parsed.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Returns the token to authenticate `dep` with, if any (see `token_source`).
+///
+/// Only the name of the variable is ever printed, and the token is registered
+/// with `redact` in case a server echoes it back. A `token_env` that isn't
+/// set is reported, since the dependency is then fetched without it.
+fn dependency_token(dep: &Dependency) -> Option<String> {
+    let default_token_env = DEPENDENCY_DEFAULTS
+        .get()
+        .and_then(|d| d.token_env.as_deref());
+    let found = token_source(dep, default_token_env);
+    match (&found, &dep.token_env) {
+        (Some((var, _)), _) => verbose!("Authenticating {} with {}", dep.name, var),
+        (None, Some(token_env)) => warning!(
+            "{} is not set; fetching {} without authentication",
+            token_env,
+            dep.name
+        ),
+        (None, None) => {}
+    }
+    let token = found.map(|(_, token)| token);
+    if let Some(token) = &token {
+        register_secret(token);
+    }
+    token
+}
+
+/// Returns the base URL used to reach GitHub.
+///
+/// Defaults to `https://github.com` and can be overridden with the
+/// `WDM_GITHUB_URL` environment variable, e.g. to point wdm at a mirror or a
+/// local test server.
+fn github_base_url() -> String {
+    env::var("WDM_GITHUB_URL")
+        .unwrap_or_else(|_| "https://github.com".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Returns the base URL of the GitHub REST API.
+///
+/// Defaults to `https://api.github.com` and can be overridden with the
+/// `WDM_GITHUB_API_URL` environment variable.
+fn github_api_url() -> String {
+    env::var("WDM_GITHUB_API_URL")
+        .unwrap_or_else(|_| "https://api.github.com".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Returns the base URL Bitbucket archives are downloaded from (`WDM_BITBUCKET_URL`).
+fn bitbucket_base_url() -> String {
+    env::var("WDM_BITBUCKET_URL")
+        .unwrap_or_else(|_| "https://bitbucket.org".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Returns the base URL of the Bitbucket REST API (`WDM_BITBUCKET_API_URL`).
+fn bitbucket_api_url() -> String {
+    env::var("WDM_BITBUCKET_API_URL")
+        .unwrap_or_else(|_| "https://api.bitbucket.org".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Returns the base URL of the WordPress.org API (`WDM_WORDPRESS_ORG_API_URL`).
+fn wordpress_org_api_url() -> String {
+    env::var("WDM_WORDPRESS_ORG_API_URL")
+        .unwrap_or_else(|_| "https://api.wordpress.org".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Returns the base URL WordPress.org plugin zips are downloaded from
+/// (`WDM_WORDPRESS_ORG_DOWNLOADS_URL`).
+fn wordpress_org_downloads_url() -> String {
+    env::var("WDM_WORDPRESS_ORG_DOWNLOADS_URL")
+        .unwrap_or_else(|_| "https://downloads.wordpress.org".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Checks if Git is installed and accessible.
+///
+/// # Returns
+///
+/// * `Ok(())` if Git is installed.
+/// * `Err(SourceError::GitUnavailable)` if `git` could not be run at all.
+/// * `Err(SourceError::GitMissing)` if `git --version` exited unsuccessfully.
+fn check_git_installed() -> Result<(), SourceError> {
+    let status = Command::new("git")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(SourceError::GitUnavailable)?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(SourceError::GitMissing)
+    }
+}
+
+/// The outcome of one `wdm doctor` check.
+#[derive(Serialize, Debug)]
+struct DoctorCheck {
+    name: String,
+    status: DoctorStatus,
+    message: String,
+    /// How to fix a failed check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+}
+
+/// Only `Fail` makes `wdm doctor` exit non-zero; `Warn` covers things wdm can work without.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum DoctorStatus {
+    Ok,
+    Warn,
+    Fail,
+}
 
-                let response = match download_with_http(&dep.repo, &version, token.as_deref()) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        println!("Error downloading {}: {}", dep.name, e);
-                        continue;
-                    }
-                };
+impl std::fmt::Display for DoctorStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.pad(match self {
+            DoctorStatus::Ok => "ok",
+            DoctorStatus::Warn => "warn",
+            DoctorStatus::Fail => "FAIL",
+        })
+    }
+}
 
-                // Define the installation directory inside wp-content/plugins with the plugin's name
-                let plugin_install_dir = wordpress_path.join("wp-content/plugins").join(&dep.name);
+impl DoctorCheck {
+    fn new(name: &str, status: DoctorStatus, message: String, hint: Option<&str>) -> Self {
+        DoctorCheck {
+            name: name.to_string(),
+            status,
+            message,
+            hint: hint.map(str::to_string),
+        }
+    }
 
-                // Check if the plugin is already installed by verifying the existence of the directory
-                if plugin_install_dir.exists() {
-                    println!(
-                        "{} is already installed in {:?}",
-                        dep.name, plugin_install_dir
-                    );
-                    continue;
-                }
+    fn ok(name: &str, message: String) -> Self {
+        Self::new(name, DoctorStatus::Ok, message, None)
+    }
 
-                // Save the zip to .wdm-cache
-                let cache_plugin_dir = cache_dir.join(format!("{}.zip", dep.name));
-                fs::write(&cache_plugin_dir, &response)?;
-                println!("Saved {} to cache at {:?}", dep.name, cache_plugin_dir);
+    fn warn(name: &str, message: String, hint: Option<&str>) -> Self {
+        Self::new(name, DoctorStatus::Warn, message, hint)
+    }
 
-                // Extract the zip file into the plugin_install_dir
-                let mut zip = match ZipArchive::new(std::io::Cursor::new(&response)) {
-                    Ok(zip) => zip,
-                    Err(e) => {
-                        println!("Error reading zip for {}: {}", dep.name, e);
-                        continue;
-                    }
-                };
+    fn fail(name: &str, message: String, hint: Option<&str>) -> Self {
+        Self::new(name, DoctorStatus::Fail, message, hint)
+    }
+}
 
-                for i in 0..zip.len() {
-                    let mut file = match zip.by_index(i) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            println!("Error accessing file {} in zip: {}", i, e);
-                            continue;
-                        }
-                    };
-                    let outpath = match file.enclosed_name().and_then(|name| {
-                        // Construct the prefix based on repo and version without 'v'
-                        let repo_name = dep.repo.split('/').last().unwrap();
-                        let version_no_v = version.trim_start_matches('v');
-                        let prefix = format!("{}-{}", repo_name, version_no_v);
-                        name.strip_prefix(prefix.as_str()).ok()
-                    }) {
-                        Some(path) => plugin_install_dir.join(path),
-                        None => {
-                            println!("Invalid file path in zip for {}", dep.name);
-                            continue;
-                        }
-                    };
+/// Checks that git, which resolves GitHub tags unless `resolver` is `api`,
+/// can be run. Without git, tags are listed through the GitHub API, so it is
+/// only a failure when wdm.yml sets `resolver: git`.
+fn doctor_git(resolver: Option<Resolver>) -> DoctorCheck {
+    const HINT: &str = "install git from https://git-scm.com and make sure it is on your PATH";
+    match (check_git_installed(), resolver) {
+        (Ok(()), _) => DoctorCheck::ok("git", "git is installed".to_string()),
+        (Err(_), Some(Resolver::Api)) => DoctorCheck::ok(
+            "git",
+            "git is not installed, and not needed with resolver: api".to_string(),
+        ),
+        (Err(e), Some(Resolver::Git)) => DoctorCheck::fail("git", e.to_string(), Some(HINT)),
+        (Err(_), None) => DoctorCheck::warn(
+            "git",
+            "git is not installed; GitHub tags are listed through the API, which is rate limited without a token"
+                .to_string(),
+            Some(HINT),
+        ),
+    }
+}
 
-                    if file.name().ends_with('/') {
-                        if let Err(e) = fs::create_dir_all(&outpath) {
-                            println!("Error creating directory {:?}: {}", outpath, e);
-                            continue;
-                        }
-                    } else {
-                        if let Some(p) = outpath.parent() {
-                            if let Err(e) = fs::create_dir_all(&p) {
-                                println!("Error creating directory {:?}: {}", p, e);
-                                continue;
-                            }
-                        }
-                        let mut outfile = match fs::File::create(&outpath) {
-                            Ok(f) => f,
-                            Err(e) => {
-                                println!("Error creating file {:?}: {}", outpath, e);
-                                continue;
-                            }
-                        };
-                        if let Err(e) = std::io::copy(&mut file, &mut outfile) {
-                            println!("Error writing to file {:?}: {}", outpath, e);
-                            continue;
-                        }
-                    }
-                }
+/// Checks that the manifest at `path` parses, returning it if it does.
+fn doctor_manifest(path: &Path) -> (DoctorCheck, Option<Config>) {
+    match read_manifest(path) {
+        Ok(config) => (
+            DoctorCheck::ok("wdm.yml", format!("{} parses", path.display())),
+            Some(config),
+        ),
+        Err(e) => (DoctorCheck::fail("wdm.yml", e.to_string(), None), None),
+    }
+}
 
-                // Update the lockfile
-                lockfile.dependencies.retain(|d| d.name != dep.name);
-                lockfile.dependencies.push(LockedDependency {
-                    name: dep.name.clone(),
-                    version: version.clone(),
-                    repo: dep.repo.clone(),
-                    hash: format!("{:x}", Sha256::digest(&response)),
-                });
+/// Checks that the lockfile at `path` parses; not having one yet is fine.
+fn doctor_lockfile(path: &Path) -> DoctorCheck {
+    if !path.exists() {
+        return DoctorCheck::ok(
+            "wdm.lock",
+            "wdm.lock does not exist yet; 'wdm install' creates it".to_string(),
+        );
+    }
+    match parse_lockfile(path) {
+        Ok(_) => DoctorCheck::ok("wdm.lock", format!("{} parses", path.display())),
+        Err(e @ WdmError::UnsupportedLockfile { .. }) => {
+            DoctorCheck::fail("wdm.lock", e.to_string(), None)
+        }
+        Err(e) => DoctorCheck::fail(
+            "wdm.lock",
+            e.to_string(),
+            Some("restore wdm.lock from version control, or delete it and run 'wdm install'"),
+        ),
+    }
+}
 
-                println!("Installed {} {}", dep.name, version);
-            }
+/// Checks that `wordpress_path` is a directory that looks like a WordPress install.
+fn doctor_wordpress_path(wordpress_path: &Path) -> DoctorCheck {
+    let hint = Some("set config.wordpress_path in wdm.yml to your WordPress directory");
+    if !wordpress_path.is_dir() {
+        DoctorCheck::fail(
+            "wordpress_path",
+            format!("{:?} does not exist", wordpress_path),
+            hint,
+        )
+    } else if !wordpress_path.join("wp-content").is_dir() {
+        DoctorCheck::fail(
+            "wordpress_path",
+            format!("{:?} has no wp-content directory", wordpress_path),
+            hint,
+        )
+    } else {
+        DoctorCheck::ok(
+            "wordpress_path",
+            format!("{:?} contains wp-content", wordpress_path),
+        )
+    }
+}
 
-            // Write the updated lockfile at root_dir
-            fs::write(&lockfile_path, serde_yaml::to_string(&lockfile)?)?;
-            println!("Updated lockfile at {:?}", lockfile_path);
+/// Checks that plugins can be installed into `wp-content/plugins`.
+fn doctor_content_dir(wordpress_path: &Path) -> DoctorCheck {
+    let name = DependencyType::Plugin.content_dir();
+    let dir = wordpress_path.join(name);
+    match check_writable(&dir) {
+        Ok(()) => DoctorCheck::ok(name, format!("{:?} is writable", dir)),
+        Err(e) => DoctorCheck::fail(
+            name,
+            format!("{:?} is not writable: {}", dir, e),
+            Some("check the owner and permissions of the directory"),
+        ),
+    }
+}
 
-            Ok(())
+/// Checks that archives can be saved to the cache directory.
+fn doctor_cache(dir: &Path) -> DoctorCheck {
+    match check_writable(dir) {
+        Ok(()) => DoctorCheck::ok("cache", format!("{:?} is writable", dir)),
+        Err(e) => DoctorCheck::fail(
+            "cache",
+            format!("{:?} is not writable: {}", dir, e),
+            Some("fix its permissions, or point cache_dir or WDM_CACHE_DIR somewhere writable"),
+        ),
+    }
+}
+
+/// Checks that every `token_env` named in wdm.yml is set.
+fn doctor_tokens(config: &Config) -> Vec<DoctorCheck> {
+    let mut checks: Vec<DoctorCheck> = Vec::new();
+    for dep in config.selected_dependencies(true) {
+        let Some(token_env) = &dep.token_env else {
+            continue;
+        };
+        let name = format!("token_env {}", token_env);
+        if checks.iter().any(|c| c.name == name) {
+            continue;
         }
+        let found = token_source(dep, config.config.default_token_env.as_deref());
+        checks.push(match found {
+            Some((var, _)) if &var == token_env => DoctorCheck::ok(
+                &name,
+                format!("{} is set (used by {})", token_env, dep.name),
+            ),
+            Some((var, _)) => DoctorCheck::warn(
+                &name,
+                format!(
+                    "{} is not set, so {} falls back to {}",
+                    token_env, dep.name, var
+                ),
+                Some("export the variable if the fallback token can't read the repository"),
+            ),
+            None => DoctorCheck::fail(
+                &name,
+                format!("{} is not set, but {} needs it", token_env, dep.name),
+                Some("export the variable with a token that can read the repository"),
+            ),
+        });
     }
+    checks
 }
 
-/// Installs a single dependency.
-///
-/// This function encapsulates the installation logic, making it reusable.
-/// It takes a reference to a `Dependency` struct and performs the necessary steps
-/// to download and install the plugin.
-fn install_dependency(dep: &Dependency) -> Result<(), Box<dyn std::error::Error>> {
-    if !Path::new("wdm.yml").exists() {
-        println!("wdm.yml does not exist. Run 'wdm init' first.");
-        return Ok(());
+/// Checks that `url` answers at all. wdm can still install from the cache
+/// without the network, so this only warns.
+fn doctor_network(url: &str) -> DoctorCheck {
+    match http_client().head(url).timeout(Duration::from_secs(5)).send() {
+        Ok(_) => DoctorCheck::ok("network", format!("{} is reachable", url)),
+        Err(e) => DoctorCheck::warn(
+            "network",
+            format!("{} is not reachable: {}", url, describe_request_error(&e)),
+            Some("check your connection and proxy settings; 'wdm install --offline' works without the network"),
+        ),
     }
+}
 
-    let config: Config = serde_yaml::from_str(&fs::read_to_string("wdm.yml")?)?;
-    let lockfile = if Path::new("wdm.lock").exists() {
-        serde_yaml::from_str(&fs::read_to_string("wdm.lock")?)?
-    } else {
-        Lockfile {
-            dependencies: Vec::new(),
-        }
-    };
+/// Creates and deletes a scratch file in `dir`, or in its closest existing
+/// ancestor when wdm would create `dir` itself.
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    let existing = dir
+        .ancestors()
+        .find(|d| d.is_dir())
+        .unwrap_or_else(|| Path::new("."));
+    let probe = existing.join(format!(".wdm-doctor-{}", std::process::id()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(&probe)
+}
 
-    // Determine the root directory (where wdm.yml is located)
-    let root_dir = resolve_root_dir()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Ensure that .wdm-cache directory is at root_dir
-    let cache_dir = root_dir.join(".wdm-cache");
-    if !cache_dir.exists() {
-        fs::create_dir_all(&cache_dir)?;
+    /// A dependency as wdm.yml would spell it, e.g. `name: x\nversion: 1.0.0`.
+    fn dependency(yaml: &str) -> Dependency {
+        serde_yaml::from_str(yaml).unwrap()
     }
 
-    // Set wordpress_path to default to '.' if not specified
-    let wordpress_path = if let Some(path) = &config.config.wordpress_path {
-        Path::new(&path).to_path_buf()
-    } else {
-        Path::new(".").to_path_buf()
-    };
-
-    println!("Installing {}...", dep.name);
+    /// Writes a zip archive holding `files` to `path`.
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
 
-    let token = if let Some(token_env) = &dep.token_env {
-        env::var(token_env).ok()
-    } else {
-        None
-    };
+    /// An install context for `config` with everything kept under `dir`.
+    fn install_context<'a>(config: &'a Config, dir: &Path) -> InstallContext<'a> {
+        let wordpress_path = dir.join("wordpress");
+        fs::create_dir_all(wordpress_path.join("wp-content/plugins")).unwrap();
+        InstallContext {
+            config,
+            root_dir: dir.to_path_buf(),
+            wordpress_path,
+            cache: Cache {
+                dir: dir.join(".wdm-cache"),
+                shared: false,
+                vendor_dir: dir.join("vendor"),
+                backups_dir: dir.join(".wdm-cache/backups"),
+            },
+            lockfile: Lockfile::new(),
+            insecure_skip_verify: false,
+            wordpress_version: None,
+            strict_compat: false,
+            strict_headers: false,
+            with_requirements: false,
+        }
+    }
 
-    let version = match resolve_github_version(&dep.repo, &dep.version, token.as_deref()) {
-        Ok(ver) => ver,
-        Err(e) => {
-            println!("Error resolving version for {}: {}", dep.name, e);
-            return Ok(());
+    /// A freshly downloaded archive of `files`, as `fetch_dependency` returns it.
+    fn downloaded(dir: &Path, files: &[(&str, &str)], locked_hash: Option<&str>) -> FetchOutcome {
+        let archive = dir.join("widget-v1.0.0.zip");
+        write_zip(&archive, files);
+        FetchOutcome::Downloaded {
+            version: "v1.0.0".to_string(),
+            locked_hash: locked_hash.map(str::to_string),
+            hash: hash_file(&archive).unwrap(),
+            archive,
+            resolved_url: None,
+            cached: false,
+            elapsed: Duration::ZERO,
         }
-    };
+    }
 
-    let response = match download_with_http(&dep.repo, &version, token.as_deref()) {
-        Ok(data) => data,
-        Err(e) => {
-            println!("Error downloading {}: {}", dep.name, e);
-            return Ok(());
+    #[test]
+    fn test_install_outcomes_map_to_the_same_named_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::new(".".to_string());
+        let mut ctx = install_context(&config, dir.path());
+        let dep = dependency("name: widget\nversion: ^1.0\nrepo: acme/widget\n");
+        let cases = [
+            (
+                FetchOutcome::Skipped("widget is already installed".to_string()),
+                InstallStatus::Skipped,
+            ),
+            (
+                FetchOutcome::UpToDate("widget is up to date".to_string()),
+                InstallStatus::UpToDate,
+            ),
+            (
+                FetchOutcome::Failed(WdmError::VersionResolution {
+                    dep: "widget".to_string(),
+                    from: Source::Github,
+                    source: ResolveError::NoVersions("repository tags"),
+                }),
+                InstallStatus::Failed,
+            ),
+            (
+                downloaded(dir.path(), &[("widget/widget.php", "<?php")], None),
+                InstallStatus::Installed,
+            ),
+        ];
+        for (fetched, status) in cases {
+            let outcome = install_one(&mut ctx, &dep, fetched);
+            assert_eq!(InstallOutcome::status(&outcome), status);
+            assert_eq!(ctx.report(&dep, &outcome).status, status);
         }
-    };
+        let installed = ctx.wordpress_path.join("wp-content/plugins/widget");
+        assert!(installed.join("widget.php").is_file());
+        assert_eq!(ctx.lockfile.dependencies.len(), 1);
+        assert_eq!(ctx.lockfile.dependencies[0].version, "v1.0.0");
+    }
 
-    // Define the installation directory inside wp-content/plugins with the plugin's name
-    let plugin_install_dir = wordpress_path.join("wp-content/plugins").join(&dep.name);
+    #[test]
+    fn test_install_one_refuses_an_archive_that_does_not_match_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::new(".".to_string());
+        let mut ctx = install_context(&config, dir.path());
+        let dep = dependency("name: widget\nversion: 1.0.0\nrepo: acme/widget\n");
+        let locked = "0".repeat(64);
 
-    // Check if the plugin is already installed by verifying the existence of the directory
-    if plugin_install_dir.exists() {
-        println!(
-            "{} is already installed in {:?}",
-            dep.name, plugin_install_dir
+        let fetched = downloaded(dir.path(), &[("widget/widget.php", "<?php")], Some(&locked));
+        let outcome = install_one(&mut ctx, &dep, fetched);
+        assert!(matches!(outcome, Err(WdmError::HashMismatch { .. })));
+        assert_eq!(ctx.report(&dep, &outcome).status, InstallStatus::Failed);
+        assert!(!dir.path().join("widget-v1.0.0.zip").exists());
+        assert!(ctx.lockfile.dependencies.is_empty());
+
+        ctx.insecure_skip_verify = true;
+        let fetched = downloaded(dir.path(), &[("widget/widget.php", "<?php")], Some(&locked));
+        let outcome = install_one(&mut ctx, &dep, fetched);
+        assert!(matches!(outcome, Ok(InstallOutcome::Installed { .. })));
+        assert_eq!(ctx.report(&dep, &outcome).status, InstallStatus::Installed);
+    }
+
+    /// The versions `select_tag` would consider for these tag names.
+    fn tag_versions(tags: &[&str]) -> Vec<(Version, String)> {
+        tags.iter()
+            .filter_map(|tag| parse_tag(tag, None).map(|ver| (ver, tag.to_string())))
+            .collect()
+    }
+
+    const RELEASE_TAGS: [&str; 5] = ["v2.0.0", "v2.1.0", "v3.0.0-beta.1", "v3.0.0-rc.1", "v2.2.0"];
+
+    #[test]
+    fn test_latest_skips_prereleases_unless_allowed() {
+        let versions = tag_versions(&RELEASE_TAGS);
+        assert_eq!(
+            select_version(versions.clone(), "latest", false).unwrap(),
+            "v2.2.0"
+        );
+        assert_eq!(
+            select_version(versions, "latest", true).unwrap(),
+            "v3.0.0-rc.1"
+        );
+
+        let only_prereleases = tag_versions(&["v1.0.0-alpha", "v1.0.0-beta"]);
+        let error = select_version(only_prereleases.clone(), "latest", false).unwrap_err();
+        assert!(matches!(error, ResolveError::OnlyPrereleases), "{}", error);
+        assert_eq!(
+            select_version(only_prereleases, "latest", true).unwrap(),
+            "v1.0.0-beta"
         );
-        return Ok(());
     }
 
-    // Save the zip to .wdm-cache
-    let cache_plugin_dir = cache_dir.join(format!("{}.zip", dep.name));
-    fs::write(&cache_plugin_dir, &response)?;
-    println!("Saved {} to cache at {:?}", dep.name, cache_plugin_dir);
+    #[test]
+    fn test_ranges_skip_prereleases_unless_allowed() {
+        let versions = tag_versions(&RELEASE_TAGS);
+        assert_eq!(
+            select_version(versions.clone(), "^2.0", false).unwrap(),
+            "v2.2.0"
+        );
+        assert!(select_version(versions.clone(), "^3.0", false).is_err());
+        assert_eq!(
+            select_version(versions.clone(), "^3.0", true).unwrap(),
+            "v3.0.0-rc.1"
+        );
+        // A requirement naming a prerelease of the same version accepts it either way
+        assert_eq!(
+            select_version(versions, ">=3.0.0-beta.1", false).unwrap(),
+            "v3.0.0-rc.1"
+        );
+    }
 
-    // Extract the zip file into the plugin_install_dir
-    let mut zip = match ZipArchive::new(std::io::Cursor::new(&response)) {
-        Ok(zip) => zip,
-        Err(e) => {
-            println!("Error reading zip for {}: {}", dep.name, e);
-            return Ok(());
+    #[test]
+    fn test_exact_prereleases_are_always_found() {
+        let versions = tag_versions(&RELEASE_TAGS);
+        for allow_prerelease in [false, true] {
+            assert_eq!(
+                select_version(versions.clone(), "3.0.0-beta.1", allow_prerelease).unwrap(),
+                "v3.0.0-beta.1"
+            );
+            assert_eq!(
+                select_version(versions.clone(), "v2.1.0", allow_prerelease).unwrap(),
+                "v2.1.0"
+            );
         }
-    };
+        let error = select_version(versions, "4.0.0", true).unwrap_err();
+        assert!(
+            matches!(&error, ResolveError::VersionNotFound(version) if version == "4.0.0"),
+            "{}",
+            error
+        );
+    }
 
-    for i in 0..zip.len() {
-        let mut file = match zip.by_index(i) {
-            Ok(f) => f,
-            Err(e) => {
-                println!("Error accessing file {} in zip: {}", i, e);
-                continue;
-            }
-        };
-        let outpath = match file.enclosed_name().and_then(|name| {
-            // Construct the prefix based on repo and version without 'v'
-            let repo_name = dep.repo.split('/').last().unwrap();
-            let version_no_v = version.trim_start_matches('v');
-            let prefix = format!("{}-{}", repo_name, version_no_v);
-            name.strip_prefix(prefix.as_str()).ok()
-        }) {
-            Some(path) => plugin_install_dir.join(path),
-            None => {
-                println!("Invalid file path in zip for {}", dep.name);
-                continue;
-            }
-        };
+    #[test]
+    fn test_single_top_level_dir_is_found_only_when_everything_shares_it() {
+        assert_eq!(
+            single_top_level_dir(["widget-2.5.0/", "widget-2.5.0/widget.php"]),
+            Some("widget-2.5.0".to_string())
+        );
+        // The folder doesn't need an entry of its own
+        assert_eq!(
+            single_top_level_dir(["v2.5.0-beta/widget.php", "v2.5.0-beta/inc/a.php"]),
+            Some("v2.5.0-beta".to_string())
+        );
+        assert_eq!(single_top_level_dir(["widget.php", "inc/a.php"]), None);
+        assert_eq!(
+            single_top_level_dir(["widget/widget.php", "docs/README.md"]),
+            None
+        );
+        // A lone file is not a folder to strip
+        assert_eq!(single_top_level_dir(["widget.php"]), None);
+        assert_eq!(single_top_level_dir(Vec::<&str>::new()), None);
+    }
 
-        if file.name().ends_with('/') {
-            if let Err(e) = fs::create_dir_all(&outpath) {
-                println!("Error creating directory {:?}: {}", outpath, e);
-                continue;
-            }
-        } else {
-            if let Some(p) = outpath.parent() {
-                if let Err(e) = fs::create_dir_all(&p) {
-                    println!("Error creating directory {:?}: {}", p, e);
-                    continue;
-                }
-            }
-            let mut outfile = match fs::File::create(&outpath) {
-                Ok(f) => f,
-                Err(e) => {
-                    println!("Error creating file {:?}: {}", outpath, e);
-                    continue;
-                }
-            };
-            if let Err(e) = std::io::copy(&mut file, &mut outfile) {
-                println!("Error writing to file {:?}: {}", outpath, e);
-                continue;
+    #[test]
+    fn test_extraction_strips_a_shared_folder_and_keeps_other_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        // Each dependency's name, archive entries, strip_prefix, and extracted files
+        type Layout<'a> = (
+            &'a str,
+            &'a [(&'a str, &'a str)],
+            Option<&'a str>,
+            &'a [&'a str],
+        );
+        let layouts: [Layout; 4] = [
+            (
+                "tagged",
+                &[
+                    ("tagged-v2.5.0/tagged.php", "<?php"),
+                    ("tagged-v2.5.0/inc/a.php", "a"),
+                ],
+                None,
+                &["inc/a.php", "tagged.php"],
+            ),
+            (
+                "flat",
+                &[("flat.php", "<?php"), ("inc/a.php", "a")],
+                None,
+                &["flat.php", "inc/a.php"],
+            ),
+            (
+                "split",
+                &[("split/split.php", "<?php"), ("docs/README.md", "docs")],
+                None,
+                &["docs/README.md", "split/split.php"],
+            ),
+            (
+                "pinned",
+                &[
+                    ("build/pinned/pinned.php", "<?php"),
+                    ("build/pinned.map", "map"),
+                ],
+                Some("build/pinned"),
+                &["pinned.php"],
+            ),
+        ];
+        for (name, files, strip_prefix, expected) in layouts {
+            let archive = dir.path().join(format!("{}.zip", name));
+            write_zip(&archive, files);
+            let mut dep = dependency(&format!(
+                "name: {}\nversion: 1.0.0\nrepo: acme/{}\n",
+                name, name
+            ));
+            dep.strip_prefix = strip_prefix.map(str::to_string);
+            let install_dir = dir.path().join("plugins").join(name);
+            let extracted = extract_archive(&dep, &archive, &install_dir).unwrap();
+            assert_eq!(extracted, expected, "{}", name);
+            for file in expected {
+                assert!(install_dir.join(file).is_file(), "{}: {}", name, file);
             }
         }
     }
 
-    // Update the lockfile
-    let mut updated_lockfile = lockfile;
-    updated_lockfile.dependencies.retain(|d| d.name != dep.name);
-    updated_lockfile.dependencies.push(LockedDependency {
-        name: dep.name.clone(),
-        version: version.clone(),
-        repo: dep.repo.clone(),
-        hash: format!("{:x}", Sha256::digest(&response)),
-    });
-
-    // Write the updated lockfile at root_dir
-    let lockfile_path = root_dir.join("wdm.lock");
-    fs::write(&lockfile_path, serde_yaml::to_string(&updated_lockfile)?)?;
-    println!("Installed {} {}", dep.name, version);
-    println!("Updated lockfile at {:?}", lockfile_path);
+    #[test]
+    fn test_doctor_checks_the_manifest_and_lockfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("wdm.yml");
+        fs::write(
+            &manifest,
+            "config:\n  wordpress_path: .\ndependencies: []\n",
+        )
+        .unwrap();
+        let (check, config) = doctor_manifest(&manifest);
+        assert_eq!(check.status, DoctorStatus::Ok);
+        assert!(config.is_some());
+        fs::write(&manifest, "config:\n  wordpress_path: .\ndependencies: [\n").unwrap();
+        let (check, config) = doctor_manifest(&manifest);
+        assert_eq!(check.status, DoctorStatus::Fail);
+        assert!(config.is_none());
 
-    Ok(())
-}
+        let lockfile = dir.path().join("wdm.lock");
+        let check = doctor_lockfile(&lockfile);
+        assert_eq!(check.status, DoctorStatus::Ok);
+        assert!(
+            check.message.contains("does not exist yet"),
+            "{}",
+            check.message
+        );
+        fs::write(&lockfile, "version: 2\ndependencies: []\n").unwrap();
+        assert_eq!(doctor_lockfile(&lockfile).status, DoctorStatus::Ok);
+        fs::write(&lockfile, "dependencies: [").unwrap();
+        let check = doctor_lockfile(&lockfile);
+        assert_eq!(check.status, DoctorStatus::Fail);
+        assert!(check.hint.is_some());
+        fs::write(&lockfile, "version: 99\ndependencies: []\n").unwrap();
+        assert_eq!(doctor_lockfile(&lockfile).status, DoctorStatus::Fail);
+    }
 
-/// Resolves the root directory where wdm.yml is located.
-fn resolve_root_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
-    // Determine the root directory (where wdm.yml is located)
-    let root_dir = Path::new("wdm.yml")
-        .parent()
-        .unwrap_or_else(|| Path::new("."))
-        .to_path_buf();
+    #[test]
+    fn test_doctor_checks_the_wordpress_and_cache_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let wordpress = dir.path().join("site");
+        let check = doctor_wordpress_path(&wordpress);
+        assert_eq!(check.status, DoctorStatus::Fail);
+        assert!(
+            check.message.ends_with("does not exist"),
+            "{}",
+            check.message
+        );
+        fs::create_dir_all(&wordpress).unwrap();
+        let check = doctor_wordpress_path(&wordpress);
+        assert_eq!(check.status, DoctorStatus::Fail);
+        assert!(
+            check.message.ends_with("has no wp-content directory"),
+            "{}",
+            check.message
+        );
+        fs::create_dir_all(wordpress.join("wp-content")).unwrap();
+        assert_eq!(doctor_wordpress_path(&wordpress).status, DoctorStatus::Ok);
 
-    // Ensure that .wdm-cache directory is at root_dir
-    let cache_dir = root_dir.join(".wdm-cache");
-    if !cache_dir.exists() {
-        fs::create_dir_all(&cache_dir)?;
+        // Directories wdm would create are checked through the closest one that exists
+        assert_eq!(doctor_content_dir(&wordpress).status, DoctorStatus::Ok);
+        assert_eq!(
+            doctor_cache(&dir.path().join(".wdm-cache")).status,
+            DoctorStatus::Ok
+        );
+        assert!(fs::read_dir(dir.path()).unwrap().all(|entry| !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with(".wdm-doctor")));
     }
 
-    Ok(root_dir)
-}
+    #[test]
+    fn test_doctor_checks_each_token_env_once() {
+        env::set_var("WDM_UNIT_DOCTOR_SET", "secret");
+        env::set_var("WDM_UNIT_DOCTOR_FALLBACK", "secret");
+        env::remove_var("WDM_UNIT_DOCTOR_UNSET");
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = dir.path().join("wdm.yml");
+        fs::write(
+            &manifest,
+            "config:\n  wordpress_path: .\ndependencies:\n\
+             - name: alpha\n  version: 1.0.0\n  repo: acme/alpha\n  source: gitlab\n  token_env: WDM_UNIT_DOCTOR_SET\n\
+             - name: beta\n  version: 1.0.0\n  repo: acme/beta\n  source: gitlab\n  token_env: WDM_UNIT_DOCTOR_SET\n\
+             - name: gamma\n  version: 1.0.0\n  repo: acme/gamma\n  source: gitlab\n  token_env: WDM_UNIT_DOCTOR_UNSET\n\
+             - name: delta\n  version: 1.0.0\n  source: wordpress.org\n",
+        )
+        .unwrap();
+        let mut config = read_manifest(&manifest).unwrap();
+        let statuses = |config: &Config| -> Vec<(String, DoctorStatus)> {
+            doctor_tokens(config)
+                .into_iter()
+                .map(|check| (check.name, check.status))
+                .collect()
+        };
+        assert_eq!(
+            statuses(&config),
+            [
+                (
+                    "token_env WDM_UNIT_DOCTOR_SET".to_string(),
+                    DoctorStatus::Ok
+                ),
+                (
+                    "token_env WDM_UNIT_DOCTOR_UNSET".to_string(),
+                    DoctorStatus::Fail
+                ),
+            ]
+        );
 
-/// Resolves the appropriate Git tag based on the version requirement using Git CLI.
-///
-/// # Arguments
-///
-/// * `repo` - The repository in the format "owner/repo".
-/// * `version_req` - The version requirement string (e.g., "^2.0.0").
-/// * `token` - Optional authentication token for private repositories.
-///
-/// # Returns
-///
-/// * `Ok(String)` containing the resolved version tag.
-/// * `Err(String)` with an error message.
-fn resolve_github_version(
-    repo: &str,
-    version_req: &str,
-    _token: Option<&str>, // Token is not used for public repos
-) -> Result<String, String> {
-    let repo_url = format!("https://github.com/{}.git", repo);
+        // A default token_env covers for one that isn't set, with a warning
+        config.config.default_token_env = Some("WDM_UNIT_DOCTOR_FALLBACK".to_string());
+        assert_eq!(
+            statuses(&config)[1],
+            (
+                "token_env WDM_UNIT_DOCTOR_UNSET".to_string(),
+                DoctorStatus::Warn
+            )
+        );
+    }
 
-    // Execute 'git ls-remote --tags <repo_url>' and capture the output without displaying it
-    let output = Command::new("git")
-        .args(&["ls-remote", "--tags", &repo_url])
-        .stdout(Stdio::piped()) // Capture stdout
-        .stderr(Stdio::piped()) // Capture stderr
-        .output()
-        .map_err(|e| format!("Failed to execute git: {}", e))?;
+    #[test]
+    fn test_doctor_only_warns_about_the_network_and_git_with_the_api() {
+        // Nothing listens on port 1, so this fails without leaving the machine
+        let check = doctor_network("http://127.0.0.1:1");
+        assert_eq!(check.status, DoctorStatus::Warn);
+        assert!(check.hint.unwrap().contains("--offline"));
+        // Whether or not git is installed, the API resolver doesn't need it
+        assert_eq!(doctor_git(Some(Resolver::Api)).status, DoctorStatus::Ok);
+    }
 
-    if !output.status.success() {
-        return Err(format!(
-            "Git command failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    #[test]
+    fn test_file_header_reads_fields_the_way_wordpress_does() {
+        let main_file = "\u{feff}<?php /* Plugin Name: Alpha */\r\n\
+                         /**\r\n \
+                         * requires at least: 6.2\r\n \
+                         * Requires PHP:   7.4 ?>\r\n \
+                         * Version:\r\n \
+                         */\r\n";
+        assert_eq!(
+            file_header(main_file, "Plugin Name").as_deref(),
+            Some("Alpha")
+        );
+        assert_eq!(
+            file_header(main_file, "Requires at least").as_deref(),
+            Some("6.2")
+        );
+        assert_eq!(
+            file_header(main_file, "Requires PHP").as_deref(),
+            Some("7.4")
+        );
+        // An empty field counts as missing, as does one that isn't there at all
+        assert_eq!(file_header(main_file, "Version"), None);
+        assert_eq!(file_header(main_file, "Requires Plugins"), None);
+        // A field's name has to start the comment line, not appear inside a value
+        assert_eq!(
+            file_header("Description: Requires PHP: 8.0", "Requires PHP"),
+            None
+        );
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut versions = Vec::new();
+    #[test]
+    fn test_read_package_headers_falls_back_to_the_readme() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("helpers.php"), "<?php\n// Version: 9.9.9\n").unwrap();
+        fs::write(
+            dir.path().join("alpha.php"),
+            "<?php\n/**\n * Plugin Name: Alpha\n * Version: 1.2.0\n * Requires PHP: 8.1\n\
+             * Requires Plugins: woocommerce, Create Block Theme,\n */\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("README.TXT"),
+            "=== Alpha ===\nRequires at least: 6.4\nRequires PHP: 7.0\n",
+        )
+        .unwrap();
+        let headers = read_package_headers(dir.path(), DependencyType::Plugin);
+        assert_eq!(headers.main_file.as_deref(), Some("alpha.php"));
+        assert_eq!(headers.name.as_deref(), Some("Alpha"));
+        assert_eq!(headers.version.as_deref(), Some("1.2.0"));
+        assert_eq!(headers.requires_wp.as_deref(), Some("6.4"));
+        assert_eq!(headers.requires_php.as_deref(), Some("8.1"));
+        assert_eq!(
+            headers.requires_plugins,
+            ["woocommerce", "create-block-theme"]
+        );
 
-    for line in stdout.lines() {
-        // Each line is of the format: <hash>\trefs/tags/<tag>
-        if let Some(pos) = line.find('\t') {
-            let tag_ref = &line[pos + 1..];
-            if let Some(tag) = tag_ref.strip_prefix("refs/tags/") {
-                // Handle annotated tags by stripping the ^{}
-                let tag = tag.trim_end_matches("^{}");
-                if let Ok(ver) = Version::parse(tag.trim_start_matches('v')) {
-                    versions.push(ver);
-                }
-            }
-        }
-    }
+        // Only plugins declare Requires Plugins, and a theme's headers are in style.css
+        fs::write(
+            dir.path().join("style.css"),
+            "/*\nTheme Name: Beta\nVersion: 2.0\n*/\n",
+        )
+        .unwrap();
+        let headers = read_package_headers(dir.path(), DependencyType::Theme);
+        assert_eq!(headers.main_file, None);
+        assert_eq!(headers.name.as_deref(), Some("Beta"));
+        assert_eq!(headers.version.as_deref(), Some("2.0"));
+        assert_eq!(headers.requires_php.as_deref(), Some("7.0"));
+        assert!(headers.requires_plugins.is_empty());
 
-    if versions.is_empty() {
-        return Err("No valid versions found in repository tags.".to_string());
+        let empty = tempfile::tempdir().unwrap();
+        let headers = read_package_headers(empty.path(), DependencyType::Plugin);
+        assert_eq!(
+            (headers.main_file, headers.name, headers.requires_wp),
+            (None, None, None)
+        );
     }
 
-    // Sort versions in descending order
-    versions.sort_by(|a, b| b.cmp(a));
+    #[test]
+    fn test_detect_wordpress_version() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(detect_wordpress_version(dir.path()), None);
+        fs::create_dir_all(dir.path().join("wp-includes")).unwrap();
+        fs::write(
+            dir.path().join("wp-includes/version.php"),
+            "<?php\n$wp_db_version = 57155;\n$wp_version = '6.5.2';\n",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_wordpress_version(dir.path()).as_deref(),
+            Some("6.5.2")
+        );
+    }
 
-    // Determine the desired version based on version_req
-    if version_req == "latest" {
-        return Ok(format!("v{}", versions[0]));
-    } else if let Ok(specific_version) = Version::parse(version_req) {
-        if versions.contains(&specific_version) {
-            return Ok(format!("v{}", specific_version));
-        } else {
-            return Err(format!(
-                "Version {} not found in repository tags",
+    #[test]
+    fn test_check_version_requirement_accepts_what_install_can_resolve() {
+        for version_req in [
+            "latest",
+            "2.5.0",
+            "v2.5.0",
+            "5.3",
+            "2.5.0.1",
+            "^2",
+            "~1.4",
+            ">=1.2, <2",
+            "*",
+            "branch=main",
+            "commit=0123abc",
+            "commit=0123ABC4567DEF",
+        ] {
+            assert!(
+                check_version_requirement(version_req, Source::Github).is_ok(),
+                "{} should be accepted",
                 version_req
-            ));
-        }
-    } else {
-        let req = VersionReq::parse(version_req)
-            .map_err(|e| format!("Invalid version requirement '{}': {}", version_req, e))?;
-        for ver in &versions {
-            if req.matches(ver) {
-                return Ok(format!("v{}", ver));
-            }
+            );
         }
-        Err(format!(
-            "No matching version found for requirement {}",
-            version_req
-        ))
     }
-}
-
-/// Downloads the specified tag of a repository using HTTP.
-///
-/// # Arguments
-///
-/// * `repo` - The repository in the format "owner/repo".
-/// * `version` - The specific version tag to download.
-/// * `token` - Optional authentication token for private repositories.
-///
-/// # Returns
-///
-/// * `Ok(Vec<u8>)` containing the ZIP archive data.
-/// * `Err(String)` with an error message.
-fn download_with_http(repo: &str, version: &str, token: Option<&str>) -> Result<Vec<u8>, String> {
-    let download_url = format!(
-        "https://github.com/{}/archive/refs/tags/{}.zip",
-        repo, version
-    );
 
-    let client = reqwest::blocking::Client::new();
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::USER_AGENT,
-        HeaderValue::from_static("wdm-cli"),
-    );
+    #[test]
+    fn test_check_version_requirement_rejects_garbage() {
+        for (version_req, message) in [
+            ("not-a-version", "unexpected character"),
+            (">=1.2 <2", "expected comma"),
+            ("^", "unexpected end of input"),
+            ("", "unexpected end of input"),
+            ("branch=", "branch= needs the name of a branch"),
+            ("commit=abc", "commit= needs a SHA"),
+            ("commit=main-branch", "commit= needs a SHA"),
+        ] {
+            let err = check_version_requirement(version_req, Source::Github).unwrap_err();
+            assert!(
+                err.to_string().contains(message),
+                "{:?} should be rejected with {:?}, got {}",
+                version_req,
+                message,
+                err
+            );
+        }
+        // A url dependency's version is only a label, so anything goes
+        assert!(check_version_requirement("not-a-version", Source::Url).is_ok());
+    }
 
-    // If a token is provided, add it to the headers for private repositories
-    if let Some(token) = token {
-        let auth_value = format!("Bearer {}", token);
-        headers.insert(
-            reqwest::header::AUTHORIZATION,
-            HeaderValue::from_str(&auth_value).map_err(|e| format!("Invalid token: {}", e))?,
+    #[test]
+    fn test_version_choices_mark_latest_and_odd_tags() {
+        let versions = tag_versions(&["v2.1.0-beta.1", "v2.0.0", "release-1.9", "1.8.0"]);
+        let shown: Vec<&(Version, String)> = versions.iter().collect();
+        assert_eq!(
+            version_choices("widget", &shown, false),
+            "Versions of widget:\n\
+             \x20  1) 2.1.0-beta.1\n\
+             \x20  2) 2.0.0 (latest)\n\
+             \x20  3) 1.9.0 (tag release-1.9)\n\
+             \x20  4) 1.8.0\n"
         );
+        assert!(version_choices("widget", &shown[1..], true).ends_with("   p) show prereleases\n"));
     }
 
-    let response = client
-        .get(&download_url)
-        .headers(headers)
-        .send()
-        .map_err(|e| format!("Failed to send HTTP request: {}", e))?;
+    /// Runs `pick_version_with` on `answers`, returning what it picked and
+    /// each list of choices it showed.
+    fn pick(dep: &Dependency, tags: &[&str], answers: &[&str]) -> (Version, Vec<String>) {
+        let versions = tag_versions(tags);
+        let mut answers = answers.iter();
+        let mut shown = Vec::new();
+        let picked = pick_version_with(dep, &versions, |choices| {
+            shown.push(choices.to_string());
+            Ok(answers
+                .next()
+                .expect("the picker asked too often")
+                .to_string())
+        })
+        .unwrap();
+        (picked, shown)
+    }
 
-    if response.status().is_success() {
-        Ok(response
-            .bytes()
-            .map_err(|e| format!("Failed to read response bytes: {}", e))?
-            .to_vec())
-    } else {
-        match response.status().as_u16() {
-            401 => Err("Unauthorized: Invalid or insufficient token permissions.".to_string()),
-            403 => Err("Forbidden: Access denied. Check token permissions.".to_string()),
-            404 => {
-                Err("Not Found: The specified tag does not exist or access is denied.".to_string())
-            }
-            _ => Err(format!(
-                "Failed to download from GitHub: HTTP {}",
-                response.status()
-            )),
-        }
+    #[test]
+    fn test_pick_version_hides_prereleases_until_asked() {
+        let dep = dependency("name: widget\nversion: latest\nrepo: acme/widget\n");
+        let tags = ["v2.1.0-beta.1", "v2.0.0", "v1.9.0"];
+
+        let (picked, shown) = pick(&dep, &tags, &[""]);
+        assert_eq!(picked.to_string(), "2.0.0");
+        assert!(!shown[0].contains("beta"), "{}", shown[0]);
+        assert!(shown[0].contains("p) show prereleases"), "{}", shown[0]);
+
+        // p lists them, after which it's no longer a choice; bad answers ask again
+        let (picked, shown) = pick(&dep, &tags, &["p", "9", "p", "1"]);
+        assert_eq!(picked.to_string(), "2.1.0-beta.1");
+        assert_eq!(shown.len(), 4);
+        assert!(shown[1].contains("1) 2.1.0-beta.1"), "{}", shown[1]);
+        assert!(!shown[1].contains("show prereleases"), "{}", shown[1]);
+
+        // Prereleases are shown from the start when allowed or all there is
+        let pre = dependency(
+            "name: widget\nversion: latest\nrepo: acme/widget\nallow_prerelease: true\n",
+        );
+        let (picked, shown) = pick(&pre, &tags, &["2"]);
+        assert_eq!(picked.to_string(), "2.0.0");
+        assert!(!shown[0].contains("show prereleases"), "{}", shown[0]);
+        let (picked, _) = pick(&dep, &["v3.0.0-rc.2", "v3.0.0-rc.1"], &[""]);
+        assert_eq!(picked.to_string(), "3.0.0-rc.2");
     }
-}
 
-/// Checks if Git is installed and accessible.
-///
-/// # Returns
-///
-/// * `Ok(())` if Git is installed.
-/// * `Err(String)` with an error message if Git is not installed.
-fn check_git_installed() -> Result<(), String> {
-    let status = Command::new("git")
-        .arg("--version")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map_err(|e| format!("Failed to execute git: {}", e))?;
+    #[test]
+    fn test_error_hints_follow_the_source_and_status() {
+        let download = |from, status: u16| {
+            WdmError::Download {
+                dep: "widget".to_string(),
+                from,
+                source: SourceError::Status(reqwest::StatusCode::from_u16(status).unwrap()),
+            }
+            .to_string()
+        };
+        let token_hint = "hint: check that the token named by token_env is set";
+        assert!(download(Source::Github, 401).contains(token_hint));
+        assert!(download(Source::Gitlab, 403).contains(token_hint));
+        assert!(download(Source::Bitbucket, 404).contains("hint: check the repository name"));
+        assert!(download(Source::Url, 404).contains("hint: check the url in wdm.yml"));
+        assert!(!download(Source::Url, 404).contains("repository"));
+        assert!(download(Source::WordpressOrg, 404).contains("slug"));
+        // WordPress.org takes no token, and other statuses have no common cause
+        assert!(!download(Source::WordpressOrg, 403).contains("hint:"));
+        assert_eq!(
+            download(Source::Github, 410),
+            "Error downloading widget: HTTP 410 Gone"
+        );
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err("Git is not installed or not accessible in PATH.".to_string())
+        let resolution = |from, source| {
+            WdmError::VersionResolution {
+                dep: "widget".to_string(),
+                from,
+                source,
+            }
+            .to_string()
+        };
+        let missing = || ResolveError::VersionNotFound("2.0.0".to_string());
+        assert!(resolution(Source::Github, missing()).contains("hint: the tag may be missing"));
+        assert!(resolution(Source::WordpressOrg, missing()).contains("released on WordPress.org"));
+        let not_found = ResolveError::Source(SourceError::Status(reqwest::StatusCode::NOT_FOUND));
+        assert!(resolution(Source::Gitlab, not_found).contains("hint: check the repository name"));
+        assert!(!resolution(Source::Github, ResolveError::OnlyPrereleases).contains("hint:"));
+
+        // Timeouts read as a sentence of their own
+        assert_eq!(
+            WdmError::Download {
+                dep: "widget".to_string(),
+                from: Source::Url,
+                source: SourceError::TimedOut(Duration::from_secs(5)),
+            }
+            .to_string(),
+            "Download of widget timed out after 5s"
+        );
     }
 }